@@ -16,9 +16,9 @@ fn decode_encode(data: &[u8], cipher: Cipher) {
 
     let mut encode_data = BytesMut::from(data);
 
-    let mut encode_cipher = ctr_init(cipher, &cipher_key, &NULL_IV);
+    let mut encode_cipher = ctr_init(cipher, &cipher_key, &NULL_IV).unwrap();
     let mut encode_hmac = Hmac::from_key(Digest::Sha256, &hmac_key);
-    let mut decode_cipher = ctr_init(cipher, &cipher_key, &NULL_IV);
+    let mut decode_cipher = ctr_init(cipher, &cipher_key, &NULL_IV).unwrap();
     let mut decode_hmac = encode_hmac.clone();
 
     encode_cipher.encrypt(&mut encode_data[..]);
@@ -12,26 +12,175 @@ use std::{
     cmp::min,
     collections::VecDeque,
     io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
 use crate::{
     codec::{stream_handle::StreamEvent, stream_handle::StreamHandle, Hmac, StreamCipher},
     error::SecioError,
+    handshake::stretch_key,
+    noise::state::CipherState,
+    stream_cipher::{ctr_init, AeadCipher, Cipher},
+    Digest,
 };
 
 const DELAY_TIME: Duration = Duration::from_millis(300);
 
+/// Encrypted traffic of a secure stream, including hmac and length prefix
+#[derive(Debug, Default)]
+pub struct SecureTraffic {
+    sent_bytes: AtomicU64,
+    received_bytes: AtomicU64,
+}
+
+impl SecureTraffic {
+    /// Encrypted bytes sent to the underlying socket
+    #[inline]
+    pub fn sent_bytes(&self) -> u64 {
+        self.sent_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Encrypted bytes received from the underlying socket
+    #[inline]
+    pub fn received_bytes(&self) -> u64 {
+        self.received_bytes.load(Ordering::Relaxed)
+    }
+}
+
+/// Length prefix size of `LengthDelimitedCodec`
+const LENGTH_PREFIX_SIZE: u64 = 4;
+
+/// Key material of one direction, the iv, the cipher key and the hmac key
+///
+/// It is kept to derive the keys of the next rekeying, the next key material is stretched
+/// from the current one, so the keys in use don't reveal the previous ones.
+pub(crate) struct KeyMaterial {
+    cipher: Cipher,
+    digest: Digest,
+    keys: Vec<u8>,
+}
+
+impl KeyMaterial {
+    pub(crate) fn new(cipher: Cipher, digest: Digest, keys: &[u8]) -> Self {
+        KeyMaterial {
+            cipher,
+            digest,
+            keys: keys.to_vec(),
+        }
+    }
+
+    fn next(&self) -> Self {
+        let mut keys = vec![0u8; self.keys.len()];
+        stretch_key(Hmac::from_key(self.digest, &self.keys), &mut keys);
+        KeyMaterial {
+            cipher: self.cipher,
+            digest: self.digest,
+            keys,
+        }
+    }
+
+    fn split(&self) -> (&[u8], &[u8], &[u8]) {
+        let (iv, rest) = self.keys.split_at(self.cipher.iv_size());
+        let (cipher_key, mac_key) = rest.split_at(self.cipher.key_size());
+        (iv, cipher_key, mac_key)
+    }
+
+    fn encoder(&self) -> Result<FrameCipher, SecioError> {
+        let (iv, cipher_key, mac_key) = self.split();
+        if self.cipher.is_aead() {
+            // AEAD ciphers authenticate frames by themselves, the mac key is unused
+            AeadCipher::sealing(self.cipher, cipher_key, iv).map(FrameCipher::Aead)
+        } else {
+            Ok(FrameCipher::Ctr(
+                ctr_init(self.cipher, cipher_key, iv)?,
+                Hmac::from_key(self.digest, mac_key),
+            ))
+        }
+    }
+
+    fn decoder(&self) -> Result<FrameCipher, SecioError> {
+        let (iv, cipher_key, mac_key) = self.split();
+        if self.cipher.is_aead() {
+            AeadCipher::opening(self.cipher, cipher_key, iv).map(FrameCipher::Aead)
+        } else {
+            Ok(FrameCipher::Ctr(
+                ctr_init(self.cipher, cipher_key, iv)?,
+                Hmac::from_key(self.digest, mac_key),
+            ))
+        }
+    }
+}
+
+/// Encryption of one direction
+enum FrameCipher {
+    /// Stream cipher, the encrypted data is followed by a hmac
+    Ctr(StreamCipher, Hmac),
+    /// AEAD cipher, the encrypted data is followed by the authentication tag
+    Aead(AeadCipher),
+    /// Noise transport cipher, ChaCha20-Poly1305 with the nonce counted by itself
+    Noise(CipherState),
+}
+
+impl FrameCipher {
+    fn encrypt(&mut self, data: &mut BytesMut) -> Result<(), SecioError> {
+        match self {
+            FrameCipher::Ctr(cipher, hmac) => {
+                cipher.encrypt(&mut data[..]);
+                let signature = hmac.sign(&data[..]);
+                data.extend_from_slice(signature.as_ref());
+                Ok(())
+            }
+            FrameCipher::Aead(cipher) => cipher.encrypt(data),
+            FrameCipher::Noise(cipher) => cipher.encrypt(data),
+        }
+    }
+
+    fn decrypt(&mut self, frame: &mut BytesMut) -> Result<(), SecioError> {
+        match self {
+            FrameCipher::Ctr(cipher, hmac) => {
+                if frame.len() < hmac.num_bytes() {
+                    debug!("frame too short when decoding secio frame");
+                    return Err(SecioError::FrameTooShort);
+                }
+
+                let content_length = frame.len() - hmac.num_bytes();
+                {
+                    let (crypted_data, expected_hash) = frame.split_at(content_length);
+                    debug_assert_eq!(expected_hash.len(), hmac.num_bytes());
+
+                    if !hmac.verify(crypted_data, expected_hash) {
+                        debug!("hmac mismatch when decoding secio frame");
+                        return Err(SecioError::HmacNotMatching);
+                    }
+                }
+
+                frame.truncate(content_length);
+                cipher.decrypt(frame);
+                Ok(())
+            }
+            FrameCipher::Aead(cipher) => cipher.decrypt(frame).map_err(|err| {
+                debug!("aead decrypt failed when decoding secio frame: {:?}", err);
+                err
+            }),
+            FrameCipher::Noise(cipher) => cipher.decrypt(frame).map_err(|err| {
+                debug!("noise decrypt failed when decoding frame: {:?}", err);
+                err
+            }),
+        }
+    }
+}
+
 /// Encrypted stream
 pub struct SecureStream<T> {
     socket: Framed<T, LengthDelimitedCodec>,
     dead: bool,
 
-    decode_cipher: StreamCipher,
-    decode_hmac: Hmac,
-
-    encode_cipher: StreamCipher,
-    encode_hmac: Hmac,
+    decode_cipher: FrameCipher,
+    encode_cipher: FrameCipher,
     /// denotes a sequence of bytes which are expected to be
     /// found at the beginning of the stream and are checked for equality
     nonce: Vec<u8>,
@@ -47,6 +196,21 @@ pub struct SecureStream<T> {
     event_receiver: Receiver<StreamEvent>,
     /// Delay notify with abnormally poor network status
     delay: Option<Delay>,
+    /// Encrypted traffic, shared with handle
+    traffic: Arc<SecureTraffic>,
+
+    /// Key material of sending, none means rekeying is not supported
+    encode_keys: Option<KeyMaterial>,
+    /// Key material of receiving
+    decode_keys: Option<KeyMaterial>,
+    /// Rekey after the number of bytes sent with the same keys
+    rekey_bytes: Option<u64>,
+    /// Rekey after the interval
+    rekey_interval: Option<Duration>,
+    /// Bytes sent since the last rekeying
+    bytes_since_rekey: u64,
+    /// Fires on the next rekeying by time
+    rekey_delay: Option<Delay>,
 }
 
 impl<T> SecureStream<T>
@@ -61,15 +225,100 @@ where
         encode_cipher: StreamCipher,
         encode_hmac: Hmac,
         nonce: Vec<u8>,
+    ) -> Self {
+        Self::with_cipher(
+            socket,
+            FrameCipher::Ctr(decode_cipher, decode_hmac),
+            FrameCipher::Ctr(encode_cipher, encode_hmac),
+            nonce,
+        )
+    }
+
+    /// New a secure stream with AEAD ciphers
+    pub fn new_aead(
+        socket: Framed<T, LengthDelimitedCodec>,
+        decode_cipher: AeadCipher,
+        encode_cipher: AeadCipher,
+        nonce: Vec<u8>,
+    ) -> Self {
+        Self::with_cipher(
+            socket,
+            FrameCipher::Aead(decode_cipher),
+            FrameCipher::Aead(encode_cipher),
+            nonce,
+        )
+    }
+
+    /// New a secure stream with the transport ciphers of a noise handshake
+    pub(crate) fn new_noise(
+        socket: Framed<T, LengthDelimitedCodec>,
+        decode_cipher: CipherState,
+        encode_cipher: CipherState,
+    ) -> Self {
+        Self::with_cipher(
+            socket,
+            FrameCipher::Noise(decode_cipher),
+            FrameCipher::Noise(encode_cipher),
+            Vec::new(),
+        )
+    }
+
+    /// New a secure stream which keeps the key material, the keys of sending are renewed after
+    /// `rekey_bytes` bytes or after `rekey_interval`, none of them means never.
+    ///
+    /// Rekeying is an empty frame encrypted with the current keys, then both sides switch to
+    /// the next keys of this direction. The keys of receiving are renewed when the remote
+    /// sends the rekeying frame.
+    pub(crate) fn with_keys(
+        socket: Framed<T, LengthDelimitedCodec>,
+        decode_keys: KeyMaterial,
+        encode_keys: KeyMaterial,
+        nonce: Vec<u8>,
+        rekey_bytes: Option<u64>,
+        rekey_interval: Option<Duration>,
+    ) -> Result<Self, SecioError> {
+        let mut stream = Self::with_cipher(
+            socket,
+            decode_keys.decoder()?,
+            encode_keys.encoder()?,
+            nonce,
+        );
+        stream.decode_keys = Some(decode_keys);
+        stream.encode_keys = Some(encode_keys);
+        stream.rekey_bytes = rekey_bytes;
+        stream.rekey_interval = rekey_interval;
+        stream.rekey_delay = rekey_interval.map(|interval| Delay::new(Instant::now() + interval));
+        Ok(stream)
+    }
+
+    /// New a secure stream from the key material without keeping it, for the remotes that
+    /// don't support rekeying. An empty frame is sent and received as data.
+    pub(crate) fn without_rekey(
+        socket: Framed<T, LengthDelimitedCodec>,
+        decode_keys: &KeyMaterial,
+        encode_keys: &KeyMaterial,
+        nonce: Vec<u8>,
+    ) -> Result<Self, SecioError> {
+        Ok(Self::with_cipher(
+            socket,
+            decode_keys.decoder()?,
+            encode_keys.encoder()?,
+            nonce,
+        ))
+    }
+
+    fn with_cipher(
+        socket: Framed<T, LengthDelimitedCodec>,
+        decode_cipher: FrameCipher,
+        encode_cipher: FrameCipher,
+        nonce: Vec<u8>,
     ) -> Self {
         let (event_sender, event_receiver) = mpsc::channel(128);
         SecureStream {
             socket,
             dead: false,
             decode_cipher,
-            decode_hmac,
             encode_cipher,
-            encode_hmac,
             read_buf: VecDeque::default(),
             nonce,
             pending: VecDeque::default(),
@@ -77,9 +326,22 @@ where
             event_sender,
             event_receiver,
             delay: None,
+            traffic: Arc::new(SecureTraffic::default()),
+            encode_keys: None,
+            decode_keys: None,
+            rekey_bytes: None,
+            rekey_interval: None,
+            bytes_since_rekey: 0,
+            rekey_delay: None,
         }
     }
 
+    /// Encrypted traffic of this stream
+    #[inline]
+    pub fn traffic(&self) -> Arc<SecureTraffic> {
+        Arc::clone(&self.traffic)
+    }
+
     /// Create a unique handle to this stream.
     /// Repeated calls will return Error.
     #[inline]
@@ -89,7 +351,11 @@ where
         }
         let (frame_sender, frame_receiver) = mpsc::channel(128);
         self.frame_sender = Some(frame_sender);
-        Ok(StreamHandle::new(frame_receiver, self.event_sender.clone()))
+        Ok(StreamHandle::new(
+            frame_receiver,
+            self.event_sender.clone(),
+            Arc::clone(&self.traffic),
+        ))
     }
 
     #[inline]
@@ -111,9 +377,21 @@ where
     fn handle_event(&mut self, event: StreamEvent) -> Result<(), io::Error> {
         match event {
             StreamEvent::Frame(mut frame) => {
+                // An empty frame means rekeying if the remote supports it
+                if frame.is_empty() && self.encode_keys.is_some() {
+                    return Ok(());
+                }
                 debug!("start send data: {:?}", frame);
-                self.encode(&mut frame);
+                self.encode(&mut frame).map_err(Into::<io::Error>::into)?;
+                self.bytes_since_rekey += frame.len() as u64;
                 self.pending.push_back(frame.freeze());
+                if self
+                    .rekey_bytes
+                    .map(|limit| self.bytes_since_rekey >= limit)
+                    .unwrap_or(false)
+                {
+                    self.rekey().map_err(Into::<io::Error>::into)?;
+                }
                 self.send_frame()?;
             }
             StreamEvent::Close => {
@@ -134,7 +412,12 @@ where
             match self.socket.poll() {
                 Ok(Async::Ready(Some(mut t))) => {
                     trace!("receive raw data size: {:?}", t.len());
-                    self.decode(&mut t)?;
+                    self.traffic
+                        .received_bytes
+                        .fetch_add(t.len() as u64 + LENGTH_PREFIX_SIZE, Ordering::Relaxed);
+                    if !self.decode(&mut t)? {
+                        continue;
+                    }
                     debug!("receive data size: {:?}", t.len());
                     self.read_buf.push_back(StreamEvent::Frame(t));
                     self.send_to_handle()?;
@@ -212,27 +495,56 @@ where
         }
     }
 
-    /// Decoding data
-    #[inline]
-    fn decode(&mut self, frame: &mut BytesMut) -> Result<(), SecioError> {
-        if frame.len() < self.decode_hmac.num_bytes() {
-            debug!("frame too short when decoding secio frame");
-            return Err(SecioError::FrameTooShort);
-        }
+    /// Sends the rekeying frame with the current keys, then switches to the next keys of sending
+    fn rekey(&mut self) -> Result<(), SecioError> {
+        let next = match self.encode_keys {
+            Some(ref keys) => keys.next(),
+            None => return Ok(()),
+        };
+
+        let mut frame = BytesMut::new();
+        self.encode(&mut frame)?;
+        self.pending.push_back(frame.freeze());
 
-        let content_length = frame.len() - self.decode_hmac.num_bytes();
-        {
-            let (crypted_data, expected_hash) = frame.split_at(content_length);
-            debug_assert_eq!(expected_hash.len(), self.decode_hmac.num_bytes());
+        self.encode_cipher = next.encoder()?;
+        self.encode_keys = Some(next);
+        self.bytes_since_rekey = 0;
+        if let (Some(delay), Some(interval)) = (self.rekey_delay.as_mut(), self.rekey_interval) {
+            delay.reset(Instant::now() + interval);
+        }
+        trace!("secure stream rekeyed");
+        Ok(())
+    }
 
-            if !self.decode_hmac.verify(crypted_data, expected_hash) {
-                debug!("hmac mismatch when decoding secio frame");
-                return Err(SecioError::HmacNotMatching);
+    /// Rekey if the interval is over
+    fn poll_rekey_delay(&mut self) -> Result<(), SecioError> {
+        loop {
+            match self.rekey_delay.as_mut().map(Future::poll) {
+                Some(Ok(Async::Ready(_))) => self.rekey()?,
+                Some(Err(err)) => {
+                    debug!("rekey timer error: {:?}", err);
+                    self.rekey_delay = None;
+                    break;
+                }
+                _ => break,
             }
         }
+        Ok(())
+    }
 
-        frame.truncate(content_length);
-        self.decode_cipher.decrypt(frame);
+    /// Decoding data, returns false on a rekeying frame which has no data
+    #[inline]
+    fn decode(&mut self, frame: &mut BytesMut) -> Result<bool, SecioError> {
+        self.decode_cipher.decrypt(frame)?;
+
+        if frame.is_empty() {
+            if let Some(next) = self.decode_keys.as_ref().map(KeyMaterial::next) {
+                self.decode_cipher = next.decoder()?;
+                self.decode_keys = Some(next);
+                trace!("remote rekeyed");
+                return Ok(false);
+            }
+        }
 
         if !self.nonce.is_empty() {
             let n = min(frame.len(), self.nonce.len());
@@ -242,15 +554,17 @@ where
             self.nonce.drain(..n);
             frame.split_to(n);
         }
-        Ok(())
+        Ok(true)
     }
 
     /// Encoding data
     #[inline]
-    fn encode(&mut self, data: &mut BytesMut) {
-        self.encode_cipher.encrypt(&mut data[..]);
-        let signature = self.encode_hmac.sign(&data[..]);
-        data.extend_from_slice(signature.as_ref());
+    fn encode(&mut self, data: &mut BytesMut) -> Result<(), SecioError> {
+        self.encode_cipher.encrypt(data)?;
+        self.traffic
+            .sent_bytes
+            .fetch_add(data.len() as u64 + LENGTH_PREFIX_SIZE, Ordering::Relaxed);
+        Ok(())
     }
 }
 
@@ -277,6 +591,12 @@ where
             }
         }
 
+        if let Err(err) = self.poll_rekey_delay() {
+            debug!("rekey error: {:?}", err);
+            self.close();
+            return Err(err.into());
+        }
+
         if !self.pending.is_empty() || !self.read_buf.is_empty() {
             self.flush()?;
         }
@@ -331,8 +651,9 @@ impl<T> Drop for SecureStream<T> {
 
 #[cfg(test)]
 mod tests {
-    use super::{Hmac, SecureStream};
-    use crate::stream_cipher::{ctr_init, Cipher};
+    use super::{Hmac, KeyMaterial, SecureStream};
+    use crate::error::SecioError;
+    use crate::stream_cipher::{ctr_init, AeadCipher, Cipher};
     use crate::Digest;
     use bytes::BytesMut;
     use futures::{sync, Future, Stream};
@@ -341,9 +662,71 @@ mod tests {
     use std::{thread, time};
     use tokio::codec::{length_delimited::LengthDelimitedCodec, Framed};
     use tokio::net::{TcpListener, TcpStream};
+    use tokio::prelude::{AsyncRead, AsyncWrite};
 
     const NULL_IV: [u8; 16] = [0; 16];
 
+    fn new_secure_stream<T: AsyncRead + AsyncWrite>(
+        socket: T,
+        cipher: Cipher,
+        cipher_key: &[u8],
+        hmac_key: &[u8],
+        nonce: Vec<u8>,
+    ) -> SecureStream<T> {
+        let socket = Framed::new(socket, LengthDelimitedCodec::new());
+        if cipher.is_aead() {
+            SecureStream::new_aead(
+                socket,
+                AeadCipher::opening(cipher, cipher_key, &NULL_IV).unwrap(),
+                AeadCipher::sealing(cipher, cipher_key, &NULL_IV).unwrap(),
+                nonce,
+            )
+        } else {
+            SecureStream::new(
+                socket,
+                ctr_init(cipher, cipher_key, &NULL_IV).unwrap(),
+                Hmac::from_key(Digest::Sha256, hmac_key),
+                ctr_init(cipher, cipher_key, &NULL_IV).unwrap(),
+                Hmac::from_key(Digest::Sha256, hmac_key),
+                nonce,
+            )
+        }
+    }
+
+    fn test_aead_decode_encode(cipher: Cipher) {
+        let cipher_key = (0..cipher.key_size())
+            .map(|_| rand::random::<u8>())
+            .collect::<Vec<_>>();
+        let iv: [u8; 16] = rand::random();
+
+        let data = b"hello world";
+
+        let mut encode_cipher = AeadCipher::sealing(cipher, &cipher_key, &iv).unwrap();
+        let mut decode_cipher = AeadCipher::opening(cipher, &cipher_key, &iv).unwrap();
+
+        let mut first = BytesMut::from(data.to_vec());
+        encode_cipher.encrypt(&mut first).unwrap();
+        assert_eq!(first.len(), data.len() + encode_cipher.tag_len());
+        let mut second = BytesMut::from(data.to_vec());
+        encode_cipher.encrypt(&mut second).unwrap();
+        // Each frame has a distinct nonce
+        assert_ne!(first, second);
+
+        let mut tampered = first.clone();
+        tampered[0] ^= 1;
+        assert_eq!(
+            AeadCipher::opening(cipher, &cipher_key, &iv)
+                .unwrap()
+                .decrypt(&mut tampered),
+            Err(SecioError::HmacNotMatching)
+        );
+
+        decode_cipher.decrypt(&mut first).unwrap();
+        assert_eq!(&first[..], &data[..]);
+        decode_cipher.decrypt(&mut second).unwrap();
+        assert_eq!(&second[..], &data[..]);
+    }
+
     fn test_decode_encode(cipher: Cipher) {
         let cipher_key = (0..cipher.key_size())
             .map(|_| rand::random::<u8>())
@@ -354,9 +737,9 @@ mod tests {
 
         let mut encode_data = BytesMut::from(data.to_vec());
 
-        let mut encode_cipher = ctr_init(cipher, &cipher_key, &NULL_IV);
+        let mut encode_cipher = ctr_init(cipher, &cipher_key, &NULL_IV).unwrap();
         let mut encode_hmac = Hmac::from_key(Digest::Sha256, &hmac_key);
-        let mut decode_cipher = ctr_init(cipher, &cipher_key, &NULL_IV);
+        let mut decode_cipher = ctr_init(cipher, &cipher_key, &NULL_IV).unwrap();
         let mut decode_hmac = encode_hmac.clone();
 
         encode_cipher.encrypt(&mut encode_data[..]);
@@ -389,7 +772,7 @@ mod tests {
         let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
         let listener_addr = listener.local_addr().unwrap();
 
-        let (sender, receiver) = sync::oneshot::channel::<bytes::BytesMut>();
+        let (sender, receiver) = sync::oneshot::channel::<(bytes::BytesMut, u64)>();
 
         let nonce2 = nonce.clone();
         let server = listener
@@ -398,19 +781,21 @@ mod tests {
             .map_err(|_| ())
             .map(move |(socket, _)| {
                 let nonce2 = nonce2.clone();
-                let mut secure = SecureStream::new(
-                    Framed::new(socket.unwrap(), LengthDelimitedCodec::new()),
-                    ctr_init(cipher, &cipher_key_clone[..key_size], &NULL_IV[..]),
-                    Hmac::from_key(Digest::Sha256, &hmac_key_clone),
-                    ctr_init(cipher, &cipher_key_clone[..key_size], &NULL_IV[..]),
-                    Hmac::from_key(Digest::Sha256, &hmac_key_clone),
+                let mut secure = new_secure_stream(
+                    socket.unwrap(),
+                    cipher,
+                    &cipher_key_clone[..key_size],
+                    &hmac_key_clone,
                     nonce2,
                 );
                 let handle = secure.create_handle().unwrap();
 
                 let task = tokio::io::read_exact(handle, [0u8; 11])
-                    .and_then(move |(_, data)| {
-                        let _ = sender.send(BytesMut::from(data.to_vec()));
+                    .and_then(move |(handle, data)| {
+                        let _ = sender.send((
+                            BytesMut::from(data.to_vec()),
+                            handle.traffic().received_bytes(),
+                        ));
                         Ok(())
                     })
                     .map_err(|_| ());
@@ -421,12 +806,11 @@ mod tests {
 
         let client = TcpStream::connect(&listener_addr)
             .map(move |stream| {
-                let mut secure = SecureStream::new(
-                    Framed::new(stream, LengthDelimitedCodec::new()),
-                    ctr_init(cipher, &cipher_key_clone[..key_size], &NULL_IV[..]),
-                    Hmac::from_key(Digest::Sha256, &hmac_key_clone),
-                    ctr_init(cipher, &cipher_key_clone[..key_size], &NULL_IV[..]),
-                    Hmac::from_key(Digest::Sha256, &hmac_key_clone),
+                let mut secure = new_secure_stream(
+                    stream,
+                    cipher,
+                    &cipher_key_clone[..key_size],
+                    &hmac_key_clone,
                     Vec::new(),
                 );
                 let mut handle = secure.create_handle().unwrap();
@@ -447,8 +831,121 @@ mod tests {
             tokio::run(client);
         });
 
-        let received = receiver.wait().unwrap();
+        let (received, traffic) = receiver.wait().unwrap();
         assert_eq!(received.to_vec(), data);
+        // length prefix + encrypted data + hmac or tag
+        let overhead = if cipher.is_aead() { 16 } else { 32 };
+        assert!(traffic >= (4 + data.len() + overhead) as u64);
+    }
+
+    fn secure_codec_rekey(
+        cipher: Cipher,
+        rekey_bytes: Option<u64>,
+        rekey_interval: Option<time::Duration>,
+    ) {
+        let keys = (0..cipher.iv_size() + cipher.key_size() + 20)
+            .map(|_| rand::random::<u8>())
+            .collect::<Vec<_>>();
+        let server_keys = keys.clone();
+        let data: &'static [u8] = b"hello world";
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+
+        let (sender, receiver) = sync::oneshot::channel::<Vec<u8>>();
+
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|_| ())
+            .map(move |(socket, _)| {
+                let mut secure = SecureStream::with_keys(
+                    Framed::new(socket.unwrap(), LengthDelimitedCodec::new()),
+                    KeyMaterial::new(cipher, Digest::Sha256, &server_keys),
+                    KeyMaterial::new(cipher, Digest::Sha256, &server_keys),
+                    Vec::new(),
+                    None,
+                    None,
+                )
+                .unwrap();
+                let handle = secure.create_handle().unwrap();
+
+                let task = tokio::io::read_exact(handle, vec![0u8; data.len() * 3])
+                    .and_then(move |(_, data)| {
+                        let _ = sender.send(data);
+                        Ok(())
+                    })
+                    .map_err(|_| ());
+
+                tokio::spawn(secure.for_each(|_| Ok(())).map_err(|_| ()));
+                tokio::spawn(task);
+            });
+
+        let client = TcpStream::connect(&listener_addr)
+            .map(move |stream| {
+                let mut secure = SecureStream::with_keys(
+                    Framed::new(stream, LengthDelimitedCodec::new()),
+                    KeyMaterial::new(cipher, Digest::Sha256, &keys),
+                    KeyMaterial::new(cipher, Digest::Sha256, &keys),
+                    Vec::new(),
+                    rekey_bytes,
+                    rekey_interval,
+                )
+                .unwrap();
+                let mut handle = secure.create_handle().unwrap();
+                tokio::spawn(secure.for_each(|_| Ok(())).map_err(|_| ()));
+
+                for _ in 0..3 {
+                    let _ = handle.write_all(data);
+                    // Wait for the rekeying by time
+                    thread::sleep(time::Duration::from_millis(100));
+                }
+                // wait test finish, don't drop handle
+                thread::sleep(time::Duration::from_secs(10));
+            })
+            .map_err(|_| ());
+
+        thread::spawn(|| {
+            tokio::run(server);
+        });
+
+        thread::spawn(|| {
+            tokio::run(client);
+        });
+
+        let received = receiver.wait().unwrap();
+        assert_eq!(received, [data, data, data].concat());
+    }
+
+    #[test]
+    fn test_key_material_next() {
+        let keys: [u8; 16 + 16 + 20] = [1; 16 + 16 + 20];
+        let current = KeyMaterial::new(Cipher::Aes128, Digest::Sha256, &keys);
+        let next = current.next();
+        assert_eq!(next.keys.len(), current.keys.len());
+        assert_ne!(next.keys, current.keys);
+        // Both sides derive the same keys
+        assert_eq!(next.keys, current.next().keys);
+        assert_ne!(next.next().keys, next.keys);
+    }
+
+    #[test]
+    fn secure_codec_rekey_by_bytes_aes128() {
+        secure_codec_rekey(Cipher::Aes128, Some(1), None);
+    }
+
+    #[test]
+    fn secure_codec_rekey_by_bytes_chacha20_poly1305() {
+        secure_codec_rekey(Cipher::ChaCha20Poly1305, Some(1), None);
+    }
+
+    #[test]
+    fn secure_codec_rekey_by_time_aes256_gcm() {
+        secure_codec_rekey(
+            Cipher::Aes256Gcm,
+            None,
+            Some(time::Duration::from_millis(30)),
+        );
     }
 
     #[test]
@@ -480,4 +977,34 @@ mod tests {
     fn secure_codec_encode_then_decode_twofish() {
         secure_codec_encode_then_decode(Cipher::TwofishCtr);
     }
+
+    #[test]
+    fn test_encode_decode_aes128_gcm() {
+        test_aead_decode_encode(Cipher::Aes128Gcm);
+    }
+
+    #[test]
+    fn test_encode_decode_aes256_gcm() {
+        test_aead_decode_encode(Cipher::Aes256Gcm);
+    }
+
+    #[test]
+    fn test_encode_decode_chacha20_poly1305() {
+        test_aead_decode_encode(Cipher::ChaCha20Poly1305);
+    }
+
+    #[test]
+    fn secure_codec_encode_then_decode_aes128_gcm() {
+        secure_codec_encode_then_decode(Cipher::Aes128Gcm);
+    }
+
+    #[test]
+    fn secure_codec_encode_then_decode_aes256_gcm() {
+        secure_codec_encode_then_decode(Cipher::Aes256Gcm);
+    }
+
+    #[test]
+    fn secure_codec_encode_then_decode_chacha20_poly1305() {
+        secure_codec_encode_then_decode(Cipher::ChaCha20Poly1305);
+    }
 }
@@ -3,7 +3,9 @@ use futures::prelude::*;
 use futures::sync::mpsc::{Receiver, Sender};
 use tokio::prelude::{AsyncRead, AsyncWrite};
 
-use std::io;
+use std::{io, sync::Arc};
+
+use crate::{codec::secure_stream::SecureTraffic, handshake::Negotiated};
 
 /// Stream handle
 #[derive(Debug)]
@@ -13,20 +15,44 @@ pub struct StreamHandle {
     frame_receiver: Receiver<StreamEvent>,
 
     event_sender: Sender<StreamEvent>,
+
+    traffic: Arc<SecureTraffic>,
+
+    negotiated: Option<Negotiated>,
 }
 
 impl StreamHandle {
     pub(crate) fn new(
         frame_receiver: Receiver<StreamEvent>,
         event_sender: Sender<StreamEvent>,
+        traffic: Arc<SecureTraffic>,
     ) -> Self {
         StreamHandle {
             frame_receiver,
             event_sender,
             read_buf: BytesMut::default(),
+            traffic,
+            negotiated: None,
         }
     }
 
+    /// Encrypted traffic of the secure stream
+    #[inline]
+    pub fn traffic(&self) -> Arc<SecureTraffic> {
+        Arc::clone(&self.traffic)
+    }
+
+    /// The algorithms negotiated by the handshake, none if the stream is not created by handshake
+    #[inline]
+    pub fn negotiated(&self) -> Option<Negotiated> {
+        self.negotiated
+    }
+
+    #[inline]
+    pub(crate) fn set_negotiated(&mut self, negotiated: Negotiated) {
+        self.negotiated = Some(negotiated);
+    }
+
     fn handle_event(&mut self, event: StreamEvent) -> Result<(), io::Error> {
         match event {
             StreamEvent::Frame(frame) => self.read_buf.extend_from_slice(&frame),
@@ -1,6 +1,8 @@
 /// I borrowed the error type of `rust-libp2p`, deleted some error types, and added an error type.
 use std::{error, fmt, io};
 
+use crate::peer_id::PeerId;
+
 /// Error at the SECIO layer communication.
 #[derive(Debug)]
 pub enum SecioError {
@@ -22,9 +24,12 @@ pub enum SecioError {
     /// The received frame was of invalid length.
     FrameTooShort,
 
-    /// The hashes of the message didn't match.
+    /// The hashes of the message didn't match, or the AEAD tag failed to verify.
     HmacNotMatching,
 
+    /// All nonces of the AEAD cipher have been used.
+    NonceExhausted,
+
     /// Connect yourself
     ConnectSelf,
 
@@ -39,6 +44,9 @@ pub enum SecioError {
 
     /// We received an invalid proposition from remote.
     InvalidProposition(&'static str),
+
+    /// The remote peer is not in the allowlist.
+    PeerNotAllowed(PeerId),
 }
 
 impl PartialEq for SecioError {
@@ -46,12 +54,14 @@ impl PartialEq for SecioError {
         use self::SecioError::*;
         match (self, other) {
             (InvalidProposition(i), InvalidProposition(j)) => i == j,
+            (PeerNotAllowed(i), PeerNotAllowed(j)) => i == j,
             (EphemeralKeyGenerationFailed, EphemeralKeyGenerationFailed)
             | (SecretGenerationFailed, SecretGenerationFailed)
             | (NoSupportIntersection, NoSupportIntersection)
             | (NonceVerificationFailed, NonceVerificationFailed)
             | (FrameTooShort, FrameTooShort)
             | (HmacNotMatching, HmacNotMatching)
+            | (NonceExhausted, NonceExhausted)
             | (ConnectSelf, ConnectSelf)
             | (HandshakeParsingFailure, HandshakeParsingFailure)
             | (SignatureVerificationFailed, SignatureVerificationFailed)
@@ -87,11 +97,13 @@ impl error::Error for SecioError {
             SecioError::NonceVerificationFailed => "Nonce Verification Failed",
             SecioError::FrameTooShort => "Frame Too Short",
             SecioError::HmacNotMatching => "Hmac Not Matching",
+            SecioError::NonceExhausted => "Nonce Exhausted",
             SecioError::ConnectSelf => "Connect Self",
             SecioError::HandshakeParsingFailure => "Handshake Parsing Failure",
             SecioError::InvalidMessage => "Invalid Message",
             SecioError::SignatureVerificationFailed => "Signature Verification Failed",
             SecioError::InvalidProposition(e) => e,
+            SecioError::PeerNotAllowed(_) => "Peer Not Allowed",
         }
     }
 }
@@ -106,11 +118,74 @@ impl fmt::Display for SecioError {
             SecioError::NonceVerificationFailed => write!(f, "Nonce Verification Failed"),
             SecioError::FrameTooShort => write!(f, "Frame Too Short"),
             SecioError::HmacNotMatching => write!(f, "Hmac Not Matching"),
+            SecioError::NonceExhausted => write!(f, "Nonce Exhausted"),
             SecioError::ConnectSelf => write!(f, "Connect Self"),
             SecioError::HandshakeParsingFailure => write!(f, "Handshake Parsing Failure"),
             SecioError::InvalidMessage => write!(f, "Invalid Message"),
             SecioError::SignatureVerificationFailed => write!(f, "Signature Verification Failed"),
             SecioError::InvalidProposition(e) => write!(f, "Invalid Proposition: {}", e),
+            SecioError::PeerNotAllowed(id) => write!(f, "Peer Not Allowed: {:?}", id),
+        }
+    }
+}
+
+/// Error when saving or loading a key pair
+#[derive(Debug)]
+pub enum KeyFileError {
+    /// I/O error.
+    IoError(io::Error),
+
+    /// The content is not in the expected format.
+    InvalidFormat(&'static str),
+
+    /// The content is well formed, but it isn't a valid or supported private key.
+    InvalidKey,
+
+    /// Wrong password, or the keystore has been tampered with.
+    DecryptionFailed,
+
+    /// The key file can be accessed by users other than the owner, contains the file mode.
+    UnsafePermissions(u32),
+}
+
+impl From<io::Error> for KeyFileError {
+    #[inline]
+    fn from(err: io::Error) -> KeyFileError {
+        KeyFileError::IoError(err)
+    }
+}
+
+impl From<SecioError> for KeyFileError {
+    #[inline]
+    fn from(_err: SecioError) -> KeyFileError {
+        KeyFileError::InvalidKey
+    }
+}
+
+impl error::Error for KeyFileError {
+    fn description(&self) -> &str {
+        match self {
+            KeyFileError::IoError(e) => error::Error::description(e),
+            KeyFileError::InvalidFormat(e) => e,
+            KeyFileError::InvalidKey => "Invalid Key",
+            KeyFileError::DecryptionFailed => "Decryption Failed",
+            KeyFileError::UnsafePermissions(_) => "Unsafe Permissions",
+        }
+    }
+}
+
+impl fmt::Display for KeyFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyFileError::IoError(e) => fmt::Display::fmt(&e, f),
+            KeyFileError::InvalidFormat(e) => write!(f, "Invalid Format: {}", e),
+            KeyFileError::InvalidKey => write!(f, "Invalid Key"),
+            KeyFileError::DecryptionFailed => write!(f, "Decryption Failed"),
+            KeyFileError::UnsafePermissions(mode) => write!(
+                f,
+                "Unsafe Permissions: key file mode {:o} is accessible by others, expect 600",
+                mode
+            ),
         }
     }
 }
@@ -10,8 +10,12 @@ use crate::error::SecioError;
 /// Possible key agreement algorithms.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum KeyAgreement {
+    /// ECDH on the NIST P-256 curve
     EcdhP256,
+    /// ECDH on the NIST P-384 curve
     EcdhP384,
+    /// ECDH on Curve25519
+    X25519,
 }
 
 impl Into<&'static agreement::Algorithm> for KeyAgreement {
@@ -20,6 +24,7 @@ impl Into<&'static agreement::Algorithm> for KeyAgreement {
         match self {
             KeyAgreement::EcdhP256 => &agreement::ECDH_P256,
             KeyAgreement::EcdhP384 => &agreement::ECDH_P384,
+            KeyAgreement::X25519 => &agreement::X25519,
         }
     }
 }
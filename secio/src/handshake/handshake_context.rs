@@ -60,6 +60,8 @@ pub struct Remote {
     pub(crate) chosen_exchange: KeyAgreement,
     pub(crate) chosen_cipher: stream_cipher::Cipher,
     pub(crate) chosen_hash: Digest,
+    // Both sides advertised the support of rekeying:
+    pub(crate) rekey_supported: bool,
 }
 
 // HandshakeContext<Remote> --with_ephemeral-> HandshakeContext<Ephemeral>
@@ -104,6 +106,10 @@ impl HandshakeContext<()> {
             .ciphers_proposal
             .clone()
             .unwrap_or_else(|| support::DEFAULT_CIPHERS_PROPOSITION.into());
+        if self.config.advertise_rekey() {
+            proposition.ciphers.push(',');
+            proposition.ciphers.push_str(support::REKEY_PROPOSITION);
+        }
         trace!("ciphers proposition: {}", proposition.ciphers);
 
         proposition.hashes = self
@@ -155,6 +161,14 @@ impl HandshakeContext<Local> {
             return Err(SecioError::ConnectSelf);
         }
 
+        if let Some(ref allowlist) = self.config.allowlist {
+            let peer_id = public_key.peer_id();
+            if !allowlist.is_allowed(&peer_id) {
+                debug!("remote peer {:?} is not in the allowlist", peer_id);
+                return Err(SecioError::PeerNotAllowed(peer_id));
+            }
+        }
+
         // In order to determine which protocols to use, we compute two hashes and choose
         // based on which hash is larger.
         let hashes_ordering = {
@@ -232,6 +246,10 @@ impl HandshakeContext<Local> {
             }
         };
 
+        let rekey_supported =
+            self.config.advertise_rekey() && support::supports_rekey(&propose.ciphers);
+        debug!("rekey supported: {}", rekey_supported);
+
         Ok(HandshakeContext {
             config: self.config,
             state: Remote {
@@ -243,6 +261,7 @@ impl HandshakeContext<Local> {
                 chosen_exchange,
                 chosen_cipher,
                 chosen_hash,
+                rekey_supported,
             },
         })
     }
@@ -113,6 +113,8 @@ impl Exchange {
 pub enum PublicKey {
     /// Secp256k1
     Secp256k1(Vec<u8>),
+    /// Ed25519
+    Ed25519(Vec<u8>),
 }
 
 impl PublicKey {
@@ -120,6 +122,7 @@ impl PublicKey {
     pub fn inner_ref(&self) -> &Vec<u8> {
         match self {
             PublicKey::Secp256k1(ref key) => key,
+            PublicKey::Ed25519(ref key) => key,
         }
     }
 
@@ -129,7 +132,10 @@ impl PublicKey {
         let pubkey = fbb.create_vector(self.inner_ref());
 
         let mut builder = PublicKeyBuilder::new(&mut fbb);
-        builder.add_key_type(Type::Secp256k1);
+        builder.add_key_type(match self {
+            PublicKey::Secp256k1(_) => Type::Secp256k1,
+            PublicKey::Ed25519(_) => Type::Ed25519,
+        });
         builder.add_pubkey(pubkey);
 
         let data = builder.finish();
@@ -144,6 +150,7 @@ impl PublicKey {
         match pubkey.pubkey() {
             Some(pub_key) => match pubkey.key_type() {
                 Type::Secp256k1 => Some(PublicKey::Secp256k1(pub_key.to_owned())),
+                Type::Ed25519 => Some(PublicKey::Ed25519(pub_key.to_owned())),
             },
             None => None,
         }
@@ -176,6 +183,11 @@ mod tests {
         let raw = SecioKeyPair::secp256k1_generated().to_public_key();
         let byte = raw.encode();
 
+        assert_eq!(raw, PublicKey::decode(&byte).unwrap());
+
+        let raw = SecioKeyPair::ed25519_generated().to_public_key();
+        let byte = raw.encode();
+
         assert_eq!(raw, PublicKey::decode(&byte).unwrap())
     }
 
@@ -2,10 +2,11 @@
 use crate::{
     codec::stream_handle::StreamHandle, error::SecioError, exchange::KeyAgreement,
     handshake::procedure::handshake, stream_cipher::Cipher, support, Digest, EphemeralPublicKey,
-    PublicKey, SecioKeyPair,
+    PeerAllowlist, PublicKey, SecioKeyPair,
 };
 
 use futures::Future;
+use std::time::Duration;
 use tokio::prelude::{AsyncRead, AsyncWrite};
 
 mod handshake_context;
@@ -19,6 +20,19 @@ mod handshake_generated_verifier;
 pub(crate) mod handshake_struct;
 mod procedure;
 
+pub(crate) use self::procedure::stretch_key;
+
+/// The algorithms negotiated by the handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+    /// Key agreement used to generate the shared secret
+    pub agreement: KeyAgreement,
+    /// Stream cipher or AEAD cipher
+    pub cipher: Cipher,
+    /// Digest of hmac, with an AEAD cipher it only stretches the shared secret
+    pub digest: Digest,
+}
+
 /// Config for Secio
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -27,6 +41,12 @@ pub struct Config {
     pub(crate) ciphers_proposal: Option<String>,
     pub(crate) digests_proposal: Option<String>,
     pub(crate) max_frame_length: usize,
+    pub(crate) rekey_bytes: Option<u64>,
+    pub(crate) rekey_interval: Option<Duration>,
+    pub(crate) allowlist: Option<PeerAllowlist>,
+    /// Emulates a peer of an older version which doesn't advertise the support of rekeying
+    #[cfg(test)]
+    pub(crate) advertise_rekey: bool,
 }
 
 impl Config {
@@ -38,15 +58,70 @@ impl Config {
             ciphers_proposal: None,
             digests_proposal: None,
             max_frame_length: 1024 * 1024 * 8,
+            rekey_bytes: None,
+            rekey_interval: None,
+            allowlist: None,
+            #[cfg(test)]
+            advertise_rekey: true,
         }
     }
 
+    /// The key pair of this config
+    pub fn key_pair(&self) -> &SecioKeyPair {
+        &self.key
+    }
+
+    /// The allowlist of this config, none means all peers are allowed
+    pub fn allowlist(&self) -> Option<&PeerAllowlist> {
+        self.allowlist.as_ref()
+    }
+
+    /// Whether the support of rekeying is advertised in the handshake
+    #[cfg(not(test))]
+    pub(crate) fn advertise_rekey(&self) -> bool {
+        true
+    }
+
+    #[cfg(test)]
+    pub(crate) fn advertise_rekey(&self) -> bool {
+        self.advertise_rekey
+    }
+
     /// Max frame length
     pub fn max_frame_length(mut self, size: usize) -> Self {
         self.max_frame_length = size;
         self
     }
 
+    /// Derive fresh keys for sending after the number of bytes sent with the same keys,
+    /// rekeying is disabled by default.
+    ///
+    /// The support of rekeying is advertised in the handshake, it's only enabled when the
+    /// remote supports it as well, with the peers of older versions the keys are never renewed.
+    /// Each side renews the keys of its own sending, so the remote should be configured as well
+    /// to renew both directions.
+    pub fn rekey_after_bytes(mut self, bytes: u64) -> Self {
+        self.rekey_bytes = Some(bytes);
+        self
+    }
+
+    /// Derive fresh keys for sending after the interval, rekeying is disabled by default.
+    ///
+    /// Same as `rekey_after_bytes`, it's only enabled when the remote supports rekeying.
+    /// Both limits can be set, the one reached first renews the keys and restarts both.
+    pub fn rekey_interval(mut self, interval: Duration) -> Self {
+        self.rekey_interval = Some(interval);
+        self
+    }
+
+    /// Only handshake with the peers of the allowlist, the public key of remote is checked
+    /// as soon as its proposition is received. Clones of the allowlist share the same peers,
+    /// reloading takes effect on the later handshakes.
+    pub fn peer_allowlist(mut self, allowlist: PeerAllowlist) -> Self {
+        self.allowlist = Some(allowlist);
+        self
+    }
+
     /// Override the default set of supported key agreement algorithms.
     pub fn key_agreements<'a, I>(mut self, xs: I) -> Self
     where
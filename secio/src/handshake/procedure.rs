@@ -6,7 +6,6 @@ use bytes::Bytes;
 use futures::{future, prelude::*, Future};
 use hmac::digest::{generic_array::ArrayLength, BlockInput, Digest, FixedOutput, Input, Reset};
 use log::{debug, trace};
-use sha2::Sha256;
 use std::{
     cmp::Ordering,
     io::{self, Write},
@@ -15,16 +14,19 @@ use tokio::codec::length_delimited::Builder;
 use tokio::prelude::{AsyncRead, AsyncWrite};
 
 use crate::{
-    codec::{secure_stream::SecureStream, stream_handle::StreamHandle, Hmac},
+    codec::{
+        secure_stream::{KeyMaterial, SecureStream},
+        stream_handle::StreamHandle,
+        Hmac,
+    },
     error::SecioError,
     exchange,
-    handshake::Config,
     handshake::{
         handshake_context::HandshakeContext,
         handshake_struct::{Exchange, PublicKey},
     },
-    stream_cipher::ctr_init,
-    EphemeralPublicKey, KeyPairInner,
+    handshake::{Config, Negotiated},
+    EphemeralPublicKey,
 };
 
 /// Performs a handshake on the given socket.
@@ -115,21 +117,7 @@ where
 
                 exchanges.epubkey = tmp_pub_key;
 
-                let data_to_sign = Sha256::digest(&data_to_sign);
-                let message = match secp256k1::Message::from_slice(data_to_sign.as_ref()) {
-                    Ok(msg) => msg,
-                    Err(_) => {
-                        debug!("message has wrong format");
-                        return Err(SecioError::InvalidMessage);
-                    }
-                };
-
-                let secp256k1_key = secp256k1::Secp256k1::signing_only();
-                let signature = match ephemeral_context.config.key.inner {
-                    KeyPairInner::Secp256k1 { ref private } => {
-                        secp256k1_key.sign(&message, private).serialize_der()
-                    }
-                };
+                let signature = ephemeral_context.config.key.sign_sha256(&data_to_sign)?;
 
                 exchanges.signature = signature;
                 exchanges
@@ -183,34 +171,11 @@ where
                 .extend_from_slice(&ephemeral_context.state.remote.local.proposition_bytes);
             data_to_verify.extend_from_slice(&remote_exchanges.epubkey);
 
-            let data_to_verify = Sha256::digest(&data_to_verify);
-
-            let message = match secp256k1::Message::from_slice(data_to_verify.as_ref()) {
-                Ok(msg) => msg,
-                Err(_) => {
-                    debug!("remote's message has wrong format");
-                    return Err(SecioError::InvalidMessage);
-                }
-            };
-
-            let secp256k1 = secp256k1::Secp256k1::verification_only();
-            let signature = secp256k1::Signature::from_der(&remote_exchanges.signature);
-            let remote_public_key = match ephemeral_context.state.remote.public_key {
-                PublicKey::Secp256k1(ref key) => secp256k1::key::PublicKey::from_slice(key),
-            };
-
-            if let (Ok(signature), Ok(remote_public_key)) = (signature, remote_public_key) {
-                match secp256k1.verify(&message, &signature, &remote_public_key) {
-                    Ok(()) => (),
-                    Err(_) => {
-                        debug!("failed to verify the remote's signature");
-                        return Err(SecioError::SignatureVerificationFailed);
-                    }
-                }
-            } else {
-                debug!("remote's secp256k1 signature has wrong format");
-                return Err(SecioError::SignatureVerificationFailed);
-            }
+            ephemeral_context
+                .state
+                .remote
+                .public_key
+                .verify_sha256(&data_to_verify, &remote_exchanges.signature)?;
 
             trace!("successfully verified the remote's signature");
             Ok((remote_exchanges, socket, ephemeral_context))
@@ -262,34 +227,34 @@ where
                 remote_infos
             );
 
-            let (encode_cipher, encode_hmac) = {
-                let (iv, rest) = local_infos.split_at(iv_size);
-                let (cipher_key, mac_key) = rest.split_at(cipher_key_size);
-                let hmac = Hmac::from_key(pub_ephemeral_context.state.remote.chosen_hash, mac_key);
-                let cipher = ctr_init(chosen_cipher, cipher_key, iv);
-                (cipher, hmac)
-            };
-
-            let (decode_cipher, decode_hmac) = {
-                let (iv, rest) = remote_infos.split_at(iv_size);
-                let (cipher_key, mac_key) = rest.split_at(cipher_key_size);
-                let hmac = Hmac::from_key(pub_ephemeral_context.state.remote.chosen_hash, mac_key);
-                let cipher = ctr_init(chosen_cipher, cipher_key, iv);
-                (cipher, hmac)
+            let nonce = pub_ephemeral_context.state.remote.local.nonce.to_vec();
+            let chosen_hash = pub_ephemeral_context.state.remote.chosen_hash;
+
+            let decode_keys = KeyMaterial::new(chosen_cipher, chosen_hash, remote_infos);
+            let encode_keys = KeyMaterial::new(chosen_cipher, chosen_hash, local_infos);
+
+            // Rekeying frames are only sent and honoured when both sides support them
+            let secure_stream = if pub_ephemeral_context.state.remote.rekey_supported {
+                SecureStream::with_keys(
+                    socket,
+                    decode_keys,
+                    encode_keys,
+                    nonce,
+                    pub_ephemeral_context.config.rekey_bytes,
+                    pub_ephemeral_context.config.rekey_interval,
+                )?
+            } else {
+                SecureStream::without_rekey(socket, &decode_keys, &encode_keys, nonce)?
             };
-
-            let secure_stream = SecureStream::new(
-                socket,
-                decode_cipher,
-                decode_hmac,
-                encode_cipher,
-                encode_hmac,
-                pub_ephemeral_context.state.remote.local.nonce.to_vec(),
-            );
             Ok((secure_stream, pub_ephemeral_context))
         })
         .and_then(|(mut secure_stream, pub_ephemeral_context)| {
             let mut handle = secure_stream.create_handle().unwrap();
+            handle.set_negotiated(Negotiated {
+                agreement: pub_ephemeral_context.state.remote.chosen_exchange,
+                cipher: pub_ephemeral_context.state.remote.chosen_cipher,
+                digest: pub_ephemeral_context.state.remote.chosen_hash,
+            });
 
             tokio::spawn(
                 secure_stream
@@ -313,7 +278,7 @@ where
 
 /// Custom algorithm translated from reference implementations. Needs to be the same algorithm
 /// amongst all implementations.
-fn stretch_key(hmac: Hmac, result: &mut [u8]) {
+pub(crate) fn stretch_key(hmac: Hmac, result: &mut [u8]) {
     match hmac {
         Hmac::Sha256(hmac) => stretch_key_inner(hmac, result),
         Hmac::Sha512(hmac) => stretch_key_inner(hmac, result),
@@ -355,7 +320,10 @@ where
 #[cfg(test)]
 mod tests {
     use super::stretch_key;
-    use crate::{codec::Hmac, handshake::Config, Digest, SecioKeyPair};
+    use crate::{
+        codec::Hmac, error::SecioError, handshake::Config, stream_cipher::Cipher, Digest,
+        KeyAgreement, PeerAllowlist, PeerId, SecioKeyPair,
+    };
 
     use bytes::BytesMut;
     use futures::{prelude::*, sync};
@@ -364,6 +332,15 @@ mod tests {
     use tokio::net::{TcpListener, TcpStream};
 
     fn handshake_with_self_success(config_1: Config, config_2: Config, data: &'static [u8]) {
+        handshake_with_self_success_agreement(config_1, config_2, data, None)
+    }
+
+    fn handshake_with_self_success_agreement(
+        config_1: Config,
+        config_2: Config,
+        data: &'static [u8],
+        agreement: Option<KeyAgreement>,
+    ) {
         let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
         let listener_addr = listener.local_addr().unwrap();
 
@@ -392,6 +369,10 @@ mod tests {
             .map_err(Into::into)
             .and_then(move |stream| config_2.handshake(stream))
             .and_then(move |(mut handle, _, _)| {
+                let negotiated = handle.negotiated().unwrap();
+                if let Some(agreement) = agreement {
+                    assert_eq!(negotiated.agreement, agreement);
+                }
                 let _ = handle.write_all(data);
 
                 let task = tokio::io::read_exact(handle, [0u8; 11])
@@ -425,6 +406,183 @@ mod tests {
         handshake_with_self_success(Config::new(key_1), Config::new(key_2), b"hello world")
     }
 
+    #[test]
+    fn handshake_with_peer_not_allowed() {
+        let key_1 = SecioKeyPair::secp256k1_generated();
+        let key_2 = SecioKeyPair::secp256k1_generated();
+        let dialer_id = key_2.to_peer_id();
+        let config_1 =
+            Config::new(key_1).peer_allowlist(PeerAllowlist::new(vec![PeerId::random()]));
+        let config_2 = Config::new(key_2);
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+
+        let (sender, receiver) = sync::oneshot::channel::<Option<SecioError>>();
+
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e.into())
+            .and_then(move |(connect, _)| config_1.handshake(connect.unwrap()))
+            .then(move |result| {
+                let _ = sender.send(result.err());
+                Ok(())
+            });
+
+        let client = TcpStream::connect(&listener_addr)
+            .map_err(Into::into)
+            .and_then(move |stream| config_2.handshake(stream))
+            .then(|_| Ok(()));
+
+        thread::spawn(|| {
+            tokio::run(server);
+        });
+
+        thread::spawn(|| {
+            tokio::run(client);
+        });
+
+        assert_eq!(
+            receiver.wait().unwrap(),
+            Some(SecioError::PeerNotAllowed(dialer_id))
+        );
+    }
+
+    #[test]
+    fn handshake_with_self_success_rekey() {
+        let key_1 = SecioKeyPair::secp256k1_generated();
+        let key_2 = SecioKeyPair::ed25519_generated();
+        let config_1 = Config::new(key_1)
+            .rekey_after_bytes(1)
+            .rekey_interval(time::Duration::from_millis(10));
+        let config_2 = Config::new(key_2).rekey_after_bytes(1);
+        handshake_with_self_success(config_1, config_2, b"hello world")
+    }
+
+    #[test]
+    fn handshake_with_peer_not_support_rekey() {
+        let key_1 = SecioKeyPair::secp256k1_generated();
+        let key_2 = SecioKeyPair::ed25519_generated();
+        let mut config_1 = Config::new(key_1);
+        config_1.advertise_rekey = false;
+        let config_2 = Config::new(key_2)
+            .rekey_after_bytes(1)
+            .rekey_interval(time::Duration::from_millis(10));
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+
+        let (sender, receiver) = sync::oneshot::channel::<bytes::BytesMut>();
+
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e.into())
+            .and_then(move |(connect, _)| config_1.handshake(connect.unwrap()))
+            .and_then(|(mut handle, _, _)| {
+                // An empty frame is data for the peers that don't support rekeying
+                let _ = handle.write(&[]);
+                let task = tokio::io::read_exact(handle, [0u8; 11])
+                    .and_then(move |(mut handle, data)| {
+                        let _ = handle.write_all(&data);
+                        // wait test finish, don't drop handle
+                        thread::sleep(time::Duration::from_secs(10));
+                        Ok(())
+                    })
+                    .map_err(|_| ());
+                tokio::spawn(task);
+                Ok(())
+            })
+            .map_err(|_| ());
+
+        let client = TcpStream::connect(&listener_addr)
+            .map_err(Into::into)
+            .and_then(move |stream| config_2.handshake(stream))
+            .and_then(move |(mut handle, _, _)| {
+                let _ = handle.write_all(b"hello world");
+
+                let task = tokio::io::read_exact(handle, [0u8; 11])
+                    .and_then(move |(_, data)| {
+                        let _ = sender.send(BytesMut::from(data.to_vec()));
+                        Ok(())
+                    })
+                    .map_err(|_| ());
+                tokio::spawn(task);
+
+                Ok(())
+            })
+            .map_err(|_| ());
+
+        thread::spawn(|| {
+            tokio::run(server);
+        });
+
+        thread::spawn(|| {
+            tokio::run(client);
+        });
+
+        let received = receiver.wait().unwrap();
+        assert_eq!(received.to_vec(), b"hello world");
+    }
+
+    #[test]
+    fn handshake_with_self_success_ctr_cipher() {
+        let key_1 = SecioKeyPair::secp256k1_generated();
+        let key_2 = SecioKeyPair::secp256k1_generated();
+        let config_1 = Config::new(key_1).ciphers(&[Cipher::Aes128, Cipher::TwofishCtr]);
+        let config_2 = Config::new(key_2).ciphers(&[Cipher::TwofishCtr]);
+        handshake_with_self_success(config_1, config_2, b"hello world")
+    }
+
+    #[test]
+    fn handshake_with_self_success_chacha20_poly1305() {
+        let key_1 = SecioKeyPair::secp256k1_generated();
+        let key_2 = SecioKeyPair::secp256k1_generated();
+        let config_1 = Config::new(key_1).ciphers(&[Cipher::ChaCha20Poly1305]);
+        let config_2 = Config::new(key_2);
+        handshake_with_self_success(config_1, config_2, b"hello world")
+    }
+
+    #[test]
+    fn handshake_with_self_success_x25519_by_default() {
+        let key_1 = SecioKeyPair::secp256k1_generated();
+        let key_2 = SecioKeyPair::secp256k1_generated();
+        handshake_with_self_success_agreement(
+            Config::new(key_1),
+            Config::new(key_2),
+            b"hello world",
+            Some(KeyAgreement::X25519),
+        )
+    }
+
+    #[test]
+    fn handshake_with_self_success_p256_only_peer() {
+        let key_1 = SecioKeyPair::secp256k1_generated();
+        let key_2 = SecioKeyPair::secp256k1_generated();
+        let config_2 = Config::new(key_2).key_agreements(&[KeyAgreement::EcdhP256]);
+        handshake_with_self_success_agreement(
+            Config::new(key_1),
+            config_2,
+            b"hello world",
+            Some(KeyAgreement::EcdhP256),
+        )
+    }
+
+    #[test]
+    fn handshake_with_self_success_ed25519_small_data() {
+        let key_1 = SecioKeyPair::ed25519_generated();
+        let key_2 = SecioKeyPair::ed25519_generated();
+        handshake_with_self_success(Config::new(key_1), Config::new(key_2), b"hello world")
+    }
+
+    #[test]
+    fn handshake_with_self_success_mixed_key_small_data() {
+        let key_1 = SecioKeyPair::secp256k1_generated();
+        let key_2 = SecioKeyPair::ed25519_generated();
+        handshake_with_self_success(Config::new(key_1), Config::new(key_2), b"hello world")
+    }
+
     #[test]
     fn stretch() {
         let mut output = [0u8; 32];
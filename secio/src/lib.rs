@@ -2,9 +2,17 @@
 
 #![deny(missing_docs)]
 
+use log::debug;
+use ring::signature::{self, Ed25519KeyPair, KeyPair};
 use secp256k1::key::SecretKey;
+use sha2::{Digest as ShaDigest, Sha256};
+use std::fmt;
 
-pub use crate::{handshake::handshake_struct::PublicKey, peer_id::PeerId};
+pub use crate::{
+    exchange::KeyAgreement,
+    handshake::{handshake_struct::PublicKey, Negotiated},
+    peer_id::{PeerAllowlist, PeerId},
+};
 
 /// Encrypted and decrypted codec implementation, and stream handle
 pub mod codec;
@@ -14,6 +22,10 @@ pub mod error;
 mod exchange;
 /// Implementation of the handshake process
 pub mod handshake;
+/// Key pair persistence
+pub mod key_file;
+/// Noise handshake
+pub mod noise;
 /// Peer id
 pub mod peer_id;
 /// Encrypted stream
@@ -25,11 +37,21 @@ mod support;
 pub type EphemeralPublicKey = Vec<u8>;
 
 /// Key pair of asymmetric encryption algorithm
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct SecioKeyPair {
     inner: KeyPairInner,
 }
 
+impl fmt::Debug for SecioKeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Don't leak the private key to logs, only the public part
+        f.debug_struct("SecioKeyPair")
+            .field("key_type", &self.key_type())
+            .field("peer_id", &self.to_peer_id())
+            .finish()
+    }
+}
+
 impl SecioKeyPair {
     /// Generates a new random sec256k1 key pair.
     pub fn secp256k1_generated() -> SecioKeyPair {
@@ -57,6 +79,61 @@ impl SecioKeyPair {
         })
     }
 
+    /// Generates a new random ed25519 key pair.
+    pub fn ed25519_generated() -> SecioKeyPair {
+        SecioKeyPair {
+            inner: KeyPairInner::Ed25519 {
+                seed: rand::random(),
+            },
+        }
+    }
+
+    /// Builds a `SecioKeyPair` from a raw ed25519 32 bytes private key(seed).
+    pub fn ed25519_raw_key<K>(key: K) -> Result<SecioKeyPair, error::SecioError>
+    where
+        K: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        if key.len() != ED25519_SEED_SIZE {
+            return Err(error::SecioError::SecretGenerationFailed);
+        }
+        let mut seed = [0u8; ED25519_SEED_SIZE];
+        seed.copy_from_slice(key);
+
+        Ok(SecioKeyPair {
+            inner: KeyPairInner::Ed25519 { seed },
+        })
+    }
+
+    /// Builds a `SecioKeyPair` from a raw private key of the given type.
+    pub fn raw_key<K>(ty: KeyType, key: K) -> Result<SecioKeyPair, error::SecioError>
+    where
+        K: AsRef<[u8]>,
+    {
+        match ty {
+            KeyType::Secp256k1 => Self::secp256k1_raw_key(key),
+            KeyType::Ed25519 => Self::ed25519_raw_key(key),
+        }
+    }
+
+    /// Returns the type of this key pair.
+    pub fn key_type(&self) -> KeyType {
+        match self.inner {
+            KeyPairInner::Secp256k1 { .. } => KeyType::Secp256k1,
+            KeyPairInner::Ed25519 { .. } => KeyType::Ed25519,
+        }
+    }
+
+    /// Returns the raw 32 bytes private key, it can be restored by `raw_key`.
+    ///
+    /// Keep it secret.
+    pub fn raw_private_key(&self) -> Vec<u8> {
+        match self.inner {
+            KeyPairInner::Secp256k1 { ref private } => private[..].to_vec(),
+            KeyPairInner::Ed25519 { ref seed } => seed.to_vec(),
+        }
+    }
+
     /// Returns the public key corresponding to this key pair.
     pub fn to_public_key(&self) -> PublicKey {
         match self.inner {
@@ -65,6 +142,9 @@ impl SecioKeyPair {
                 let pubkey = secp256k1::key::PublicKey::from_secret_key(&secp, private);
                 PublicKey::Secp256k1(pubkey.serialize().to_vec())
             }
+            KeyPairInner::Ed25519 { ref seed } => {
+                PublicKey::Ed25519(ed25519_key_pair(seed).public_key().as_ref().to_vec())
+            }
         }
     }
 
@@ -72,11 +152,116 @@ impl SecioKeyPair {
     pub fn to_peer_id(&self) -> PeerId {
         self.to_public_key().peer_id()
     }
+
+    /// Signs a message of the application with the identity key, verified by `PublicKey::verify`
+    ///
+    /// The message is prefixed by a domain tag before hashing, so the signature can't be used as
+    /// a handshake signature, and a handshake signature can't be used as an application one.
+    pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, error::SecioError> {
+        self.sign_sha256(&[APPLICATION_SIGNATURE_DOMAIN, msg].concat())
+    }
+
+    /// Signs the sha256 digest of the data
+    pub(crate) fn sign_sha256(&self, data: &[u8]) -> Result<Vec<u8>, error::SecioError> {
+        let digest = Sha256::digest(data);
+
+        match self.inner {
+            KeyPairInner::Secp256k1 { ref private } => {
+                let message = match secp256k1::Message::from_slice(digest.as_ref()) {
+                    Ok(msg) => msg,
+                    Err(_) => {
+                        debug!("message has wrong format");
+                        return Err(error::SecioError::InvalidMessage);
+                    }
+                };
+                let secp256k1_key = secp256k1::Secp256k1::signing_only();
+                Ok(secp256k1_key.sign(&message, private).serialize_der())
+            }
+            KeyPairInner::Ed25519 { ref seed } => Ok(ed25519_key_pair(seed)
+                .sign(digest.as_ref())
+                .as_ref()
+                .to_vec()),
+        }
+    }
+}
+
+impl PublicKey {
+    /// Verifies a signature of `SecioKeyPair::sign`
+    pub fn verify(&self, msg: &[u8], sig: &[u8]) -> Result<(), error::SecioError> {
+        self.verify_sha256(&[APPLICATION_SIGNATURE_DOMAIN, msg].concat(), sig)
+    }
+
+    /// Verifies the signature of the sha256 digest of the data
+    pub(crate) fn verify_sha256(&self, data: &[u8], sig: &[u8]) -> Result<(), error::SecioError> {
+        let digest = Sha256::digest(data);
+
+        match self {
+            PublicKey::Secp256k1(ref key) => {
+                let message = match secp256k1::Message::from_slice(digest.as_ref()) {
+                    Ok(msg) => msg,
+                    Err(_) => {
+                        debug!("remote's message has wrong format");
+                        return Err(error::SecioError::InvalidMessage);
+                    }
+                };
+
+                let secp256k1 = secp256k1::Secp256k1::verification_only();
+                let signature = secp256k1::Signature::from_der(sig);
+                let public_key = secp256k1::key::PublicKey::from_slice(key);
+
+                if let (Ok(signature), Ok(public_key)) = (signature, public_key) {
+                    match secp256k1.verify(&message, &signature, &public_key) {
+                        Ok(()) => Ok(()),
+                        Err(_) => {
+                            debug!("failed to verify the remote's signature");
+                            Err(error::SecioError::SignatureVerificationFailed)
+                        }
+                    }
+                } else {
+                    debug!("remote's secp256k1 signature has wrong format");
+                    Err(error::SecioError::SignatureVerificationFailed)
+                }
+            }
+            PublicKey::Ed25519(ref key) => signature::verify(
+                &signature::ED25519,
+                untrusted::Input::from(key),
+                untrusted::Input::from(digest.as_ref()),
+                untrusted::Input::from(sig),
+            )
+            .map_err(|_| {
+                debug!("failed to verify the remote's ed25519 signature");
+                error::SecioError::SignatureVerificationFailed
+            }),
+        }
+    }
+}
+
+/// Type of key pair
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    /// Secp256k1
+    Secp256k1,
+    /// Ed25519
+    Ed25519,
 }
 
-#[derive(Clone, Debug)]
+/// The private key size of ed25519
+const ED25519_SEED_SIZE: usize = 32;
+
+/// Prefix of the messages signed by the application
+const APPLICATION_SIGNATURE_DOMAIN: &[u8] = b"tentacle-application-signature:";
+
+#[derive(Clone)]
 enum KeyPairInner {
     Secp256k1 { private: SecretKey },
+    Ed25519 { seed: [u8; ED25519_SEED_SIZE] },
+}
+
+/// The seed is always 32 bytes, so it can't fail
+#[inline]
+pub(crate) fn ed25519_key_pair(seed: &[u8; ED25519_SEED_SIZE]) -> Ed25519KeyPair {
+    Ed25519KeyPair::from_seed_unchecked(untrusted::Input::from(seed))
+        .expect("ed25519 seed is 32 bytes")
 }
 
 /// Possible digest algorithms.
@@ -98,3 +283,61 @@ impl Digest {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::SecioError, SecioKeyPair};
+
+    fn sign_then_verify(key: SecioKeyPair) {
+        let public_key = key.to_public_key();
+        let signature = key.sign(b"peer record").unwrap();
+
+        assert!(public_key.verify(b"peer record", &signature).is_ok());
+        assert_eq!(
+            public_key.verify(b"another record", &signature),
+            Err(SecioError::SignatureVerificationFailed)
+        );
+        assert_eq!(
+            SecioKeyPair::secp256k1_generated()
+                .to_public_key()
+                .verify(b"peer record", &signature),
+            Err(SecioError::SignatureVerificationFailed)
+        );
+
+        // Domain separation from the handshake signatures
+        let handshake_signature = key.sign_sha256(b"peer record").unwrap();
+        assert!(public_key
+            .verify_sha256(b"peer record", &handshake_signature)
+            .is_ok());
+        assert!(public_key
+            .verify(b"peer record", &handshake_signature)
+            .is_err());
+        assert!(public_key
+            .verify_sha256(b"peer record", &signature)
+            .is_err());
+    }
+
+    #[test]
+    fn sign_then_verify_secp256k1() {
+        sign_then_verify(SecioKeyPair::secp256k1_generated())
+    }
+
+    #[test]
+    fn sign_then_verify_ed25519() {
+        sign_then_verify(SecioKeyPair::ed25519_generated())
+    }
+
+    #[test]
+    fn debug_hides_private_key() {
+        for key in vec![
+            SecioKeyPair::secp256k1_generated(),
+            SecioKeyPair::ed25519_generated(),
+        ] {
+            let output = format!("{:?}", key);
+            assert!(output.contains(&format!("{:?}", key.to_peer_id())));
+            let private_key = key.raw_private_key();
+            assert!(!output.contains(&format!("{:?}", private_key)));
+            assert!(!output.contains(&hex::encode(&private_key)));
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! Noise handshake, an alternative to the secio handshake
+//!
+//! The pattern is `Noise_XX_25519_ChaChaPoly_SHA256`. The noise static key is generated
+//! for each handshake, the identity key of `SecioKeyPair` signs it in the handshake payload,
+//! so the result is the same as secio: a stream handle and the public key of remote.
+//!
+//! The handshake messages and transport messages use the same 4 bytes length prefix
+//! framing as secio, so the transport messages are not limited to 65535 bytes.
+
+use bytes::Bytes;
+use futures::{future, prelude::*};
+use log::{debug, trace};
+use tokio::codec::{length_delimited::Builder, Framed, LengthDelimitedCodec};
+use tokio::prelude::{AsyncRead, AsyncWrite};
+
+use std::io::{self, Read, Write};
+
+use crate::{
+    codec::{secure_stream::SecureStream, stream_handle::StreamHandle},
+    error::SecioError,
+    handshake::Negotiated,
+    stream_cipher::Cipher,
+    Digest, EphemeralPublicKey, KeyAgreement, PeerAllowlist, PublicKey, SecioKeyPair,
+};
+
+use self::state::HandshakeState;
+
+pub(crate) mod state;
+
+/// The identity key signs this prefix with the noise static key
+const STATIC_KEY_DOMAIN: &[u8] = b"tentacle-noise-static-key:";
+
+/// Size of the first message of XX, the ephemeral key with an empty payload
+const FIRST_MESSAGE_SIZE: u32 = 32;
+
+/// Config for Noise
+#[derive(Debug, Clone)]
+pub struct Config {
+    key: SecioKeyPair,
+    max_frame_length: usize,
+    allowlist: Option<PeerAllowlist>,
+}
+
+impl Config {
+    /// Create config
+    pub fn new(key_pair: SecioKeyPair) -> Self {
+        Config {
+            key: key_pair,
+            max_frame_length: 1024 * 1024 * 8,
+            allowlist: None,
+        }
+    }
+
+    /// The key pair of this config
+    pub fn key_pair(&self) -> &SecioKeyPair {
+        &self.key
+    }
+
+    /// Max frame length
+    pub fn max_frame_length(mut self, size: usize) -> Self {
+        self.max_frame_length = size;
+        self
+    }
+
+    /// Only handshake with the peers of the allowlist, same as the secio config
+    pub fn peer_allowlist(mut self, allowlist: PeerAllowlist) -> Self {
+        self.allowlist = Some(allowlist);
+        self
+    }
+
+    /// Attempts to perform a noise handshake on the given socket.
+    ///
+    /// The dialer is the initiator. On success, produces a stream handle,
+    /// plus the public key of the remote, plus the local ephemeral public key.
+    pub fn handshake<T>(
+        self,
+        socket: T,
+        initiator: bool,
+    ) -> impl Future<Item = (StreamHandle, PublicKey, EphemeralPublicKey), Error = SecioError>
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        handshake(socket, self, initiator)
+    }
+}
+
+type HandshakeResult<T> = (Framed<T, LengthDelimitedCodec>, HandshakeState, PublicKey);
+
+fn handshake<T>(
+    socket: T,
+    config: Config,
+    initiator: bool,
+) -> impl Future<Item = (StreamHandle, PublicKey, EphemeralPublicKey), Error = SecioError>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let socket = Builder::new()
+        .big_endian()
+        .length_field_length(4)
+        .max_frame_length(config.max_frame_length)
+        .new_framed(socket);
+    let mut state = HandshakeState::new(initiator);
+    let key = config.key.clone();
+
+    let exchange: Box<dyn Future<Item = HandshakeResult<T>, Error = SecioError> + Send> =
+        if initiator {
+            Box::new(
+                future::result(state.write_message_1())
+                    .and_then(|message| send(socket, message))
+                    .and_then(recv)
+                    .and_then(move |(message, socket)| {
+                        trace!("received noise message 2");
+                        let payload = state.read_message_2(&message)?;
+                        let remote_public_key = verify_payload(&payload, state.remote_static())?;
+                        let payload = sign_payload(&key, state.local_static())?;
+                        let message = state.write_message_3(&payload)?;
+                        Ok((socket, message, state, remote_public_key))
+                    })
+                    .and_then(|(socket, message, state, remote_public_key)| {
+                        send(socket, message).map(|socket| (socket, state, remote_public_key))
+                    }),
+            )
+        } else {
+            Box::new(
+                recv(socket)
+                    .and_then(move |(message, socket)| {
+                        trace!("received noise message 1");
+                        state.read_message_1(&message)?;
+                        let payload = sign_payload(&key, state.local_static())?;
+                        let message = state.write_message_2(&payload)?;
+                        Ok((socket, message, state))
+                    })
+                    .and_then(|(socket, message, state)| {
+                        send(socket, message).map(|socket| (socket, state))
+                    })
+                    .and_then(|(socket, state)| {
+                        recv(socket).map(|(message, socket)| (message, socket, state))
+                    })
+                    .and_then(|(message, socket, mut state)| {
+                        trace!("received noise message 3");
+                        let payload = state.read_message_3(&message)?;
+                        let remote_public_key = verify_payload(&payload, state.remote_static())?;
+                        Ok((socket, state, remote_public_key))
+                    }),
+            )
+        };
+
+    exchange.and_then(move |(socket, state, remote_public_key)| {
+        if remote_public_key == config.key.to_public_key() {
+            return Err(SecioError::ConnectSelf);
+        }
+
+        if let Some(ref allowlist) = config.allowlist {
+            let peer_id = remote_public_key.peer_id();
+            if !allowlist.is_allowed(&peer_id) {
+                debug!("remote peer {:?} is not in the allowlist", peer_id);
+                return Err(SecioError::PeerNotAllowed(peer_id));
+            }
+        }
+
+        let local_ephemeral = state.local_ephemeral();
+        let (encode_cipher, decode_cipher) = state.into_transport();
+        let mut secure_stream = SecureStream::new_noise(socket, decode_cipher, encode_cipher);
+        let mut handle = secure_stream.create_handle().unwrap();
+        handle.set_negotiated(Negotiated {
+            agreement: KeyAgreement::X25519,
+            cipher: Cipher::ChaCha20Poly1305,
+            digest: Digest::Sha256,
+        });
+
+        tokio::spawn(
+            secure_stream
+                .for_each(|_| Ok(()))
+                .map_err(|err| debug!("Abnormal disconnection: {:?}", err)),
+        );
+
+        Ok((handle, remote_public_key, local_ephemeral))
+    })
+}
+
+fn send<T>(
+    socket: Framed<T, LengthDelimitedCodec>,
+    message: Vec<u8>,
+) -> impl Future<Item = Framed<T, LengthDelimitedCodec>, Error = SecioError>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    socket.send(Bytes::from(message)).from_err()
+}
+
+fn recv<T>(
+    socket: Framed<T, LengthDelimitedCodec>,
+) -> impl Future<Item = (bytes::BytesMut, Framed<T, LengthDelimitedCodec>), Error = SecioError>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    socket
+        .into_future()
+        .map_err(|(e, _)| e.into())
+        .and_then(|(message, socket)| match message {
+            Some(message) => Ok((message, socket)),
+            None => {
+                debug!("unexpected eof while waiting for noise message");
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected eof").into())
+            }
+        })
+}
+
+/// The payload is the length of the encoded identity public key, the key,
+/// and the signature of the noise static key
+fn sign_payload(key: &SecioKeyPair, static_key: &[u8]) -> Result<Vec<u8>, SecioError> {
+    let public_key = key.to_public_key().encode();
+    let signature = key.sign_sha256(&[STATIC_KEY_DOMAIN, static_key].concat())?;
+
+    let mut payload = Vec::with_capacity(2 + public_key.len() + signature.len());
+    payload.extend_from_slice(&(public_key.len() as u16).to_be_bytes());
+    payload.extend_from_slice(&public_key);
+    payload.extend_from_slice(&signature);
+    Ok(payload)
+}
+
+fn verify_payload(payload: &[u8], static_key: Option<&[u8]>) -> Result<PublicKey, SecioError> {
+    let static_key = static_key.ok_or(SecioError::InvalidMessage)?;
+    if payload.len() < 2 {
+        return Err(SecioError::HandshakeParsingFailure);
+    }
+    let len = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+    if payload.len() < 2 + len {
+        return Err(SecioError::HandshakeParsingFailure);
+    }
+    let (public_key, signature) = payload[2..].split_at(len);
+    let public_key = PublicKey::decode(public_key).ok_or_else(|| {
+        debug!("failed to parse remote's public key");
+        SecioError::HandshakeParsingFailure
+    })?;
+
+    public_key.verify_sha256(&[STATIC_KEY_DOMAIN, static_key].concat(), signature)?;
+    Ok(public_key)
+}
+
+/// Detects whether the remote starts a noise handshake or a secio handshake
+///
+/// Used by the responder which accepts both. The 4 bytes length prefix of the first message
+/// is read, the first message of noise XX is 32 bytes, and the secio proposition is much larger.
+/// The prefix is given back by the returned stream, so either handshake can start on it.
+pub fn detect<T>(socket: T) -> impl Future<Item = (bool, Detected<T>), Error = SecioError>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    tokio::io::read_exact(socket, [0u8; 4])
+        .from_err()
+        .map(|(socket, prefix)| {
+            let is_noise = u32::from_be_bytes(prefix) == FIRST_MESSAGE_SIZE;
+            (
+                is_noise,
+                Detected {
+                    prefix,
+                    read: 0,
+                    inner: socket,
+                },
+            )
+        })
+}
+
+/// The stream returned by `detect`, reads the length prefix again before the underlying stream
+#[derive(Debug)]
+pub struct Detected<T> {
+    prefix: [u8; 4],
+    read: usize,
+    inner: T,
+}
+
+impl<T: Read> Read for Detected<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read < self.prefix.len() {
+            let rest = &self.prefix[self.read..];
+            let n = ::std::cmp::min(rest.len(), buf.len());
+            buf[..n].copy_from_slice(&rest[..n]);
+            self.read += n;
+            return Ok(n);
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for Detected<T> {}
+
+impl<T: Write> Write for Detected<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for Detected<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use crate::{stream_cipher::Cipher, KeyAgreement, SecioKeyPair};
+
+    use futures::{prelude::*, sync};
+    use std::io::Write;
+    use std::{thread, time};
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn handshake_with_self_success(config_1: Config, config_2: Config, data: &'static [u8]) {
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+        let server_public_key = config_1.key_pair().to_public_key();
+
+        let (sender, receiver) = sync::oneshot::channel::<Vec<u8>>();
+
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e.into())
+            .and_then(move |(connect, _)| config_1.handshake(connect.unwrap(), false))
+            .and_then(move |(handle, _, _)| {
+                let task = tokio::io::read_exact(handle, vec![0u8; data.len()])
+                    .and_then(move |(mut handle, data)| {
+                        let _ = handle.write_all(&data);
+                        // wait test finish, don't drop handle
+                        thread::sleep(time::Duration::from_secs(10));
+                        Ok(())
+                    })
+                    .map_err(|_| ());
+                tokio::spawn(task);
+                Ok(())
+            })
+            .map_err(|_| ());
+
+        let client = TcpStream::connect(&listener_addr)
+            .map_err(Into::into)
+            .and_then(move |stream| config_2.handshake(stream, true))
+            .and_then(move |(mut handle, remote_public_key, _)| {
+                assert_eq!(remote_public_key, server_public_key);
+                let negotiated = handle.negotiated().unwrap();
+                assert_eq!(negotiated.agreement, KeyAgreement::X25519);
+                assert_eq!(negotiated.cipher, Cipher::ChaCha20Poly1305);
+                let _ = handle.write_all(data);
+
+                let task = tokio::io::read_exact(handle, vec![0u8; data.len()])
+                    .and_then(move |(_, data)| {
+                        let _ = sender.send(data);
+                        Ok(())
+                    })
+                    .map_err(|_| ());
+                tokio::spawn(task);
+
+                Ok(())
+            })
+            .map_err(|_| ());
+
+        thread::spawn(|| {
+            tokio::run(server);
+        });
+
+        thread::spawn(|| {
+            tokio::run(client);
+        });
+
+        let received = receiver.wait().unwrap();
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn handshake_with_self_success_secp256k1_small_data() {
+        let key_1 = SecioKeyPair::secp256k1_generated();
+        let key_2 = SecioKeyPair::secp256k1_generated();
+        handshake_with_self_success(Config::new(key_1), Config::new(key_2), b"hello world")
+    }
+
+    #[test]
+    fn handshake_with_self_success_mixed_key_big_data() {
+        let key_1 = SecioKeyPair::secp256k1_generated();
+        let key_2 = SecioKeyPair::ed25519_generated();
+        // Bigger than the 65535 bytes limit of the noise specification
+        const DATA: [u8; 100 * 1024] = [1u8; 100 * 1024];
+        handshake_with_self_success(Config::new(key_1), Config::new(key_2), &DATA)
+    }
+}
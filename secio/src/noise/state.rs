@@ -0,0 +1,427 @@
+use bytes::BytesMut;
+use hmac::{Hmac, Mac};
+use ring::aead;
+use sha2::{Digest, Sha256};
+use x25519_dalek::{x25519, X25519_BASEPOINT_BYTES};
+
+use crate::error::SecioError;
+
+/// Exactly `HASH_LEN` bytes, so it is used as the initial hash without padding
+const PROTOCOL_NAME: &[u8; HASH_LEN] = b"Noise_XX_25519_ChaChaPoly_SHA256";
+const HASH_LEN: usize = 32;
+const DH_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// X25519 key pair, the secret is clamped by `x25519`
+struct DhKeyPair {
+    secret: [u8; DH_LEN],
+    public: [u8; DH_LEN],
+}
+
+impl DhKeyPair {
+    fn generate() -> Self {
+        let secret: [u8; DH_LEN] = rand::random();
+        DhKeyPair {
+            secret,
+            public: x25519(secret, X25519_BASEPOINT_BYTES),
+        }
+    }
+
+    fn dh(&self, public: &[u8; DH_LEN]) -> Result<[u8; DH_LEN], SecioError> {
+        let shared = x25519(self.secret, *public);
+        // Low order points give an all-zero output, which contributes nothing to the key
+        if shared == [0; DH_LEN] {
+            return Err(SecioError::SecretGenerationFailed);
+        }
+        Ok(shared)
+    }
+}
+
+struct CipherKeys {
+    sealing: aead::SealingKey,
+    opening: aead::OpeningKey,
+}
+
+/// Noise `CipherState` of ChaChaPoly, a key and a nonce counter
+pub(crate) struct CipherState {
+    keys: Option<CipherKeys>,
+    nonce: u64,
+}
+
+impl CipherState {
+    fn empty() -> Self {
+        CipherState {
+            keys: None,
+            nonce: 0,
+        }
+    }
+
+    fn with_key(key: &[u8]) -> Self {
+        let keys = CipherKeys {
+            sealing: aead::SealingKey::new(&aead::CHACHA20_POLY1305, key)
+                .expect("key size is right"),
+            opening: aead::OpeningKey::new(&aead::CHACHA20_POLY1305, key)
+                .expect("key size is right"),
+        };
+        CipherState {
+            keys: Some(keys),
+            nonce: 0,
+        }
+    }
+
+    /// 32 bits of zeros followed by the little endian counter, the max counter is reserved
+    fn next_nonce(&mut self) -> Result<aead::Nonce, SecioError> {
+        if self.nonce == u64::max_value() {
+            return Err(SecioError::NonceExhausted);
+        }
+        let mut nonce = [0; NONCE_LEN];
+        nonce[NONCE_LEN - 8..].copy_from_slice(&self.nonce.to_le_bytes());
+        self.nonce += 1;
+        Ok(aead::Nonce::assume_unique_for_key(nonce))
+    }
+
+    /// Encrypts `in_out` except the last `TAG_LEN` bytes, which are replaced by the tag
+    fn seal(&mut self, ad: &[u8], in_out: &mut [u8]) -> Result<(), SecioError> {
+        let nonce = self.next_nonce()?;
+        let keys = self.keys.as_ref().ok_or(SecioError::InvalidMessage)?;
+        aead::seal_in_place(&keys.sealing, nonce, aead::Aad::from(ad), in_out, TAG_LEN)
+            .map(|_| ())
+            .map_err(|_| SecioError::InvalidMessage)
+    }
+
+    /// Decrypts `in_out` in place, returns the length of the plaintext
+    fn open(&mut self, ad: &[u8], in_out: &mut [u8]) -> Result<usize, SecioError> {
+        if in_out.len() < TAG_LEN {
+            return Err(SecioError::FrameTooShort);
+        }
+        let nonce = self.next_nonce()?;
+        let keys = self.keys.as_ref().ok_or(SecioError::InvalidMessage)?;
+        aead::open_in_place(&keys.opening, nonce, aead::Aad::from(ad), 0, in_out)
+            .map(|plaintext| plaintext.len())
+            .map_err(|_| SecioError::HmacNotMatching)
+    }
+
+    /// Encrypts a transport message in place and appends the tag
+    pub(crate) fn encrypt(&mut self, data: &mut BytesMut) -> Result<(), SecioError> {
+        data.extend_from_slice(&[0; TAG_LEN]);
+        self.seal(&[], &mut data[..])
+    }
+
+    /// Verifies and decrypts a transport message in place
+    pub(crate) fn decrypt(&mut self, frame: &mut BytesMut) -> Result<(), SecioError> {
+        let len = self.open(&[], &mut frame[..])?;
+        frame.truncate(len);
+        Ok(())
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[&[u8]]) -> [u8; HASH_LEN] {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("Hmac::new_varkey accepts any key length");
+    for input in data {
+        mac.input(input);
+    }
+    let mut output = [0; HASH_LEN];
+    output.copy_from_slice(&mac.result().code());
+    output
+}
+
+/// Noise `HKDF` with two outputs
+fn hkdf(chaining_key: &[u8], input_key_material: &[u8]) -> ([u8; HASH_LEN], [u8; HASH_LEN]) {
+    let temp_key = hmac_sha256(chaining_key, &[input_key_material]);
+    let output_1 = hmac_sha256(&temp_key, &[&[1]]);
+    let output_2 = hmac_sha256(&temp_key, &[&output_1, &[2]]);
+    (output_1, output_2)
+}
+
+/// Noise `SymmetricState`
+struct SymmetricState {
+    chaining_key: [u8; HASH_LEN],
+    hash: [u8; HASH_LEN],
+    cipher: CipherState,
+}
+
+impl SymmetricState {
+    fn new() -> Self {
+        let mut state = SymmetricState {
+            chaining_key: *PROTOCOL_NAME,
+            hash: *PROTOCOL_NAME,
+            cipher: CipherState::empty(),
+        };
+        // Empty prologue
+        state.mix_hash(&[]);
+        state
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        let mut hasher = Sha256::default();
+        hasher.input(&self.hash[..]);
+        hasher.input(data);
+        self.hash.copy_from_slice(&hasher.result());
+    }
+
+    fn mix_key(&mut self, input_key_material: &[u8]) {
+        let (chaining_key, key) = hkdf(&self.chaining_key, input_key_material);
+        self.chaining_key = chaining_key;
+        self.cipher = CipherState::with_key(&key);
+    }
+
+    /// Appends the encrypted plaintext to message
+    fn encrypt_and_hash(
+        &mut self,
+        plaintext: &[u8],
+        message: &mut Vec<u8>,
+    ) -> Result<(), SecioError> {
+        let start = message.len();
+        message.extend_from_slice(plaintext);
+        if self.cipher.keys.is_some() {
+            message.extend_from_slice(&[0; TAG_LEN]);
+            let hash = self.hash;
+            self.cipher.seal(&hash, &mut message[start..])?;
+        }
+        self.mix_hash(&message[start..]);
+        Ok(())
+    }
+
+    fn decrypt_and_hash(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, SecioError> {
+        let mut plaintext = ciphertext.to_vec();
+        if self.cipher.keys.is_some() {
+            let hash = self.hash;
+            let len = self.cipher.open(&hash, &mut plaintext)?;
+            plaintext.truncate(len);
+        }
+        self.mix_hash(ciphertext);
+        Ok(plaintext)
+    }
+
+    fn split(&self) -> (CipherState, CipherState) {
+        let (key_1, key_2) = hkdf(&self.chaining_key, &[]);
+        (CipherState::with_key(&key_1), CipherState::with_key(&key_2))
+    }
+}
+
+/// Noise `HandshakeState` of the XX pattern
+///
+/// ```text
+/// -> e
+/// <- e, ee, s, es
+/// -> s, se
+/// ```
+pub(crate) struct HandshakeState {
+    symmetric: SymmetricState,
+    initiator: bool,
+    local_static: DhKeyPair,
+    local_ephemeral: Option<DhKeyPair>,
+    remote_static: Option<[u8; DH_LEN]>,
+    remote_ephemeral: Option<[u8; DH_LEN]>,
+}
+
+impl HandshakeState {
+    /// The static key is generated for each handshake
+    pub(crate) fn new(initiator: bool) -> Self {
+        HandshakeState {
+            symmetric: SymmetricState::new(),
+            initiator,
+            local_static: DhKeyPair::generate(),
+            local_ephemeral: None,
+            remote_static: None,
+            remote_ephemeral: None,
+        }
+    }
+
+    /// The static public key of this side
+    pub(crate) fn local_static(&self) -> &[u8] {
+        &self.local_static.public
+    }
+
+    /// The static public key of remote, available after it is received
+    pub(crate) fn remote_static(&self) -> Option<&[u8]> {
+        self.remote_static.as_ref().map(|key| &key[..])
+    }
+
+    /// The ephemeral public key of this side
+    pub(crate) fn local_ephemeral(&self) -> Vec<u8> {
+        self.local_ephemeral
+            .as_ref()
+            .map(|key| key.public.to_vec())
+            .unwrap_or_default()
+    }
+
+    /// -> e
+    pub(crate) fn write_message_1(&mut self) -> Result<Vec<u8>, SecioError> {
+        let ephemeral = DhKeyPair::generate();
+        let mut message = ephemeral.public.to_vec();
+        self.symmetric.mix_hash(&ephemeral.public);
+        self.local_ephemeral = Some(ephemeral);
+        self.symmetric.encrypt_and_hash(&[], &mut message)?;
+        Ok(message)
+    }
+
+    /// -> e
+    pub(crate) fn read_message_1(&mut self, message: &[u8]) -> Result<(), SecioError> {
+        let (remote_ephemeral, rest) = read_public_key(message)?;
+        self.symmetric.mix_hash(&remote_ephemeral);
+        self.remote_ephemeral = Some(remote_ephemeral);
+        self.symmetric.decrypt_and_hash(rest)?;
+        Ok(())
+    }
+
+    /// <- e, ee, s, es
+    pub(crate) fn write_message_2(&mut self, payload: &[u8]) -> Result<Vec<u8>, SecioError> {
+        let remote_ephemeral = self.remote_ephemeral.ok_or(SecioError::InvalidMessage)?;
+        let ephemeral = DhKeyPair::generate();
+        let mut message = ephemeral.public.to_vec();
+        self.symmetric.mix_hash(&ephemeral.public);
+        self.symmetric.mix_key(&ephemeral.dh(&remote_ephemeral)?);
+        self.symmetric
+            .encrypt_and_hash(&self.local_static.public, &mut message)?;
+        self.symmetric
+            .mix_key(&self.local_static.dh(&remote_ephemeral)?);
+        self.symmetric.encrypt_and_hash(payload, &mut message)?;
+        self.local_ephemeral = Some(ephemeral);
+        Ok(message)
+    }
+
+    /// <- e, ee, s, es
+    pub(crate) fn read_message_2(&mut self, message: &[u8]) -> Result<Vec<u8>, SecioError> {
+        let ephemeral = self
+            .local_ephemeral
+            .as_ref()
+            .ok_or(SecioError::InvalidMessage)?;
+        let (remote_ephemeral, rest) = read_public_key(message)?;
+        self.symmetric.mix_hash(&remote_ephemeral);
+        self.symmetric.mix_key(&ephemeral.dh(&remote_ephemeral)?);
+        let (remote_static, rest) = self.read_static_key(rest)?;
+        let ephemeral = self
+            .local_ephemeral
+            .as_ref()
+            .ok_or(SecioError::InvalidMessage)?;
+        self.symmetric.mix_key(&ephemeral.dh(&remote_static)?);
+        let payload = self.symmetric.decrypt_and_hash(rest)?;
+        self.remote_ephemeral = Some(remote_ephemeral);
+        self.remote_static = Some(remote_static);
+        Ok(payload)
+    }
+
+    /// -> s, se
+    pub(crate) fn write_message_3(&mut self, payload: &[u8]) -> Result<Vec<u8>, SecioError> {
+        let remote_ephemeral = self.remote_ephemeral.ok_or(SecioError::InvalidMessage)?;
+        let mut message = Vec::new();
+        self.symmetric
+            .encrypt_and_hash(&self.local_static.public, &mut message)?;
+        self.symmetric
+            .mix_key(&self.local_static.dh(&remote_ephemeral)?);
+        self.symmetric.encrypt_and_hash(payload, &mut message)?;
+        Ok(message)
+    }
+
+    /// -> s, se
+    pub(crate) fn read_message_3(&mut self, message: &[u8]) -> Result<Vec<u8>, SecioError> {
+        let (remote_static, rest) = self.read_static_key(message)?;
+        let ephemeral = self
+            .local_ephemeral
+            .as_ref()
+            .ok_or(SecioError::InvalidMessage)?;
+        self.symmetric.mix_key(&ephemeral.dh(&remote_static)?);
+        let payload = self.symmetric.decrypt_and_hash(rest)?;
+        self.remote_static = Some(remote_static);
+        Ok(payload)
+    }
+
+    /// Returns the cipher states to encrypt and decrypt the transport messages
+    pub(crate) fn into_transport(self) -> (CipherState, CipherState) {
+        let (initiator_to_responder, responder_to_initiator) = self.symmetric.split();
+        if self.initiator {
+            (initiator_to_responder, responder_to_initiator)
+        } else {
+            (responder_to_initiator, initiator_to_responder)
+        }
+    }
+
+    /// The encrypted static key is followed by its tag
+    fn read_static_key<'a>(
+        &mut self,
+        message: &'a [u8],
+    ) -> Result<([u8; DH_LEN], &'a [u8]), SecioError> {
+        if message.len() < DH_LEN + TAG_LEN {
+            return Err(SecioError::FrameTooShort);
+        }
+        let (ciphertext, rest) = message.split_at(DH_LEN + TAG_LEN);
+        let plaintext = self.symmetric.decrypt_and_hash(ciphertext)?;
+        let (key, _) = read_public_key(&plaintext)?;
+        Ok((key, rest))
+    }
+}
+
+fn read_public_key(message: &[u8]) -> Result<([u8; DH_LEN], &[u8]), SecioError> {
+    if message.len() < DH_LEN {
+        return Err(SecioError::FrameTooShort);
+    }
+    let (key, rest) = message.split_at(DH_LEN);
+    let mut public = [0; DH_LEN];
+    public.copy_from_slice(key);
+    Ok((public, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HandshakeState;
+    use crate::error::SecioError;
+    use bytes::BytesMut;
+
+    fn handshake() -> (HandshakeState, HandshakeState) {
+        let mut initiator = HandshakeState::new(true);
+        let mut responder = HandshakeState::new(false);
+
+        let message = initiator.write_message_1().unwrap();
+        assert_eq!(message.len(), 32);
+        responder.read_message_1(&message).unwrap();
+
+        let message = responder.write_message_2(b"responder").unwrap();
+        assert_eq!(initiator.read_message_2(&message).unwrap(), b"responder");
+
+        let message = initiator.write_message_3(b"initiator").unwrap();
+        assert_eq!(responder.read_message_3(&message).unwrap(), b"initiator");
+
+        assert_eq!(initiator.remote_static(), Some(responder.local_static()));
+        assert_eq!(responder.remote_static(), Some(initiator.local_static()));
+        (initiator, responder)
+    }
+
+    #[test]
+    fn test_xx_handshake_and_transport() {
+        let (initiator, responder) = handshake();
+        let (mut initiator_encode, mut initiator_decode) = initiator.into_transport();
+        let (mut responder_encode, mut responder_decode) = responder.into_transport();
+
+        for data in &[&b"hello world"[..], &b""[..], &b"hello again"[..]] {
+            let mut frame = BytesMut::from(data.to_vec());
+            initiator_encode.encrypt(&mut frame).unwrap();
+            assert_ne!(&frame[..], &data[..]);
+            responder_decode.decrypt(&mut frame).unwrap();
+            assert_eq!(&frame[..], &data[..]);
+
+            let mut frame = BytesMut::from(data.to_vec());
+            responder_encode.encrypt(&mut frame).unwrap();
+            initiator_decode.decrypt(&mut frame).unwrap();
+            assert_eq!(&frame[..], &data[..]);
+        }
+    }
+
+    #[test]
+    fn test_xx_handshake_tampered() {
+        let mut initiator = HandshakeState::new(true);
+        let mut responder = HandshakeState::new(false);
+
+        let message = initiator.write_message_1().unwrap();
+        responder.read_message_1(&message).unwrap();
+
+        let mut message = responder.write_message_2(b"responder").unwrap();
+        let last = message.len() - 1;
+        message[last] ^= 1;
+        assert_eq!(
+            initiator.read_message_2(&message),
+            Err(SecioError::HmacNotMatching)
+        );
+    }
+}
@@ -1,5 +1,9 @@
 /// Most of the code for this module comes from `rust-libp2p`.
-use std::fmt;
+use std::{
+    collections::HashSet,
+    fmt,
+    sync::{Arc, RwLock},
+};
 
 use rand::{thread_rng, Rng};
 use sha2::digest::Digest;
@@ -129,9 +133,53 @@ impl ::std::str::FromStr for PeerId {
     }
 }
 
+/// Peers allowed to handshake, clones share the same list, so it can be reloaded at runtime
+///
+/// The default allows all peers.
+#[derive(Debug, Clone, Default)]
+pub struct PeerAllowlist {
+    peers: Arc<RwLock<Option<HashSet<PeerId>>>>,
+}
+
+impl PeerAllowlist {
+    /// Only allow these peers
+    pub fn new<I: IntoIterator<Item = PeerId>>(peers: I) -> Self {
+        let allowlist = PeerAllowlist::default();
+        allowlist.reload(Some(peers.into_iter().collect()));
+        allowlist
+    }
+
+    /// Replace the allowed peers, none allows all peers
+    pub fn reload(&self, peers: Option<HashSet<PeerId>>) {
+        if let Ok(mut inner) = self.peers.write() {
+            *inner = peers;
+        }
+    }
+
+    /// Snapshot of the allowed peers, none means all peers are allowed
+    pub fn peers(&self) -> Option<HashSet<PeerId>> {
+        self.peers.read().ok().and_then(|peers| peers.clone())
+    }
+
+    /// Whether the peer is allowed
+    pub fn is_allowed(&self, peer_id: &PeerId) -> bool {
+        match self.peers.read() {
+            Ok(peers) => peers
+                .as_ref()
+                .map(|peers| peers.contains(peer_id))
+                .unwrap_or(true),
+            // Fail closed
+            Err(_) => false,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{peer_id::PeerId, SecioKeyPair};
+    use crate::{
+        peer_id::{PeerAllowlist, PeerId},
+        SecioKeyPair,
+    };
 
     #[test]
     fn peer_id_is_public_key() {
@@ -154,6 +202,23 @@ mod tests {
         assert_eq!(peer_id, second);
     }
 
+    #[test]
+    fn peer_allowlist_reload() {
+        let peer_id = PeerId::random();
+        let other = PeerId::random();
+
+        let allowlist = PeerAllowlist::default();
+        assert!(allowlist.is_allowed(&other));
+
+        let shared = allowlist.clone();
+        shared.reload(Some(vec![peer_id.clone()].into_iter().collect()));
+        assert!(allowlist.is_allowed(&peer_id));
+        assert!(!allowlist.is_allowed(&other));
+
+        allowlist.reload(None);
+        assert!(shared.is_allowed(&other));
+    }
+
     #[test]
     fn peer_id_randomness() {
         let peer_id = PeerId::random();
@@ -1,13 +1,18 @@
 /// Most of the code for this module comes from `rust-libp2p`
 ///
 /// But upgrade library dependencies and follow the latest library requirements
-use crate::codec::StreamCipher;
+use crate::{codec::StreamCipher, error::SecioError};
 use aes_ctr::stream_cipher::generic_array::GenericArray;
 use aes_ctr::stream_cipher::NewStreamCipher;
 use aes_ctr::{Aes128Ctr, Aes256Ctr};
+use bytes::BytesMut;
 use ctr::Ctr128;
+use ring::aead;
 use twofish::Twofish;
 
+/// Nonce size of all supported AEAD ciphers
+const AEAD_NONCE_SIZE: usize = 12;
+
 /// Possible encryption ciphers.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Cipher {
@@ -17,6 +22,12 @@ pub enum Cipher {
     Aes256,
     /// Two fish
     TwofishCtr,
+    /// Aes128 in GCM mode, AEAD
+    Aes128Gcm,
+    /// Aes256 in GCM mode, AEAD
+    Aes256Gcm,
+    /// ChaCha20 with Poly1305, AEAD
+    ChaCha20Poly1305,
 }
 
 impl Cipher {
@@ -26,31 +37,185 @@ impl Cipher {
             Cipher::Aes128 => 16,
             Cipher::Aes256 => 32,
             Cipher::TwofishCtr => 32,
+            Cipher::Aes128Gcm => 16,
+            Cipher::Aes256Gcm => 32,
+            Cipher::ChaCha20Poly1305 => 32,
         }
     }
 
     /// Returns the size of in bytes of the IV expected by the cipher.
+    ///
+    /// AEAD ciphers only use the first 12 bytes as the base of nonce.
     #[inline]
     pub const fn iv_size(self) -> usize {
         16
     }
+
+    /// Whether the cipher authenticates the data by itself, no hmac is needed.
+    #[inline]
+    pub fn is_aead(self) -> bool {
+        match self {
+            Cipher::Aes128 | Cipher::Aes256 | Cipher::TwofishCtr => false,
+            Cipher::Aes128Gcm | Cipher::Aes256Gcm | Cipher::ChaCha20Poly1305 => true,
+        }
+    }
+
+    fn aead_algorithm(self) -> Option<&'static aead::Algorithm> {
+        match self {
+            Cipher::Aes128Gcm => Some(&aead::AES_128_GCM),
+            Cipher::Aes256Gcm => Some(&aead::AES_256_GCM),
+            Cipher::ChaCha20Poly1305 => Some(&aead::CHACHA20_POLY1305),
+            _ => None,
+        }
+    }
 }
 
 /// Returns your stream cipher depending on `Cipher`.
+///
+/// Returns an error if the cipher is AEAD, use `AeadCipher` instead.
 #[inline]
-pub fn ctr_init(key_size: Cipher, key: &[u8], iv: &[u8]) -> StreamCipher {
+pub fn ctr_init(key_size: Cipher, key: &[u8], iv: &[u8]) -> Result<StreamCipher, SecioError> {
     match key_size {
-        Cipher::Aes128 => Box::new(Aes128Ctr::new(
+        Cipher::Aes128 => Ok(Box::new(Aes128Ctr::new(
             GenericArray::from_slice(key),
             GenericArray::from_slice(iv),
-        )),
-        Cipher::Aes256 => Box::new(Aes256Ctr::new(
+        ))),
+        Cipher::Aes256 => Ok(Box::new(Aes256Ctr::new(
             GenericArray::from_slice(key),
             GenericArray::from_slice(iv),
-        )),
-        Cipher::TwofishCtr => Box::new(Ctr128::<Twofish>::new(
+        ))),
+        Cipher::TwofishCtr => Ok(Box::new(Ctr128::<Twofish>::new(
             GenericArray::from_slice(key),
             GenericArray::from_slice(iv),
-        )),
+        ))),
+        Cipher::Aes128Gcm | Cipher::Aes256Gcm | Cipher::ChaCha20Poly1305 => {
+            Err(SecioError::InvalidProposition("not a stream cipher"))
+        }
+    }
+}
+
+enum AeadKey {
+    Sealing(aead::SealingKey),
+    Opening(aead::OpeningKey),
+}
+
+/// AEAD cipher of one direction of the stream
+///
+/// Each frame is encrypted with a distinct nonce, which is the iv xor the frame counter,
+/// so both sides must encrypt and decrypt frames in the same order.
+pub struct AeadCipher {
+    key: AeadKey,
+    tag_len: usize,
+    iv: [u8; AEAD_NONCE_SIZE],
+    counter: u64,
+}
+
+impl AeadCipher {
+    /// Creates an AEAD cipher to encrypt frames.
+    pub fn sealing(cipher: Cipher, key: &[u8], iv: &[u8]) -> Result<Self, SecioError> {
+        let algorithm = cipher
+            .aead_algorithm()
+            .ok_or(SecioError::InvalidProposition("not an AEAD cipher"))?;
+        let key = aead::SealingKey::new(algorithm, key)
+            .map_err(|_| SecioError::SecretGenerationFailed)?;
+        Self::new(AeadKey::Sealing(key), algorithm.tag_len(), iv)
+    }
+
+    /// Creates an AEAD cipher to decrypt frames.
+    pub fn opening(cipher: Cipher, key: &[u8], iv: &[u8]) -> Result<Self, SecioError> {
+        let algorithm = cipher
+            .aead_algorithm()
+            .ok_or(SecioError::InvalidProposition("not an AEAD cipher"))?;
+        let key = aead::OpeningKey::new(algorithm, key)
+            .map_err(|_| SecioError::SecretGenerationFailed)?;
+        Self::new(AeadKey::Opening(key), algorithm.tag_len(), iv)
+    }
+
+    fn new(key: AeadKey, tag_len: usize, iv: &[u8]) -> Result<Self, SecioError> {
+        if iv.len() < AEAD_NONCE_SIZE {
+            return Err(SecioError::SecretGenerationFailed);
+        }
+        let mut nonce_base = [0; AEAD_NONCE_SIZE];
+        nonce_base.copy_from_slice(&iv[..AEAD_NONCE_SIZE]);
+        Ok(AeadCipher {
+            key,
+            tag_len,
+            iv: nonce_base,
+            counter: 0,
+        })
+    }
+
+    /// Returns the size in bytes of the authentication tag appended to each frame.
+    #[inline]
+    pub fn tag_len(&self) -> usize {
+        self.tag_len
+    }
+
+    /// The nonce of the next frame, a nonce must never be reused with the same key
+    fn next_nonce(&mut self) -> Result<aead::Nonce, SecioError> {
+        let mut nonce = self.iv;
+        for (byte, counter) in nonce[AEAD_NONCE_SIZE - 8..]
+            .iter_mut()
+            .zip(self.counter.to_be_bytes().iter())
+        {
+            *byte ^= counter;
+        }
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or(SecioError::NonceExhausted)?;
+        Ok(aead::Nonce::assume_unique_for_key(nonce))
+    }
+
+    /// Encrypts the data in place and appends the authentication tag.
+    pub fn encrypt(&mut self, data: &mut BytesMut) -> Result<(), SecioError> {
+        let nonce = self.next_nonce()?;
+        let key = match self.key {
+            AeadKey::Sealing(ref key) => key,
+            AeadKey::Opening(_) => return Err(SecioError::InvalidMessage),
+        };
+        data.extend_from_slice(&[0; aead::MAX_TAG_LEN][..self.tag_len]);
+        let len = aead::seal_in_place(key, nonce, aead::Aad::empty(), &mut data[..], self.tag_len)
+            .map_err(|_| SecioError::InvalidMessage)?;
+        data.truncate(len);
+        Ok(())
+    }
+
+    /// Verifies the authentication tag at the end of the frame, then decrypts it in place.
+    pub fn decrypt(&mut self, frame: &mut BytesMut) -> Result<(), SecioError> {
+        if frame.len() < self.tag_len {
+            return Err(SecioError::FrameTooShort);
+        }
+        let nonce = self.next_nonce()?;
+        let key = match self.key {
+            AeadKey::Opening(ref key) => key,
+            AeadKey::Sealing(_) => return Err(SecioError::InvalidMessage),
+        };
+        let len = aead::open_in_place(key, nonce, aead::Aad::empty(), 0, &mut frame[..])
+            .map_err(|_| SecioError::HmacNotMatching)?
+            .len();
+        frame.truncate(len);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ctr_init, Cipher};
+
+    #[test]
+    fn ctr_init_refuses_aead() {
+        for cipher in &[Cipher::Aes128, Cipher::Aes256, Cipher::TwofishCtr] {
+            let key = vec![0; cipher.key_size()];
+            assert!(ctr_init(*cipher, &key, &[0; 16]).is_ok());
+        }
+        for cipher in &[
+            Cipher::Aes128Gcm,
+            Cipher::Aes256Gcm,
+            Cipher::ChaCha20Poly1305,
+        ] {
+            let key = vec![0; cipher.key_size()];
+            assert!(ctr_init(*cipher, &key, &[0; 16]).is_err());
+        }
     }
 }
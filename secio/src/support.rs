@@ -9,17 +9,32 @@ use std::cmp::Ordering;
 
 const ECDH_P256: &str = "P-256";
 const ECDH_P384: &str = "P-384";
+const X25519: &str = "X25519";
 
 const AES_128: &str = "AES-128";
 const AES_256: &str = "AES-256";
 const TWOFISH_CTR: &str = "TwofishCTR";
+const AES_128_GCM: &str = "AES-128-GCM";
+const AES_256_GCM: &str = "AES-256-GCM";
+const CHACHA20_POLY1305: &str = "CHACHA20-POLY1305";
 
 const SHA_256: &str = "SHA256";
 const SHA_512: &str = "SHA512";
 
-pub(crate) const DEFAULT_AGREEMENTS_PROPOSITION: &str = "P-256,P-384";
-pub(crate) const DEFAULT_CIPHERS_PROPOSITION: &str = "AES-128,AES-256,TwofishCTR";
+/// X25519 is preferred, peers that don't support it still negotiate the NIST curves
+pub(crate) const DEFAULT_AGREEMENTS_PROPOSITION: &str = "X25519,P-256,P-384";
+/// AEAD ciphers are preferred, peers that don't support them still negotiate the CTR ciphers
+pub(crate) const DEFAULT_CIPHERS_PROPOSITION: &str =
+    "AES-128-GCM,AES-256-GCM,CHACHA20-POLY1305,AES-128,AES-256,TwofishCTR";
 pub(crate) const DEFAULT_DIGESTS_PROPOSITION: &str = "SHA256,SHA512";
+/// Not a cipher, it's appended to the ciphers proposition to advertise the support of rekeying,
+/// peers that don't support it skip it as an unknown cipher
+pub(crate) const REKEY_PROPOSITION: &str = "REKEY";
+
+/// Whether the ciphers proposition advertises the support of rekeying
+pub(crate) fn supports_rekey(ciphers: &str) -> bool {
+    ciphers.split(',').any(|x| x == REKEY_PROPOSITION)
+}
 
 /// Return a proposition string from the given sequence of `KeyAgreement` values.
 pub fn key_agreements_proposition<'a, I>(exchanges: I) -> String
@@ -37,6 +52,10 @@ where
                 s.push_str(ECDH_P384);
                 s.push(',')
             }
+            KeyAgreement::X25519 => {
+                s.push_str(X25519);
+                s.push(',')
+            }
         }
     }
     s.pop(); // remove trailing comma if any
@@ -57,6 +76,7 @@ pub fn select_agreement(r: Ordering, ours: &str, theirs: &str) -> Result<KeyAgre
             match x {
                 ECDH_P256 => return Ok(KeyAgreement::EcdhP256),
                 ECDH_P384 => return Ok(KeyAgreement::EcdhP384),
+                X25519 => return Ok(KeyAgreement::X25519),
                 _ => continue,
             }
         }
@@ -84,6 +104,18 @@ where
                 s.push_str(TWOFISH_CTR);
                 s.push(',')
             }
+            Cipher::Aes128Gcm => {
+                s.push_str(AES_128_GCM);
+                s.push(',')
+            }
+            Cipher::Aes256Gcm => {
+                s.push_str(AES_256_GCM);
+                s.push(',')
+            }
+            Cipher::ChaCha20Poly1305 => {
+                s.push_str(CHACHA20_POLY1305);
+                s.push(',')
+            }
         }
     }
     s.pop(); // remove trailing comma if any
@@ -148,6 +180,9 @@ pub fn select_cipher(r: Ordering, ours: &str, theirs: &str) -> Result<Cipher, Se
                 AES_128 => return Ok(Cipher::Aes128),
                 AES_256 => return Ok(Cipher::Aes256),
                 TWOFISH_CTR => return Ok(Cipher::TwofishCtr),
+                AES_128_GCM => return Ok(Cipher::Aes128Gcm),
+                AES_256_GCM => return Ok(Cipher::Aes256Gcm),
+                CHACHA20_POLY1305 => return Ok(Cipher::ChaCha20Poly1305),
                 _ => continue,
             }
         }
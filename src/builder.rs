@@ -5,11 +5,13 @@ use tokio::codec::LengthDelimitedCodec;
 
 use crate::{
     protocol_select::SelectFn,
-    secio::SecioKeyPair,
+    secio::{handshake::Config as SecioConfig, PeerId, SecioKeyPair},
     service::{
-        config::{Meta, ServiceConfig},
-        ProtocolHandle, ProtocolMeta, Service,
+        config::{HandlePoolConfig, InboundLimit, Meta, ServiceConfig},
+        BandwidthLimit, DialProtocol, EventHandle, EventStream, HandlePanicPolicy, PreSharedKey,
+        ProtocolHandle, ProtocolMeta, SecurityProtocol, Service,
     },
+    substream::MaxLengthCodec,
     traits::{Codec, ServiceHandle, ServiceProtocol, SessionProtocol},
     yamux::Config,
     ProtocolId,
@@ -30,13 +32,40 @@ impl ServiceBuilder {
     }
 
     /// Combine the configuration of this builder with service handle to create a Service.
-    pub fn build<H>(self, handle: H) -> Service<H>
+    pub fn build<H>(mut self, handle: H) -> Service<H>
     where
         H: ServiceHandle,
     {
+        // The service owns its allowlist, the one of secio config is only copied
+        if let Some(allowlist) = self
+            .config
+            .secio_config
+            .as_ref()
+            .and_then(SecioConfig::allowlist)
+        {
+            let peers = match (self.config.peer_allowlist.peers(), allowlist.peers()) {
+                (Some(mut peers), Some(others)) => {
+                    peers.extend(others);
+                    Some(peers)
+                }
+                (peers, others) => peers.or(others),
+            };
+            self.config.peer_allowlist.reload(peers);
+        }
         Service::new(self.inner, handle, self.key_pair, self.forever, self.config)
     }
 
+    /// Combine the configuration of this builder to create a service without handle
+    ///
+    /// All events and errors, and the events of protocols which have event handle,
+    /// are output by the returned stream through a bounded channel of `capacity`.
+    ///
+    /// When the channel is full, the service stops receiving new events until the stream is consumed.
+    pub fn build_with_stream(self, capacity: usize) -> (Service<EventHandle>, EventStream) {
+        let (handle, stream) = EventHandle::new(capacity);
+        (self.build(handle), stream)
+    }
+
     /// Insert a custom protocol
     pub fn insert_protocol(mut self, mut protocol: ProtocolMeta) -> Self {
         if protocol.session_handle().has_event() || protocol.service_handle.has_event() {
@@ -50,8 +79,71 @@ impl ServiceBuilder {
     /// Enable encrypted communication mode.
     ///
     /// If you do not need encrypted communication, you do not need to call this method
+    ///
+    /// It replaces the config set by `secio_config`, the default algorithms of secio are used.
     pub fn key_pair(mut self, key_pair: SecioKeyPair) -> Self {
         self.key_pair = Some(key_pair);
+        self.config.secio_config = None;
+        self
+    }
+
+    /// Enable encrypted communication mode with a full secio config,
+    /// to choose the supported key agreements, ciphers and digests.
+    ///
+    /// It replaces the key pair set by `key_pair`. The max frame length of
+    /// the config is ignored, use `max_frame_length` of this builder instead.
+    /// The algorithms don't apply to noise, see `accept_inbound_noise`.
+    ///
+    /// The peers of the config's `peer_allowlist` are copied to the allowlist of the service
+    /// on `build`, along with the ones of `peer_allowlist`, a peer in either list is allowed.
+    /// A list which allows all peers counts as no list, it doesn't widen the other one.
+    /// The config's list itself is never modified, and reloading it later doesn't affect
+    /// the service, use `ServiceControl::set_peer_allowlist` instead.
+    pub fn secio_config(mut self, config: SecioConfig) -> Self {
+        self.key_pair = Some(config.key_pair().clone());
+        self.config.secio_config = Some(config);
+        self
+    }
+
+    /// Handshake used to dial with encrypted communication mode, default is secio.
+    ///
+    /// Inbound sessions always accept secio, and accept noise if it is noise or
+    /// `accept_inbound_noise` is set. It only takes effect with `key_pair` or `secio_config`.
+    pub fn security_protocol(mut self, protocol: SecurityProtocol) -> Self {
+        self.config.security_protocol = protocol;
+        self
+    }
+
+    /// Inbound sessions accept noise besides secio, detected from the first message
+    /// of the remote, so the nodes of a network can switch one by one, default is false.
+    ///
+    /// Noise always uses ChaCha20-Poly1305, the algorithms of `secio_config` don't apply to it,
+    /// so don't accept noise if the algorithms of secio are restricted.
+    pub fn accept_inbound_noise(mut self, accept: bool) -> Self {
+        self.config.accept_inbound_noise = accept;
+        self
+    }
+
+    /// Join a private network, the raw connection is encrypted by the pre-shared key
+    /// before the secio or noise handshake.
+    ///
+    /// The nodes with another key, or without key, fail before the handshake with
+    /// `Error::PreSharedKeyMismatch` or a handshake error. Without `key_pair` or
+    /// `secio_config`, the connection is only encrypted by the pre-shared key.
+    pub fn pre_shared_key(mut self, key: PreSharedKey) -> Self {
+        self.config.pre_shared_key = Some(key);
+        self
+    }
+
+    /// Only accept the handshakes of these peers, the others are rejected as soon as
+    /// their public key is received, and reported by `ServiceError::PeerNotAllowed`.
+    ///
+    /// It can be reloaded at runtime by `ServiceControl::set_peer_allowlist`. It only takes
+    /// effect with `key_pair` or `secio_config`, by default all peers are allowed.
+    pub fn peer_allowlist<I: IntoIterator<Item = PeerId>>(self, peers: I) -> Self {
+        self.config
+            .peer_allowlist
+            .reload(Some(peers.into_iter().collect()));
         self
     }
 
@@ -88,6 +180,58 @@ impl ServiceBuilder {
         self
     }
 
+    /// What to do when a protocol handle panic
+    ///
+    /// Default is shutdown service
+    pub fn handle_panic_policy(mut self, policy: HandlePanicPolicy) -> Self {
+        self.config.handle_panic_policy = policy;
+        self
+    }
+
+    /// Limit the total bandwidth of all sessions, default is unlimited
+    ///
+    /// Can be adjusted at runtime by `ServiceControl`
+    pub fn global_bandwidth_limit(mut self, limit: BandwidthLimit) -> Self {
+        self.config.global_bandwidth = limit;
+        self
+    }
+
+    /// Limit the bandwidth of each session, default is unlimited
+    ///
+    /// Can be adjusted at runtime by `ServiceControl`
+    pub fn session_bandwidth_limit(mut self, limit: BandwidthLimit) -> Self {
+        self.config.session_bandwidth = limit;
+        self
+    }
+
+    /// Protocols opened by this side on inbound sessions, just like `DialProtocol` on dial
+    ///
+    /// Default is none, inbound sessions only open the protocols chosen by remote
+    pub fn inbound_protocols(mut self, target: DialProtocol) -> Self {
+        self.config.inbound_protocols = Some(target);
+        self
+    }
+
+    /// When opening protocols on a new session, open `before` first,
+    /// `after` is opened when `before` is open or failed to open
+    ///
+    /// It applies to both outbound and inbound sessions, only on the protocols opened by this side.
+    /// If the constraints form a cycle, the protocols in the cycle are opened together.
+    pub fn open_before(mut self, before: ProtocolId, after: ProtocolId) -> Self {
+        self.config.open_order.push((before, after));
+        self
+    }
+
+    /// How many distinct subnets (IPv4 /24, IPv6 /64) must report the same observed address
+    /// before it is confirmed as an external address, default is 3
+    ///
+    /// Observed addresses are reported by `ServiceControl::add_observed_addr`,
+    /// the report of a session is dropped when it is closed.
+    pub fn external_address_threshold(mut self, threshold: usize) -> Self {
+        self.config.external_address_threshold = threshold;
+        self
+    }
+
     /// Clear all protocols
     pub fn clear(&mut self) {
         self.inner.clear();
@@ -117,10 +261,13 @@ pub struct MetaBuilder {
     id: ProtocolId,
     name: NameFn,
     support_versions: Vec<String>,
-    codec: CodecFn,
+    codec: Option<CodecFn>,
     service_handle: ProtocolHandle<Box<dyn ServiceProtocol + Send + 'static>>,
     session_handle: SessionHandleFn,
     select_version: SelectVersionFn,
+    handle_pool: Option<HandlePoolConfig>,
+    inbound_limit: InboundLimit,
+    depends_on: Vec<ProtocolId>,
 }
 
 impl MetaBuilder {
@@ -166,7 +313,7 @@ impl MetaBuilder {
         mut self,
         codec: T,
     ) -> Self {
-        self.codec = Box::new(codec);
+        self.codec = Some(Box::new(codec));
         self
     }
 
@@ -201,14 +348,84 @@ impl MetaBuilder {
         self
     }
 
+    /// Run the service/session handles of this protocol on a dedicated thread pool,
+    /// default is running on the runtime of service
+    ///
+    /// Suitable for CPU-heavy handles, such as block verification. A slow handle will
+    /// only stall the events of this protocol, not the entire service.
+    ///
+    /// `threads` is the worker count of the pool, `queue_size` is the channel size of each handle,
+    /// when the channel is full, the events of this handle will be buffered by service.
+    ///
+    /// The pool is created when the first handle of this protocol is opened,
+    /// it has its own timer and reactor, so handles can still use `Delay`/`Interval`/`tokio::spawn`.
+    pub fn dedicated_pool(mut self, threads: usize, queue_size: usize) -> Self {
+        self.handle_pool = Some(HandlePoolConfig {
+            threads: ::std::cmp::max(threads, 1),
+            queue_size,
+        });
+        self
+    }
+
+    /// Define the max size of inbound message, default is unlimited
+    ///
+    /// The violation will be reported as `ServiceError::ProtocolError` with `Error::MessageTooLarge`,
+    /// and the message will be dropped before it reaches the handle.
+    ///
+    /// With the default codec, the length field of the message is checked before the payload
+    /// is read, and the sub stream is always closed on violation since the framing is lost.
+    /// A custom codec should limit its own frame size, the limit is checked after decoding.
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.inbound_limit.max_message_size = Some(size);
+        self
+    }
+
+    /// Define the max rate of inbound message, `per_second` messages per second with `burst`,
+    /// default is unlimited
+    ///
+    /// The violation will be reported as `ServiceError::ProtocolError` with `Error::MessageRateExceeded`,
+    /// and the message will be dropped before it reaches the handle.
+    pub fn max_message_rate(mut self, per_second: u32, burst: u32) -> Self {
+        self.inbound_limit.message_rate = Some((per_second, burst));
+        self
+    }
+
+    /// Close the protocol sub stream when inbound message violates the limits, default is false
+    pub fn close_on_violation(mut self, close: bool) -> Self {
+        self.inbound_limit.close_on_violation = close;
+        self
+    }
+
+    /// Declare that this protocol depends on another protocol, can be called multiple times
+    ///
+    /// When this side opens the protocol, the sub stream is opened only after the handles of
+    /// all the dependencies have run `connected` on the session. If a dependency fails to open,
+    /// this protocol is reported as a select error. When a dependency is closed,
+    /// this protocol is closed too.
+    pub fn depends_on(mut self, proto_id: ProtocolId) -> Self {
+        if !self.depends_on.contains(&proto_id) {
+            self.depends_on.push(proto_id);
+        }
+        self
+    }
+
     /// Combine the configuration of this builder to create a ProtocolMeta
     pub fn build(self) -> ProtocolMeta {
+        let codec: CodecFn = match (self.codec, self.inbound_limit.max_message_size) {
+            (Some(codec), _) => codec,
+            // Reject the oversized message by its length field
+            (None, Some(size)) => Box::new(move || Box::new(MaxLengthCodec::new(size))),
+            (None, None) => Box::new(|| Box::new(LengthDelimitedCodec::new())),
+        };
         let meta = Meta {
             id: self.id,
             name: self.name,
             support_versions: self.support_versions,
-            codec: self.codec,
+            codec,
             select_version: self.select_version,
+            handle_pool: self.handle_pool,
+            inbound_limit: self.inbound_limit,
+            depends_on: self.depends_on,
         };
         ProtocolMeta {
             inner: Arc::new(meta),
@@ -224,10 +441,13 @@ impl Default for MetaBuilder {
             id: ProtocolId::new(0),
             name: Box::new(|id| format!("/p2p/{}", id.value())),
             support_versions: vec!["0.0.1".to_owned()],
-            codec: Box::new(|| Box::new(LengthDelimitedCodec::new())),
+            codec: None,
             service_handle: ProtocolHandle::Neither,
             session_handle: Box::new(|| ProtocolHandle::Neither),
             select_version: Box::new(|| None),
+            handle_pool: None,
+            inbound_limit: InboundLimit::default(),
+            depends_on: Vec::new(),
         }
     }
 }
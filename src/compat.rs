@@ -0,0 +1,314 @@
+//! A `std::future` compatible facade over the futures 0.1 API
+//!
+//! Enabled by the `async-compat` feature, requires rustc 1.36+ for `std::future`,
+//! and rustc 1.39+ to use it with async/await. The pinned toolchain is older,
+//! so it is checked by `make test-compat` with a newer one.
+//!
+//! The service itself still needs to run on a tokio 0.1 runtime, such as `tokio::run`,
+//! this module only lets the code outside the runtime drive it with modern futures.
+
+use bytes::Bytes;
+use futures::{sync::mpsc, Async};
+use futures03::{
+    compat::{Compat01As03, Stream01CompatExt},
+    future::{self, FutureExt, Ready, TryFutureExt},
+    stream::{Stream, StreamExt},
+};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use crate::{
+    builder::ServiceBuilder,
+    context::ServiceContext,
+    error::Error,
+    multiaddr::Multiaddr,
+    service::{
+        event_stream::OutputSender, DialProtocol, EventStream, ProtocolEvent, ProtocolMeta,
+        Service, ServiceControl, ServiceError, ServiceEvent, ServiceOutput, TargetSession,
+    },
+    traits::ServiceHandle,
+    ProtocolId, SessionId,
+};
+
+/// `ServiceControl` whose operations return `std::future::Future`
+#[derive(Clone)]
+pub struct AsyncServiceControl {
+    inner: ServiceControl,
+}
+
+impl From<ServiceControl> for AsyncServiceControl {
+    fn from(inner: ServiceControl) -> Self {
+        AsyncServiceControl { inner }
+    }
+}
+
+impl AsyncServiceControl {
+    /// Get the futures 0.1 control, for the queries that do not need to wait
+    #[inline]
+    pub fn control(&self) -> &ServiceControl {
+        &self.inner
+    }
+
+    /// Create a new listener
+    #[inline]
+    pub fn listen(&self, address: Multiaddr) -> Ready<Result<(), Error>> {
+        future::ready(self.inner.listen(address))
+    }
+
+    /// Initiate a connection request to address
+    #[inline]
+    pub fn dial(&self, address: Multiaddr, target: DialProtocol) -> Ready<Result<(), Error>> {
+        future::ready(self.inner.dial(address, target))
+    }
+
+    /// Disconnect a connection
+    #[inline]
+    pub fn disconnect(&self, session_id: SessionId) -> Ready<Result<(), Error>> {
+        future::ready(self.inner.disconnect(session_id))
+    }
+
+    /// Send message
+    #[inline]
+    pub fn send_message_to(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        data: Bytes,
+    ) -> Ready<Result<(), Error>> {
+        future::ready(self.inner.send_message_to(session_id, proto_id, data))
+    }
+
+    /// Send data to the specified protocol for the specified sessions.
+    #[inline]
+    pub fn filter_broadcast(
+        &self,
+        target: TargetSession,
+        proto_id: ProtocolId,
+        data: Bytes,
+    ) -> Ready<Result<(), Error>> {
+        future::ready(self.inner.filter_broadcast(target, proto_id, data))
+    }
+
+    /// Run a `std::future::Future` on the service runtime
+    #[inline]
+    pub fn future_task<T>(&self, task: T) -> Ready<Result<(), Error>>
+    where
+        T: Future<Output = ()> + Send + 'static,
+    {
+        future::ready(self.inner.future_task(task.unit_error().boxed().compat()))
+    }
+
+    /// Try open a protocol
+    #[inline]
+    pub fn open_protocol(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+    ) -> Ready<Result<(), Error>> {
+        future::ready(self.inner.open_protocol(session_id, proto_id))
+    }
+
+    /// Try close a protocol
+    #[inline]
+    pub fn close_protocol(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+    ) -> Ready<Result<(), Error>> {
+        future::ready(self.inner.close_protocol(session_id, proto_id))
+    }
+
+    /// Register a new protocol at runtime
+    #[inline]
+    pub fn register_protocol(&self, meta: ProtocolMeta, open: bool) -> Ready<Result<(), Error>> {
+        future::ready(self.inner.register_protocol(meta, open))
+    }
+
+    /// Unregister a protocol at runtime
+    #[inline]
+    pub fn unregister_protocol(&self, proto_id: ProtocolId) -> Ready<Result<(), Error>> {
+        future::ready(self.inner.unregister_protocol(proto_id))
+    }
+
+    /// Set a service notify token
+    #[inline]
+    pub fn set_service_notify(
+        &self,
+        proto_id: ProtocolId,
+        interval: Duration,
+        token: u64,
+    ) -> Ready<Result<(), Error>> {
+        future::ready(self.inner.set_service_notify(proto_id, interval, token))
+    }
+
+    /// Remove a service notify token
+    #[inline]
+    pub fn remove_service_notify(
+        &self,
+        proto_id: ProtocolId,
+        token: u64,
+    ) -> Ready<Result<(), Error>> {
+        future::ready(self.inner.remove_service_notify(proto_id, token))
+    }
+
+    /// Close service, see `ServiceControl::close`
+    #[inline]
+    pub fn close(&self) -> Ready<Result<(), Error>> {
+        future::ready(self.inner.close())
+    }
+
+    /// Shutdown service, don't care anything, may cause partial message loss
+    #[inline]
+    pub fn shutdown(&self) -> Ready<Result<(), Error>> {
+        future::ready(self.inner.shutdown())
+    }
+}
+
+/// `std` version of `EventStream`, output service events, errors and protocol events
+///
+/// ```ignore
+/// let (service, events) = ServiceBuilder::default().build_with_stream(64);
+/// let mut events = AsyncEventStream::from(events);
+/// while let Some(output) = events.next().await {
+///     ...
+/// }
+/// ```
+pub struct AsyncEventStream {
+    inner: Compat01As03<EventStream>,
+}
+
+impl From<EventStream> for AsyncEventStream {
+    fn from(stream: EventStream) -> Self {
+        AsyncEventStream {
+            inner: stream.compat(),
+        }
+    }
+}
+
+impl Stream for AsyncEventStream {
+    type Item = ServiceOutput;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.inner.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(output))) => Poll::Ready(Some(output)),
+            Poll::Ready(Some(Err(_))) | Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl ServiceBuilder {
+    /// Combine the configuration of this builder to create a service without handle,
+    /// the outputs are split into `std` streams of session events, protocol events and errors
+    ///
+    /// Each stream has its own bounded channel of `capacity`, when one of the channels is full,
+    /// the service stops receiving new events until the stream is consumed.
+    ///
+    /// ```ignore
+    /// let (service, streams) = ServiceBuilder::default().build_with_async_streams(64);
+    /// let AsyncEventStreams { mut sessions, mut protocols, .. } = streams;
+    /// while let Some(event) = protocols.next().await {
+    ///     ...
+    /// }
+    /// ```
+    pub fn build_with_async_streams(
+        self,
+        capacity: usize,
+    ) -> (Service<AsyncEventHandle>, AsyncEventStreams) {
+        let (handle, streams) = AsyncEventHandle::new(capacity);
+        (self.build(handle), streams)
+    }
+}
+
+/// A service handle that forwards the session events, protocol events and errors
+/// to their own channels, created by `ServiceBuilder::build_with_async_streams`
+pub struct AsyncEventHandle {
+    sessions: OutputSender<ServiceEvent>,
+    protocols: OutputSender<ProtocolEvent>,
+    errors: OutputSender<ServiceError>,
+}
+
+impl AsyncEventHandle {
+    fn new(capacity: usize) -> (Self, AsyncEventStreams) {
+        let (sessions, session_receiver) = OutputSender::new(capacity);
+        let (protocols, protocol_receiver) = OutputSender::new(capacity);
+        let (errors, error_receiver) = OutputSender::new(capacity);
+        (
+            AsyncEventHandle {
+                sessions,
+                protocols,
+                errors,
+            },
+            AsyncEventStreams {
+                sessions: session_receiver.into(),
+                protocols: protocol_receiver.into(),
+                errors: error_receiver.into(),
+            },
+        )
+    }
+}
+
+impl ServiceHandle for AsyncEventHandle {
+    fn handle_error(&mut self, _control: &mut ServiceContext, error: ServiceError) {
+        self.errors.push(error)
+    }
+
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        self.sessions.push(event)
+    }
+
+    fn handle_proto(&mut self, _control: &mut ServiceContext, event: ProtocolEvent) {
+        self.protocols.push(event)
+    }
+
+    fn poll_ready(&mut self) -> Async<()> {
+        // Flush all channels, the service is paused if any of them is full
+        let sessions = self.sessions.flush();
+        let protocols = self.protocols.flush();
+        let errors = self.errors.flush();
+        if sessions.is_ready() && protocols.is_ready() && errors.is_ready() {
+            Async::Ready(())
+        } else {
+            Async::NotReady
+        }
+    }
+}
+
+/// The `std` streams of a service built by `ServiceBuilder::build_with_async_streams`
+pub struct AsyncEventStreams {
+    /// Session establishment and disconnection events, and the other service events
+    pub sessions: AsyncOutputStream<ServiceEvent>,
+    /// Events of the protocols which have event handle
+    pub protocols: AsyncOutputStream<ProtocolEvent>,
+    /// Runtime errors
+    pub errors: AsyncOutputStream<ServiceError>,
+}
+
+/// `std` stream of one kind of service output
+pub struct AsyncOutputStream<T> {
+    inner: Compat01As03<mpsc::Receiver<T>>,
+}
+
+impl<T> From<mpsc::Receiver<T>> for AsyncOutputStream<T> {
+    fn from(receiver: mpsc::Receiver<T>) -> Self {
+        AsyncOutputStream {
+            inner: receiver.compat(),
+        }
+    }
+}
+
+impl<T> Stream for AsyncOutputStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.inner.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(output))) => Poll::Ready(Some(output)),
+            Poll::Ready(Some(Err(_))) | Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
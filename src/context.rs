@@ -8,15 +8,23 @@ use std::{
     collections::HashMap,
     ops::{Deref, DerefMut},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 
+pub use crate::transports::TransportKind;
+
 use crate::{
+    extensions::Extensions,
     multiaddr::Multiaddr,
     protocol_select::ProtocolInfo,
-    secio::{PublicKey, SecioKeyPair},
-    service::{event::ServiceTask, DialProtocol, ServiceControl, SessionType, TargetSession},
+    secio::{Negotiated, PeerAllowlist, PublicKey, SecioKeyPair},
+    service::{
+        event::ServiceTask, BandwidthLimit, DialProtocol, NotifySchedule, ProtocolMeta,
+        ServiceControl, SessionType, TargetSession,
+    },
     session::SessionEvent,
+    traffic::{SessionTraffic, SessionTrafficCounter},
+    transports::limit::BandwidthControl,
     ProtocolId, SessionId,
 };
 
@@ -38,6 +46,31 @@ pub struct SessionContext {
     // TODO: use reference?
     /// Remote public key
     pub remote_pubkey: Option<PublicKey>,
+    /// Local socket address
+    pub local_address: Option<Multiaddr>,
+    /// The transport of this session
+    pub transport: TransportKind,
+    /// The algorithms negotiated by secio, none if secio is not enabled
+    pub negotiated: Option<Negotiated>,
+    /// The time the session was opened
+    pub opened_at: SystemTime,
+    pub(crate) traffic: Arc<SessionTrafficCounter>,
+    pub(crate) extensions: Arc<Extensions>,
+}
+
+impl SessionContext {
+    /// User data of this session, shared by all protocol handles,
+    /// and dropped when the session is closed
+    #[inline]
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Get a snapshot of the traffic of this session
+    #[inline]
+    pub fn traffic(&self) -> SessionTraffic {
+        self.traffic.snapshot()
+    }
 }
 
 /// The Service runtime can send some instructions to the inside of the handle.
@@ -55,9 +88,11 @@ impl ServiceContext {
         service_task_sender: mpsc::UnboundedSender<ServiceTask>,
         proto_infos: HashMap<ProtocolId, ProtocolInfo>,
         key_pair: Option<SecioKeyPair>,
+        bandwidth: BandwidthControl,
+        peer_allowlist: PeerAllowlist,
     ) -> Self {
         ServiceContext {
-            inner: ServiceControl::new(service_task_sender, proto_infos),
+            inner: ServiceControl::new(service_task_sender, proto_infos, bandwidth, peer_allowlist),
             key_pair,
             listens: Vec::new(),
         }
@@ -148,10 +183,53 @@ impl ServiceContext {
         &self.inner
     }
 
-    /// Get service protocol message, Map(ID, Name), but can't modify
+    /// Get a snapshot of the service protocol message, Map(ID, Name)
+    #[inline]
+    pub fn protocols(&self) -> HashMap<ProtocolId, ProtocolInfo> {
+        self.inner.protocols()
+    }
+
+    /// Register a new protocol at runtime
+    ///
+    /// If `open` is true, the protocol will be try open on all existing outbound sessions
+    #[inline]
+    pub fn register_protocol(&self, meta: ProtocolMeta, open: bool) {
+        if self.inner.register_protocol(meta, open).is_err() {
+            warn!("Service is abnormally closed")
+        }
+    }
+
+    /// Unregister a protocol at runtime, close it on all sessions and drop its handle
+    #[inline]
+    pub fn unregister_protocol(&self, proto_id: ProtocolId) {
+        if self.inner.unregister_protocol(proto_id).is_err() {
+            warn!("Service is abnormally closed")
+        }
+    }
+
+    /// Get a snapshot of the traffic of all sessions
+    #[inline]
+    pub fn traffic(&self) -> HashMap<SessionId, SessionTraffic> {
+        self.inner.traffic()
+    }
+
+    /// Render service metrics in the Prometheus text format
+    #[cfg(feature = "metrics")]
+    #[inline]
+    pub fn metrics(&self) -> String {
+        self.inner.metrics()
+    }
+
+    /// Adjust the total bandwidth limit of all sessions
+    #[inline]
+    pub fn set_global_bandwidth_limit(&self, limit: BandwidthLimit) {
+        self.inner.set_global_bandwidth_limit(limit)
+    }
+
+    /// Adjust the bandwidth limit of a session
     #[inline]
-    pub fn protocols(&self) -> &Arc<HashMap<ProtocolId, ProtocolInfo>> {
-        &self.inner.proto_infos
+    pub fn set_session_bandwidth_limit(&self, session_id: SessionId, limit: BandwidthLimit) {
+        self.inner.set_session_bandwidth_limit(session_id, limit)
     }
 
     /// Get the key pair of self
@@ -160,7 +238,17 @@ impl ServiceContext {
         self.key_pair.as_ref()
     }
 
-    /// Get service listen address list
+    /// Report the address of this node observed by a remote peer, such as the one from identify
+    ///
+    /// When enough distinct peers report the same address, it is confirmed as an external
+    /// address and included in `listens`
+    pub fn add_observed_addr(&self, session_id: SessionId, address: Multiaddr) {
+        if self.inner.add_observed_addr(session_id, address).is_err() {
+            warn!("Service is abnormally closed")
+        }
+    }
+
+    /// Get service listen address list, including the confirmed external addresses
     #[inline]
     pub fn listens(&self) -> &[Multiaddr] {
         self.listens.as_ref()
@@ -200,6 +288,85 @@ impl ServiceContext {
         }
     }
 
+    /// Set a service notify token that fires only once after the delay
+    pub fn set_service_delay(&self, proto_id: ProtocolId, after: Duration, token: u64) {
+        if self
+            .inner
+            .set_service_delay(proto_id, after, token)
+            .is_err()
+        {
+            warn!("Service is abnormally closed")
+        }
+    }
+
+    /// Set a session notify token that fires only once after the delay
+    pub fn set_session_delay(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        after: Duration,
+        token: u64,
+    ) {
+        if self
+            .inner
+            .set_session_delay(session_id, proto_id, after, token)
+            .is_err()
+        {
+            warn!("Service is abnormally closed")
+        }
+    }
+
+    /// Set a service notify token with a schedule
+    pub fn set_service_notify_schedule(
+        &self,
+        proto_id: ProtocolId,
+        schedule: NotifySchedule,
+        token: u64,
+    ) {
+        if self
+            .inner
+            .set_service_notify_schedule(proto_id, schedule, token)
+            .is_err()
+        {
+            warn!("Service is abnormally closed")
+        }
+    }
+
+    /// Set a session notify token with a schedule
+    pub fn set_session_notify_schedule(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        schedule: NotifySchedule,
+        token: u64,
+    ) {
+        if self
+            .inner
+            .set_session_notify_schedule(session_id, proto_id, schedule, token)
+            .is_err()
+        {
+            warn!("Service is abnormally closed")
+        }
+    }
+
+    /// The next fire time of a service notify token
+    #[inline]
+    pub fn service_notify_next_fire(&self, proto_id: ProtocolId, token: u64) -> Option<Instant> {
+        self.inner.service_notify_next_fire(proto_id, token)
+    }
+
+    /// The next fire time of a session notify token
+    #[inline]
+    pub fn session_notify_next_fire(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        token: u64,
+    ) -> Option<Instant> {
+        self.inner
+            .session_notify_next_fire(session_id, proto_id, token)
+    }
+
     /// Remove a service timer by a token
     pub fn remove_service_notify(&self, proto_id: ProtocolId, token: u64) {
         if self.inner.remove_service_notify(proto_id, token).is_err() {
@@ -1,4 +1,4 @@
-use crate::{secio::error::SecioError, SessionId};
+use crate::{secio::error::SecioError, ProtocolId, SessionId};
 use futures::sync::mpsc;
 use std::{error, fmt, io};
 
@@ -27,6 +27,14 @@ pub enum Error {
     SessionProtoHandleBlock(SessionId),
     /// Session protocol handle abnormally closed, may be user's protocol handle implementation problem
     SessionProtoHandleAbnormallyClosed(SessionId),
+    /// A protocol with the same id or name has been registered
+    RepeatedProtocol(ProtocolId),
+    /// Inbound message exceeds the max message size of protocol, contains the message size
+    MessageTooLarge(usize),
+    /// Inbound messages exceed the max message rate of protocol
+    MessageRateExceeded,
+    /// The remote is not in the same private network, its pre-shared key is different
+    PreSharedKeyMismatch,
 }
 
 impl PartialEq for Error {
@@ -37,6 +45,10 @@ impl PartialEq for Error {
             | (ConnectSelf, ConnectSelf)
             | (PeerIdNotMatch, PeerIdNotMatch) => true,
             (RepeatedConnection(i), RepeatedConnection(j)) => i == j,
+            (RepeatedProtocol(i), RepeatedProtocol(j)) => i == j,
+            (MessageTooLarge(i), MessageTooLarge(j)) => i == j,
+            (MessageRateExceeded, MessageRateExceeded) => true,
+            (PreSharedKeyMismatch, PreSharedKeyMismatch) => true,
             (HandshakeError(i), HandshakeError(j)) => i == j,
             _ => false,
         }
@@ -85,6 +97,10 @@ impl error::Error for Error {
             Error::SessionProtoHandleAbnormallyClosed(_) => {
                 "Session protocol handle abnormally closed"
             }
+            Error::RepeatedProtocol(_) => "Protocol has been registered",
+            Error::MessageTooLarge(_) => "Message exceeds the max message size of protocol",
+            Error::MessageRateExceeded => "Messages exceed the max message rate of protocol",
+            Error::PreSharedKeyMismatch => "Pre-shared key of private network mismatch",
         }
     }
 }
@@ -111,6 +127,16 @@ impl fmt::Display for Error {
             Error::SessionProtoHandleAbnormallyClosed(id) => {
                 write!(f, "Session [{}] protocol handle abnormally closed", id)
             }
+            Error::RepeatedProtocol(id) => write!(f, "Protocol [{}] has been registered", id),
+            Error::MessageTooLarge(size) => write!(
+                f,
+                "Message size [{}] exceeds the max message size of protocol",
+                size
+            ),
+            Error::MessageRateExceeded => {
+                write!(f, "Messages exceed the max message rate of protocol")
+            }
+            Error::PreSharedKeyMismatch => write!(f, "Pre-shared key of private network mismatch"),
         }
     }
 }
@@ -0,0 +1,91 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    sync::RwLock,
+};
+
+type AnyMap = HashMap<TypeId, Box<dyn Any + Send + Sync>>;
+
+/// A typed map of user data, at most one value of each type
+///
+/// Attached to a session, shared by all protocol handles of the session, and dropped with it
+///
+/// ```rust
+/// use tentacle::extensions::Extensions;
+///
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct ChainHeight(u64);
+///
+/// let extensions = Extensions::default();
+/// extensions.insert(ChainHeight(100));
+/// extensions.update(|height: &mut ChainHeight| height.0 += 1);
+/// assert_eq!(extensions.get::<ChainHeight>(), Some(ChainHeight(101)));
+/// ```
+#[derive(Default)]
+pub struct Extensions {
+    map: RwLock<AnyMap>,
+}
+
+impl Extensions {
+    /// Insert a value, return the old value of the same type
+    pub fn insert<T: Send + Sync + 'static>(&self, value: T) -> Option<T> {
+        self.map.write().ok().and_then(|mut map| {
+            map.insert(TypeId::of::<T>(), Box::new(value))
+                .and_then(downcast)
+        })
+    }
+
+    /// Get a clone of the value
+    pub fn get<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.with(T::clone)
+    }
+
+    /// Read the value without cloning it
+    pub fn with<T: Send + Sync + 'static, R, F: FnOnce(&T) -> R>(&self, f: F) -> Option<R> {
+        self.map.read().ok().and_then(|map| {
+            map.get(&TypeId::of::<T>())
+                .and_then(|value| value.downcast_ref::<T>())
+                .map(f)
+        })
+    }
+
+    /// Modify the value in place, return none if the value does not exist
+    pub fn update<T: Send + Sync + 'static, R, F: FnOnce(&mut T) -> R>(&self, f: F) -> Option<R> {
+        self.map.write().ok().and_then(|mut map| {
+            map.get_mut(&TypeId::of::<T>())
+                .and_then(|value| value.downcast_mut::<T>())
+                .map(f)
+        })
+    }
+
+    /// Whether the value of the type exists
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.map
+            .read()
+            .map(|map| map.contains_key(&TypeId::of::<T>()))
+            .unwrap_or_default()
+    }
+
+    /// Remove the value
+    pub fn remove<T: Send + Sync + 'static>(&self) -> Option<T> {
+        self.map
+            .write()
+            .ok()
+            .and_then(|mut map| map.remove(&TypeId::of::<T>()).and_then(downcast))
+    }
+}
+
+/// `downcast` is not implemented for `Box<dyn Any + Send + Sync>`, drop `Sync` first
+#[inline]
+fn downcast<T: 'static>(value: Box<dyn Any + Send + Sync>) -> Option<T> {
+    let value: Box<dyn Any + Send> = value;
+    value.downcast().ok().map(|value| *value)
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let len = self.map.read().map(|map| map.len()).unwrap_or_default();
+        write!(f, "Extensions {{ len: {} }}", len)
+    }
+}
@@ -29,10 +29,16 @@ pub use yamux;
 
 /// Some gadgets that help create a service
 pub mod builder;
+#[cfg(feature = "async-compat")]
+pub mod compat;
 /// Context for Session and Service
 pub mod context;
 /// Error
 pub mod error;
+/// Typed user data attached to sessions
+pub mod extensions;
+/// Service level metrics
+pub(crate) mod metrics;
 /// Protocol handle callback stream
 pub(crate) mod protocol_handle_stream;
 /// Protocol select
@@ -43,6 +49,8 @@ pub mod service;
 pub(crate) mod session;
 /// Each custom protocol in a session corresponds to a sub stream
 pub(crate) mod substream;
+/// Traffic statistics of sessions and protocols
+pub mod traffic;
 /// Useful traits
 pub mod traits;
 /// Underlying transport protocols wrapper
@@ -0,0 +1,210 @@
+use std::{
+    io,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use crate::{error::Error, service::SessionType};
+
+/// Kinds of dial error, used as metric label
+#[cfg(feature = "metrics")]
+const DIAL_ERROR_KINDS: [&str; 10] = [
+    "connect_self",
+    "repeated_connection",
+    "peer_id_not_match",
+    "handshake",
+    "dns_resolver",
+    "timeout",
+    "connection_refused",
+    "unreachable",
+    "io",
+    "other",
+];
+
+/// Service level metrics, updated by service and sessions
+#[derive(Debug, Default)]
+pub(crate) struct ServiceMetrics {
+    inbound_sessions: AtomicUsize,
+    outbound_sessions: AtomicUsize,
+    handshake_success: AtomicU64,
+    handshake_failure: AtomicU64,
+    dial_errors: [AtomicU64; 10],
+    protocol_select_failures: AtomicU64,
+    /// Events buffered by service because the service level handle channel is full
+    service_handle_queue: AtomicUsize,
+    /// Events buffered by service because the session level handle channel is full
+    session_handle_queue: AtomicUsize,
+    /// Handles whose channel has been full
+    blocked_handles: AtomicUsize,
+    yamux_streams: AtomicUsize,
+}
+
+impl ServiceMetrics {
+    #[inline]
+    pub(crate) fn session_open(&self, ty: SessionType) {
+        self.sessions(ty).fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn session_close(&self, ty: SessionType) {
+        self.sessions(ty).fetch_sub(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn sessions(&self, ty: SessionType) -> &AtomicUsize {
+        match ty {
+            SessionType::Inbound => &self.inbound_sessions,
+            SessionType::Outbound => &self.outbound_sessions,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn handshake(&self, success: bool) {
+        if success {
+            self.handshake_success.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.handshake_failure.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn dial_error(&self, error: &Error) {
+        let kind = match error {
+            Error::ConnectSelf => 0,
+            Error::RepeatedConnection(_) => 1,
+            Error::PeerIdNotMatch => 2,
+            Error::HandshakeError(_) => 3,
+            Error::DNSResolverError(_) => 4,
+            Error::IoError(err) => match err.kind() {
+                io::ErrorKind::TimedOut => 5,
+                io::ErrorKind::ConnectionRefused => 6,
+                io::ErrorKind::AddrNotAvailable => 7,
+                _ => 8,
+            },
+            _ => 9,
+        };
+        self.dial_errors[kind].fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn protocol_select_failure(&self) {
+        self.protocol_select_failures
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn handle_queue(&self, service: usize, session: usize, blocked: usize) {
+        self.service_handle_queue.store(service, Ordering::Relaxed);
+        self.session_handle_queue.store(session, Ordering::Relaxed);
+        self.blocked_handles.store(blocked, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn yamux_streams_change(&self, old: usize, new: usize) {
+        if new > old {
+            self.yamux_streams.fetch_add(new - old, Ordering::Relaxed);
+        } else {
+            self.yamux_streams.fetch_sub(old - new, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl ServiceMetrics {
+    /// Render metrics in the Prometheus text exposition format
+    pub(crate) fn render(&self) -> String {
+        use std::fmt::Write;
+
+        let mut output = String::new();
+
+        let _ = writeln!(output, "# HELP tentacle_sessions Open sessions.");
+        let _ = writeln!(output, "# TYPE tentacle_sessions gauge");
+        let _ = writeln!(
+            output,
+            "tentacle_sessions{{direction=\"inbound\"}} {}",
+            self.inbound_sessions.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            output,
+            "tentacle_sessions{{direction=\"outbound\"}} {}",
+            self.outbound_sessions.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(output, "# HELP tentacle_handshakes_total Secio handshakes.");
+        let _ = writeln!(output, "# TYPE tentacle_handshakes_total counter");
+        let _ = writeln!(
+            output,
+            "tentacle_handshakes_total{{result=\"success\"}} {}",
+            self.handshake_success.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            output,
+            "tentacle_handshakes_total{{result=\"failure\"}} {}",
+            self.handshake_failure.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            output,
+            "# HELP tentacle_dial_errors_total Dial errors by kind."
+        );
+        let _ = writeln!(output, "# TYPE tentacle_dial_errors_total counter");
+        for (kind, count) in DIAL_ERROR_KINDS.iter().zip(self.dial_errors.iter()) {
+            let _ = writeln!(
+                output,
+                "tentacle_dial_errors_total{{kind=\"{}\"}} {}",
+                kind,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        let _ = writeln!(
+            output,
+            "# HELP tentacle_protocol_select_failures_total Protocol select failures."
+        );
+        let _ = writeln!(
+            output,
+            "# TYPE tentacle_protocol_select_failures_total counter"
+        );
+        let _ = writeln!(
+            output,
+            "tentacle_protocol_select_failures_total {}",
+            self.protocol_select_failures.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            output,
+            "# HELP tentacle_handle_queue_depth Events waiting for a full protocol handle."
+        );
+        let _ = writeln!(output, "# TYPE tentacle_handle_queue_depth gauge");
+        let _ = writeln!(
+            output,
+            "tentacle_handle_queue_depth{{level=\"service\"}} {}",
+            self.service_handle_queue.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            output,
+            "tentacle_handle_queue_depth{{level=\"session\"}} {}",
+            self.session_handle_queue.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            output,
+            "# HELP tentacle_blocked_handles Protocol handles whose channel has been full."
+        );
+        let _ = writeln!(output, "# TYPE tentacle_blocked_handles gauge");
+        let _ = writeln!(
+            output,
+            "tentacle_blocked_handles {}",
+            self.blocked_handles.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(output, "# HELP tentacle_yamux_streams Open yamux streams.");
+        let _ = writeln!(output, "# TYPE tentacle_yamux_streams gauge");
+        let _ = writeln!(
+            output,
+            "tentacle_yamux_streams {}",
+            self.yamux_streams.load(Ordering::Relaxed)
+        );
+
+        output
+    }
+}
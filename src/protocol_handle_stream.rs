@@ -1,15 +1,71 @@
 use futures::{prelude::*, sync::mpsc};
-use log::warn;
+use log::{error, warn};
+use std::any::Any;
 use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
 
 use crate::{
     context::{ProtocolContext, ServiceContext, SessionContext},
     multiaddr::Multiaddr,
+    service::event::ServiceTask,
     traits::{ServiceProtocol, SessionProtocol},
     ProtocolId, SessionId,
 };
 
+/// Get the message from a panic payload
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<Any>".to_owned()
+    }
+}
+
+/// Report the panic of handle to service
+fn panic_report(
+    context: &ProtocolContext,
+    session_id: Option<SessionId>,
+    version: Option<String>,
+    payload: Box<dyn Any + Send>,
+) {
+    let message = panic_message(payload);
+    error!(
+        "proto [{}] handle panic, session: {:?}, message: {}",
+        context.proto_id, session_id, message
+    );
+    if context
+        .control()
+        .service_task_sender
+        .unbounded_send(ServiceTask::ProtocolHandlePanic {
+            proto_id: context.proto_id,
+            session_id,
+            version,
+            message,
+        })
+        .is_err()
+    {
+        warn!("Service is abnormally closed")
+    }
+}
+
+/// Tell service the handle has run `connected` of the session
+fn connected_report(context: &ProtocolContext, session_id: SessionId) {
+    if context
+        .control()
+        .service_task_sender
+        .unbounded_send(ServiceTask::ProtocolConnected {
+            session_id,
+            proto_id: context.proto_id,
+        })
+        .is_err()
+    {
+        warn!("Service is abnormally closed")
+    }
+}
+
 pub enum ServiceProtocolEvent {
     Init,
     Connected {
@@ -35,6 +91,19 @@ pub enum ServiceProtocolEvent {
     },
 }
 
+impl ServiceProtocolEvent {
+    /// The session which the event belongs to
+    #[inline]
+    fn session_id(&self) -> Option<SessionId> {
+        match self {
+            ServiceProtocolEvent::Connected { session, .. } => Some(session.id),
+            ServiceProtocolEvent::Disconnected { id }
+            | ServiceProtocolEvent::Received { id, .. } => Some(*id),
+            _ => None,
+        }
+    }
+}
+
 pub struct ServiceProtocolStream<T> {
     handle: T,
     /// External event is passed in from this
@@ -61,15 +130,29 @@ where
         }
     }
 
+    /// Handle event, if the handle panic, report it to service and continue
     #[inline]
     pub fn handle_event(&mut self, event: ServiceProtocolEvent) {
+        let session_id = event.session_id();
+        if let Err(payload) =
+            panic::catch_unwind(AssertUnwindSafe(|| self.handle_event_inner(event)))
+        {
+            panic_report(&self.handle_context, session_id, None, payload);
+        }
+    }
+
+    #[inline]
+    fn handle_event_inner(&mut self, event: ServiceProtocolEvent) {
         use self::ServiceProtocolEvent::*;
         match event {
             Init => self.handle.init(&mut self.handle_context),
             Connected { session, version } => {
+                // Record session before callback, the session can still receive message
+                // after the handle panic here
+                self.sessions.insert(session.id, Arc::clone(&session));
                 self.handle
                     .connected(self.handle_context.as_mut(&session), &version);
-                self.sessions.insert(session.id, session);
+                connected_report(&self.handle_context, session.id);
             }
             Disconnected { id } => {
                 if let Some(session) = self.sessions.remove(&id) {
@@ -118,7 +201,12 @@ where
             }
         }
 
-        self.handle.poll(&mut self.handle_context);
+        let handle = &mut self.handle;
+        let handle_context = &mut self.handle_context;
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| handle.poll(handle_context)))
+        {
+            panic_report(&self.handle_context, None, None, payload);
+        }
 
         Ok(Async::NotReady)
     }
@@ -149,6 +237,10 @@ pub struct SessionProtocolStream<T> {
     handle_context: ProtocolContext,
     context: Arc<SessionContext>,
     receiver: mpsc::Receiver<SessionProtocolEvent>,
+    /// Protocol version, record for rebuilding handle after panic
+    version: Option<String>,
+    /// The handle has panicked, all events will be dropped until service remove it
+    panicked: bool,
 }
 
 impl<T> SessionProtocolStream<T>
@@ -167,16 +259,32 @@ where
             handle_context: ProtocolContext::new(service_context, proto_id),
             receiver,
             context,
+            version: None,
+            panicked: false,
         }
     }
 
     #[inline]
     fn handle_event(&mut self, event: SessionProtocolEvent) {
+        if self.panicked {
+            return;
+        }
+        if let Err(payload) =
+            panic::catch_unwind(AssertUnwindSafe(|| self.handle_event_inner(event)))
+        {
+            self.panic(payload);
+        }
+    }
+
+    #[inline]
+    fn handle_event_inner(&mut self, event: SessionProtocolEvent) {
         use self::SessionProtocolEvent::*;
         match event {
             Connected { version } => {
+                self.version = Some(version.clone());
                 self.handle
                     .connected(self.handle_context.as_mut(&self.context), &version);
+                connected_report(&self.handle_context, self.context.id);
             }
             Disconnected => {
                 self.handle
@@ -197,6 +305,19 @@ where
         }
     }
 
+    /// Keep the receiver open until service remove this handle,
+    /// otherwise service will treat it as abnormally closed
+    #[inline]
+    fn panic(&mut self, payload: Box<dyn Any + Send>) {
+        self.panicked = true;
+        panic_report(
+            &self.handle_context,
+            Some(self.context.id),
+            self.version.clone(),
+            payload,
+        );
+    }
+
     #[inline(always)]
     fn close(&mut self) {
         self.receiver.close();
@@ -229,7 +350,16 @@ where
             }
         }
 
-        self.handle.poll(self.handle_context.as_mut(&self.context));
+        if !self.panicked {
+            let handle = &mut self.handle;
+            let handle_context = &mut self.handle_context;
+            let context = &self.context;
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| {
+                handle.poll(handle_context.as_mut(context))
+            })) {
+                self.panic(payload);
+            }
+        }
 
         Ok(Async::NotReady)
     }
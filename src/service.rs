@@ -1,32 +1,48 @@
 use futures::{
+    future,
     prelude::*,
+    stream,
     sync::{mpsc, oneshot},
 };
 use log::{debug, error, trace, warn};
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use std::{error::Error as ErrorTrait, io};
 use tokio::prelude::{AsyncRead, AsyncWrite, FutureExt};
-use tokio::timer::{self, Delay, Interval};
+use tokio::runtime::{self, Runtime};
+use tokio::timer::{timeout, Delay};
 
 use crate::{
     context::{ServiceContext, SessionContext, SessionControl},
     error::Error,
+    extensions::Extensions,
     multiaddr::{multihash::Multihash, Multiaddr, Protocol},
     protocol_handle_stream::{
         ServiceProtocolEvent, ServiceProtocolStream, SessionProtocolEvent, SessionProtocolStream,
     },
     protocol_select::ProtocolInfo,
-    secio::{handshake::Config, PublicKey, SecioKeyPair},
+    secio::{
+        codec::stream_handle::StreamHandle,
+        error::SecioError,
+        handshake::Config,
+        noise::{self, Config as NoiseConfig},
+        EphemeralPublicKey, PublicKey, SecioKeyPair,
+    },
     service::{
         config::{ServiceConfig, State},
         event::ServiceTask,
+        external::{ExternalAddrs, Subnet},
         future_task::{BoxedFutureTask, FutureTaskManager},
+        timer::{NotifyKey, NotifyTimer},
     },
-    session::{Session, SessionEvent, SessionMeta},
+    session::{ConnectionInfo, Session, SessionEvent, SessionMeta},
+    traffic::SessionTrafficCounter,
     traits::{ServiceHandle, ServiceProtocol, SessionProtocol},
-    transports::{MultiIncoming, MultiTransport, Transport, TransportError},
+    transports::{
+        limit::{BandwidthControl, BandwidthLimiter, LimitedStream},
+        pnet, MultiIncoming, MultiStream, MultiTransport, Transport, TransportError,
+    },
     utils::extract_peer_id,
     yamux::{session::SessionType as YamuxType, Config as YamuxConfig},
     ProtocolId, SessionId,
@@ -35,12 +51,20 @@ use crate::{
 pub(crate) mod config;
 mod control;
 pub(crate) mod event;
+pub(crate) mod event_stream;
+mod external;
 pub(crate) mod future_task;
+mod timer;
 
 pub use crate::service::{
-    config::{DialProtocol, ProtocolHandle, ProtocolMeta, TargetSession},
+    config::{
+        BandwidthLimit, DialProtocol, HandlePanicPolicy, PreSharedKey, ProtocolHandle,
+        ProtocolMeta, SecurityProtocol, TargetSession,
+    },
     control::ServiceControl,
     event::{ProtocolEvent, ServiceError, ServiceEvent},
+    event_stream::{EventHandle, EventStream, ServiceOutput},
+    timer::{MissedTickPolicy, NotifySchedule},
 };
 use bytes::Bytes;
 
@@ -60,6 +84,44 @@ pub(crate) enum InnerProtocolHandle {
     Session(Box<dyn SessionProtocol + Send + 'static>),
 }
 
+/// The protocols opened by this side on a new session, in the order of `ServiceBuilder::open_before`
+#[derive(Default)]
+struct PendingProtocols {
+    /// Waiting for the protocols that should be opened before them
+    waiting: Vec<ProtocolId>,
+    /// Opening, neither open nor failed yet
+    opening: HashSet<ProtocolId>,
+}
+
+impl PendingProtocols {
+    /// The protocol is open or failed to open
+    fn resolve(&mut self, proto_id: ProtocolId) {
+        self.waiting.retain(|id| *id != proto_id);
+        self.opening.remove(&proto_id);
+    }
+
+    /// Take the protocols which can be opened now
+    fn release(&mut self, order: &[(ProtocolId, ProtocolId)]) -> Vec<ProtocolId> {
+        let (blocked, mut ready): (Vec<ProtocolId>, Vec<ProtocolId>) =
+            self.waiting.iter().partition(|proto_id| {
+                order.iter().any(|(before, after)| {
+                    after == *proto_id
+                        && (self.waiting.contains(before) || self.opening.contains(before))
+                })
+            });
+
+        if ready.is_empty() && self.opening.is_empty() {
+            // Cycle in the order, open them together
+            ready = blocked;
+            self.waiting.clear();
+        } else {
+            self.waiting = blocked;
+        }
+        self.opening.extend(ready.iter().cloned());
+        ready
+    }
+}
+
 /// An abstraction of p2p service, currently only supports TCP protocol
 pub struct Service<T> {
     protocol_configs: HashMap<String, ProtocolMeta>,
@@ -70,6 +132,9 @@ pub struct Service<T> {
 
     listens: Vec<(Multiaddr, MultiIncoming)>,
 
+    /// External addresses observed by remote peers
+    external_addrs: ExternalAddrs,
+
     dial_protocols: HashMap<Multiaddr, DialProtocol>,
     config: ServiceConfig,
     /// service state
@@ -98,10 +163,22 @@ pub struct Service<T> {
     // The service protocols open with the session
     session_service_protos: HashMap<SessionId, HashSet<ProtocolId>>,
 
+    /// The protocols waiting to be opened by this side in order
+    pending_protocols: HashMap<SessionId, PendingProtocols>,
+    /// The number of handles which have not run `connected` of the open protocols
+    connecting_protocols: HashMap<(SessionId, ProtocolId), usize>,
+
     service_proto_handles: HashMap<ProtocolId, mpsc::Sender<ServiceProtocolEvent>>,
 
     session_proto_handles: HashMap<(SessionId, ProtocolId), mpsc::Sender<SessionProtocolEvent>>,
 
+    /// The dedicated thread pools of protocol handles
+    handle_pools: HashMap<ProtocolId, Runtime>,
+
+    /// The protocols unregistered at runtime, their handles are dropped
+    /// after all sessions close them
+    unregistered_protocols: HashSet<ProtocolId>,
+
     /// Send events to service, clone to session
     session_event_sender: mpsc::Sender<SessionEvent>,
     /// Receive event from service
@@ -141,6 +218,7 @@ where
             })
             .collect();
         let (future_task_sender, future_task_receiver) = mpsc::channel(SEND_SIZE);
+        let bandwidth = BandwidthControl::new(config.global_bandwidth, config.session_bandwidth);
 
         Service {
             protocol_configs,
@@ -151,9 +229,14 @@ where
             service_notify_signals: HashMap::default(),
             sessions: HashMap::default(),
             session_service_protos: HashMap::default(),
+            pending_protocols: HashMap::default(),
+            connecting_protocols: HashMap::default(),
             service_proto_handles: HashMap::default(),
             session_proto_handles: HashMap::default(),
+            handle_pools: HashMap::default(),
+            unregistered_protocols: HashSet::default(),
             listens: Vec::new(),
+            external_addrs: ExternalAddrs::new(config.external_address_threshold),
             dial_protocols: HashMap::default(),
             config,
             state: State::new(forever),
@@ -163,7 +246,13 @@ where
             read_session_buf: VecDeque::default(),
             session_event_sender,
             session_event_receiver,
-            service_context: ServiceContext::new(service_task_sender, proto_infos, key_pair),
+            service_context: ServiceContext::new(
+                service_task_sender,
+                proto_infos,
+                key_pair,
+                bandwidth,
+                config.peer_allowlist.clone(),
+            ),
             service_task_receiver,
             pending_tasks: VecDeque::default(),
             handles_error_count: HashMap::default(),
@@ -359,13 +448,10 @@ where
                         );
 
                         error = true;
-                        self.handle.handle_error(
-                            &mut self.service_context,
-                            ServiceError::ProtocolHandleError {
-                                proto_id,
-                                error: Error::ServiceProtoHandleAbnormallyClosed,
-                            },
-                        );
+                        self.handle_error(ServiceError::ProtocolHandleError {
+                            proto_id,
+                            error: Error::ServiceProtoHandleAbnormallyClosed,
+                        });
                     }
                 }
             }
@@ -389,13 +475,10 @@ where
                         );
 
                         error = true;
-                        self.handle.handle_error(
-                            &mut self.service_context,
-                            ServiceError::ProtocolHandleError {
-                                proto_id,
-                                error: Error::SessionProtoHandleAbnormallyClosed(session_id),
-                            },
-                        )
+                        self.handle_error(ServiceError::ProtocolHandleError {
+                            proto_id,
+                            error: Error::SessionProtoHandleAbnormallyClosed(session_id),
+                        })
                     }
                 }
             }
@@ -413,6 +496,29 @@ where
         if self.read_session_buf.capacity() > BUF_SHRINK_THRESHOLD {
             self.read_session_buf.shrink_to_fit();
         }
+
+        self.service_context.control().metrics.handle_queue(
+            self.read_service_buf.len(),
+            self.read_session_buf.len(),
+            self.handles_error_count.len(),
+        );
+    }
+
+    /// Report error to the service handle
+    #[inline]
+    fn handle_error(&mut self, error: ServiceError) {
+        match error {
+            ServiceError::DialerError { ref error, .. } => {
+                self.service_context.control().metrics.dial_error(error)
+            }
+            ServiceError::ProtocolSelectError { .. } => self
+                .service_context
+                .control()
+                .metrics
+                .protocol_select_failure(),
+            _ => (),
+        }
+        self.handle.handle_error(&mut self.service_context, error);
     }
 
     /// When proto handle channel is full, call here
@@ -432,10 +538,7 @@ where
                         .map(Error::SessionProtoHandleBlock)
                         .unwrap_or(Error::ServiceProtoHandleBlock);
                     self.set_delay();
-                    self.handle.handle_error(
-                        &mut self.service_context,
-                        ServiceError::ProtocolHandleError { proto_id, error },
-                    );
+                    self.handle_error(ServiceError::ProtocolHandleError { proto_id, error });
                 }
                 Ok(Async::NotReady) => *delay = Some(inner),
                 Err(_) => {
@@ -448,6 +551,96 @@ where
         }
     }
 
+    /// When proto handle panic, call here
+    fn handle_panic(
+        &mut self,
+        proto_id: ProtocolId,
+        session_id: Option<SessionId>,
+        version: Option<String>,
+        message: String,
+    ) {
+        self.handle_error(ServiceError::ProtocolHandlePanic {
+            proto_id,
+            session_id,
+            message,
+        });
+
+        match self.config.handle_panic_policy {
+            HandlePanicPolicy::Restart => {
+                if let (Some(id), Some(version)) = (session_id, version) {
+                    // Drop the old handle, and the events that have not been processed
+                    if self.session_proto_handles.remove(&(id, proto_id)).is_some() {
+                        debug!("restart session [{}] proto [{}] handle", id, proto_id);
+                        self.read_session_buf
+                            .retain(|(s_id, p_id, _)| (*s_id, *p_id) != (id, proto_id));
+                        if let Some(handle) = self.proto_handle(true, proto_id) {
+                            self.handle_open(handle, proto_id, Some(id));
+                            self.read_session_buf.push_back((
+                                id,
+                                proto_id,
+                                SessionProtocolEvent::Connected { version },
+                            ));
+                            self.distribute_to_user_level();
+                        }
+                    }
+                }
+            }
+            HandlePanicPolicy::CloseProtocol => {
+                if let Some(id) = session_id {
+                    self.protocol_close(id, proto_id, Source::External);
+                }
+            }
+            HandlePanicPolicy::Shutdown => self.handle_service_task(ServiceTask::Shutdown(false)),
+        }
+    }
+
+    /// Spawn protocol handle stream, on the dedicated thread pool if the protocol has one
+    fn spawn_handle<F>(&mut self, proto_id: ProtocolId, stream: F)
+    where
+        F: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        let pool_config = self
+            .protocol_configs
+            .values()
+            .find(|meta| meta.id() == proto_id)
+            .and_then(|meta| meta.inner.handle_pool);
+
+        if let Some(config) = pool_config {
+            if !self.handle_pools.contains_key(&proto_id) {
+                match runtime::Builder::new()
+                    .core_threads(config.threads)
+                    .name_prefix(format!("proto-{}-handle-", proto_id.value()))
+                    .build()
+                {
+                    Ok(pool) => {
+                        self.handle_pools.insert(proto_id, pool);
+                    }
+                    Err(err) => error!(
+                        "proto [{}] handle pool create error: {}, run on service runtime",
+                        proto_id, err
+                    ),
+                }
+            }
+            if let Some(pool) = self.handle_pools.get(&proto_id) {
+                pool.executor().spawn(stream);
+                return;
+            }
+        }
+
+        tokio::spawn(stream);
+    }
+
+    /// The channel size of protocol handle
+    #[inline]
+    fn handle_queue_size(&self, proto_id: ProtocolId, default: usize) -> usize {
+        self.protocol_configs
+            .values()
+            .find(|meta| meta.id() == proto_id)
+            .and_then(|meta| meta.inner.handle_pool)
+            .map(|config| config.queue_size)
+            .unwrap_or(default)
+    }
+
     /// Spawn protocol handle
     #[inline]
     fn handle_open(
@@ -459,7 +652,8 @@ where
         match handle {
             InnerProtocolHandle::Service(handle) => {
                 debug!("init service level [{}] proto handle", proto_id);
-                let (sender, receiver) = mpsc::channel(RECEIVED_SIZE);
+                let (sender, receiver) =
+                    mpsc::channel(self.handle_queue_size(proto_id, RECEIVED_SIZE));
                 let mut stream = ServiceProtocolStream::new(
                     handle,
                     self.service_context.clone_self(),
@@ -474,23 +668,27 @@ where
 
                 stream.handle_event(ServiceProtocolEvent::Init);
 
-                tokio::spawn(stream.for_each(|_| Ok(())).map_err(|_| ()));
+                self.spawn_handle(proto_id, stream.for_each(|_| Ok(())).map_err(|_| ()));
             }
 
             InnerProtocolHandle::Session(handle) => {
                 let id = id.unwrap();
-                if let Some(session_control) = self.sessions.get(&id) {
+                if let Some(inner) = self
+                    .sessions
+                    .get(&id)
+                    .map(|session_control| Arc::clone(&session_control.inner))
+                {
                     debug!("init session [{}] level proto [{}] handle", id, proto_id);
-                    let (sender, receiver) = mpsc::channel(32);
+                    let (sender, receiver) = mpsc::channel(self.handle_queue_size(proto_id, 32));
                     let stream = SessionProtocolStream::new(
                         handle,
                         self.service_context.clone_self(),
-                        Arc::clone(&session_control.inner),
+                        inner,
                         receiver,
                         proto_id,
                     );
 
-                    tokio::spawn(stream.for_each(|_| Ok(())).map_err(|_| ()));
+                    self.spawn_handle(proto_id, stream.for_each(|_| Ok(())).map_err(|_| ()));
 
                     self.session_proto_handles
                         .entry((id, proto_id))
@@ -605,51 +803,103 @@ where
 
     /// Handshake
     #[inline]
-    fn handshake<H>(&mut self, socket: H, ty: SessionType, remote_address: Multiaddr)
-    where
-        H: AsyncRead + AsyncWrite + Send + 'static,
-    {
+    fn handshake(&mut self, socket: MultiStream, ty: SessionType, remote_address: Multiaddr) {
+        let info = ConnectionInfo {
+            local_address: socket.local_addr(),
+            transport: socket.kind(),
+            negotiated: None,
+            secure_traffic: None,
+        };
+
+        // The limiter wraps the raw transport, so secio and yamux overhead is counted as well
+        let bandwidth = self.service_context.control().bandwidth.new_session();
+        let socket = LimitedStream::new(
+            socket,
+            Arc::clone(&self.service_context.control().bandwidth.global),
+            Arc::clone(&bandwidth),
+        );
+
         if let Some(key_pair) = self.service_context.key_pair() {
-            let key_pair = key_pair.clone();
+            let config = match self.config.secio_config {
+                Some(ref config) => config.clone(),
+                None => Config::new(key_pair.clone()),
+            };
+            let allowlist = self.service_context.control().peer_allowlist.clone();
+            let config = config
+                .max_frame_length(self.config.max_frame_length)
+                .peer_allowlist(allowlist.clone());
+            let noise_config = NoiseConfig::new(key_pair.clone())
+                .max_frame_length(self.config.max_frame_length)
+                .peer_allowlist(allowlist);
             let sender = self.session_event_sender.clone();
 
-            let task = Config::new(key_pair)
-                .max_frame_length(self.config.max_frame_length)
-                .handshake(socket)
+            let security_protocol = self.config.security_protocol;
+            let accept_noise =
+                self.config.accept_inbound_noise || security_protocol == SecurityProtocol::Noise;
+            let handshake: Box<
+                dyn Future<Item = (StreamHandle, PublicKey, EphemeralPublicKey), Error = Error>
+                    + Send,
+            > = match self.config.pre_shared_key.clone() {
+                Some(psk) => Box::new(pnet::handshake(socket, psk).and_then(move |socket| {
+                    secure_handshake(
+                        socket,
+                        ty,
+                        security_protocol,
+                        accept_noise,
+                        config,
+                        noise_config,
+                    )
+                    .from_err()
+                })),
+                None => Box::new(
+                    secure_handshake(
+                        socket,
+                        ty,
+                        security_protocol,
+                        accept_noise,
+                        config,
+                        noise_config,
+                    )
+                    .from_err(),
+                ),
+            };
+
+            let task = handshake.timeout(self.config.timeout).then(move |result| {
+                let send_task = match result {
+                    Ok((handle, public_key, _)) => sender.send(SessionEvent::HandshakeSuccess {
+                        handle,
+                        public_key,
+                        bandwidth,
+                        info,
+                        address: remote_address,
+                        ty,
+                    }),
+                    Err(err) => sender.send(handshake_fail(err, remote_address, ty)),
+                };
+
+                tokio::spawn(send_task.map(|_| ()).map_err(|err| {
+                    error!("handshake result send back error: {:?}", err);
+                }));
+
+                Ok(())
+            });
+
+            tokio::spawn(task);
+        } else if let Some(psk) = self.config.pre_shared_key.clone() {
+            // Without secio, the private network still encrypts the raw connection
+            let sender = self.session_event_sender.clone();
+            let task = pnet::handshake(socket, psk)
                 .timeout(self.config.timeout)
                 .then(move |result| {
                     let send_task = match result {
-                        Ok((handle, public_key, _)) => {
-                            sender.send(SessionEvent::HandshakeSuccess {
-                                handle,
-                                public_key,
-                                address: remote_address,
-                                ty,
-                            })
-                        }
-                        Err(err) => {
-                            let error = if err.is_timer() {
-                                // tokio timer error
-                                io::Error::new(io::ErrorKind::Other, err.description()).into()
-                            } else if err.is_elapsed() {
-                                // time out error
-                                io::Error::new(io::ErrorKind::TimedOut, err.description()).into()
-                            } else {
-                                // dialer error
-                                err.into_inner().unwrap().into()
-                            };
-
-                            debug!(
-                                "Handshake with {} failed, error: {:?}",
-                                remote_address, error
-                            );
-
-                            sender.send(SessionEvent::HandshakeFail {
-                                ty,
-                                error,
-                                address: remote_address,
-                            })
-                        }
+                        Ok(stream) => sender.send(SessionEvent::PnetHandshakeSuccess {
+                            stream,
+                            bandwidth,
+                            info,
+                            address: remote_address,
+                            ty,
+                        }),
+                        Err(err) => sender.send(handshake_fail(err, remote_address, ty)),
                     };
 
                     tokio::spawn(send_task.map(|_| ()).map_err(|err| {
@@ -661,7 +911,7 @@ where
 
             tokio::spawn(task);
         } else {
-            self.session_open(socket, None, remote_address, ty);
+            self.session_open(socket, None, info, bandwidth, remote_address, ty);
         }
     }
 
@@ -671,6 +921,8 @@ where
         &mut self,
         mut handle: H,
         remote_pubkey: Option<PublicKey>,
+        info: ConnectionInfo,
+        bandwidth: Arc<BandwidthLimiter>,
         mut address: Multiaddr,
         ty: SessionType,
     ) where
@@ -679,10 +931,12 @@ where
         if ty.is_outbound() {
             self.state.decrease();
         }
-        let target = self
-            .dial_protocols
-            .remove(&address)
-            .unwrap_or_else(|| DialProtocol::All);
+        let target = self.dial_protocols.remove(&address);
+        let target = if ty.is_outbound() {
+            target.or(Some(DialProtocol::All))
+        } else {
+            self.config.inbound_protocols.clone()
+        };
         if let Some(ref key) = remote_pubkey {
             // If the public key exists, the connection has been established
             // and then the useless connection needs to be closed.
@@ -695,21 +949,15 @@ where
                     trace!("Connected to the connected node");
                     let _ = handle.shutdown();
                     if ty.is_outbound() {
-                        self.handle.handle_error(
-                            &mut self.service_context,
-                            ServiceError::DialerError {
-                                error: Error::RepeatedConnection(context.inner.id),
-                                address,
-                            },
-                        );
+                        self.handle_error(ServiceError::DialerError {
+                            error: Error::RepeatedConnection(context.inner.id),
+                            address,
+                        });
                     } else {
-                        self.handle.handle_error(
-                            &mut self.service_context,
-                            ServiceError::ListenError {
-                                error: Error::RepeatedConnection(context.inner.id),
-                                address,
-                            },
-                        );
+                        self.handle_error(ServiceError::ListenError {
+                            error: Error::RepeatedConnection(context.inner.id),
+                            address,
+                        });
                     }
                     return;
                 }
@@ -718,13 +966,10 @@ where
                     if let Some(peer_id) = extract_peer_id(&address) {
                         if key.peer_id() != peer_id {
                             trace!("Peer id not match");
-                            self.handle.handle_error(
-                                &mut self.service_context,
-                                ServiceError::DialerError {
-                                    error: Error::PeerIdNotMatch,
-                                    address,
-                                },
-                            );
+                            self.handle_error(ServiceError::DialerError {
+                                error: Error::PeerIdNotMatch,
+                                address,
+                            });
                             return;
                         }
                     } else {
@@ -742,6 +987,7 @@ where
         }
 
         let (service_event_sender, service_event_receiver) = mpsc::channel(SEND_SIZE);
+        let traffic = Arc::new(SessionTrafficCounter::new(info.secure_traffic));
         let session_control = SessionControl {
             notify_signals: HashMap::default(),
             event_sender: service_event_sender,
@@ -750,6 +996,12 @@ where
                 address,
                 ty,
                 remote_pubkey,
+                local_address: info.local_address,
+                transport: info.transport,
+                negotiated: info.negotiated,
+                opened_at: SystemTime::now(),
+                traffic: Arc::clone(&traffic),
+                extensions: Arc::new(Extensions::default()),
             }),
         };
 
@@ -760,7 +1012,16 @@ where
                     .map(|(key, value)| (key.clone(), value.inner.clone()))
                     .collect(),
             )
-            .config(self.config.yamux_config);
+            .config(self.config.yamux_config)
+            .traffic(Arc::clone(&traffic))
+            .metrics(Arc::clone(&self.service_context.control().metrics));
+
+        if let Ok(mut sessions) = self.service_context.control().traffic.write() {
+            sessions.insert(self.next_session, traffic);
+        }
+        if let Ok(mut sessions) = self.service_context.control().bandwidth.sessions.write() {
+            sessions.insert(self.next_session, bandwidth);
+        }
 
         let mut session = Session::new(
             handle,
@@ -769,27 +1030,31 @@ where
             meta,
         );
 
-        if ty.is_outbound() {
-            match target {
-                DialProtocol::All => {
-                    self.protocol_configs
-                        .keys()
-                        .for_each(|name| session.open_proto_stream(name));
-                }
-                DialProtocol::Single(proto_id) => {
-                    self.protocol_configs
-                        .values()
-                        .find(|meta| meta.id() == proto_id)
-                        .and_then(|meta| {
-                            session.open_proto_stream(&meta.name());
-                            Some(())
-                        });
-                }
-                DialProtocol::Multi(proto_ids) => self
+        if let Some(target) = target {
+            let mut pending = PendingProtocols {
+                waiting: self
                     .protocol_configs
                     .values()
-                    .filter(|meta| proto_ids.contains(&meta.id()))
-                    .for_each(|meta| session.open_proto_stream(&meta.name())),
+                    .map(ProtocolMeta::id)
+                    .filter(|proto_id| match target {
+                        DialProtocol::All => true,
+                        DialProtocol::Single(ref id) => id == proto_id,
+                        DialProtocol::Multi(ref ids) => ids.contains(proto_id),
+                    })
+                    .collect(),
+                opening: HashSet::default(),
+            };
+            for proto_id in pending.release(&self.config.open_order) {
+                if let Some(meta) = self
+                    .protocol_configs
+                    .values()
+                    .find(|meta| meta.id() == proto_id)
+                {
+                    session.open_proto_stream(&meta.name());
+                }
+            }
+            if !pending.waiting.is_empty() {
+                self.pending_protocols.insert(self.next_session, pending);
             }
         }
 
@@ -802,6 +1067,7 @@ where
             },
         );
 
+        self.service_context.control().metrics.session_open(ty);
         self.sessions
             .insert(session_control.inner.id, session_control);
     }
@@ -827,7 +1093,23 @@ where
             self.protocol_close(id, proto_id, Source::Internal);
         });
 
+        self.pending_protocols.remove(&id);
+        // The vote of a closed session is not trusted anymore
+        if self.external_addrs.remove(id) {
+            self.external_addrs_changed();
+        }
+        if let Ok(mut sessions) = self.service_context.control().traffic.write() {
+            sessions.remove(&id);
+        }
+        if let Ok(mut sessions) = self.service_context.control().bandwidth.sessions.write() {
+            sessions.remove(&id);
+        }
+
         if let Some(session_control) = self.sessions.remove(&id) {
+            self.service_context
+                .control()
+                .metrics
+                .session_close(session_control.inner.ty);
             // Service handle processing flow
             self.handle.handle_event(
                 &mut self.service_context,
@@ -869,6 +1151,7 @@ where
             .entry(id)
             .or_default()
             .insert(proto_id);
+        self.open_pending_protocols(id, proto_id);
 
         if self.config.event.contains(&proto_id) {
             if let Some(session_control) = self.sessions.get(&id) {
@@ -884,6 +1167,9 @@ where
             }
         }
 
+        // The number of handles will run `connected`
+        let mut connecting = 0;
+
         // callback output
         // Service proto handle processing flow
         if !self.service_proto_handles.contains_key(&proto_id) {
@@ -901,6 +1187,7 @@ where
                         version: version.clone(),
                     },
                 ));
+                connecting += 1;
             }
         }
 
@@ -913,11 +1200,43 @@ where
                 proto_id,
                 SessionProtocolEvent::Connected { version },
             ));
+            connecting += 1;
+        }
+
+        if connecting == 0 {
+            self.protocol_connected(id, proto_id);
+        } else {
+            self.connecting_protocols.insert((id, proto_id), connecting);
         }
 
         self.distribute_to_user_level();
     }
 
+    /// A handle of the protocol has run `connected`, tell the session after all of them,
+    /// then the protocols depend on it can be opened
+    fn handle_connected(&mut self, session_id: SessionId, proto_id: ProtocolId) {
+        let key = (session_id, proto_id);
+        let connected = match self.connecting_protocols.get_mut(&key) {
+            Some(connecting) => {
+                *connecting -= 1;
+                *connecting == 0
+            }
+            None => return,
+        };
+        if connected {
+            self.connecting_protocols.remove(&key);
+            self.protocol_connected(session_id, proto_id);
+        }
+    }
+
+    #[inline]
+    fn protocol_connected(&mut self, id: SessionId, proto_id: ProtocolId) {
+        debug!("service session [{}] proto [{}] connected", id, proto_id);
+        self.write_buf
+            .push_back((id, SessionEvent::ProtocolConnected { id, proto_id }));
+        self.distribute_to_session();
+    }
+
     /// Processing the received data
     #[inline]
     fn protocol_message(
@@ -1031,10 +1350,175 @@ where
         if let Some(infos) = self.session_service_protos.get_mut(&session_id) {
             infos.remove(&proto_id);
         }
+        self.connecting_protocols.remove(&(session_id, proto_id));
 
         // remove handle error count
         self.handles_error_count
             .remove(&(proto_id, Some(session_id)));
+
+        self.drop_unregistered_handles(proto_id);
+    }
+
+    /// Register a protocol at runtime, and notify all sessions
+    fn register_protocol(&mut self, mut meta: ProtocolMeta, open: bool) {
+        let proto_id = meta.id();
+        let name = meta.name();
+
+        if self.protocol_configs.contains_key(&name)
+            || self
+                .protocol_configs
+                .values()
+                .any(|meta| meta.id() == proto_id)
+            // The handles of an unregistered protocol may be still alive
+            || self.unregistered_protocols.contains(&proto_id)
+        {
+            debug!("proto [{}] name [{}] has been registered", proto_id, name);
+            self.handle_error(ServiceError::ProtocolRegisterError {
+                proto_id,
+                error: Error::RepeatedProtocol(proto_id),
+            });
+            return;
+        }
+
+        debug!("register proto [{}], name: {}", proto_id, name);
+
+        if meta.session_handle().has_event() || meta.service_handle.has_event() {
+            self.config.event.insert(proto_id);
+        }
+
+        if let Ok(mut infos) = self.service_context.control().proto_infos.write() {
+            infos.insert(proto_id, ProtocolInfo::new(&name, meta.support_versions()));
+        }
+
+        for (id, session_control) in self.sessions.iter() {
+            self.write_buf.push_back((
+                *id,
+                SessionEvent::ProtocolRegister {
+                    id: *id,
+                    meta: Arc::clone(&meta.inner),
+                    // Like dial, only outbound session try open the protocol
+                    open: open && session_control.inner.ty.is_outbound(),
+                },
+            ));
+        }
+
+        self.protocol_configs.insert(name, meta);
+        self.distribute_to_session();
+    }
+
+    /// Unregister a protocol at runtime, close it on all sessions and drop its handle
+    fn unregister_protocol(&mut self, proto_id: ProtocolId) {
+        let name = match self
+            .protocol_configs
+            .iter()
+            .find(|(_, meta)| meta.id() == proto_id)
+        {
+            Some((name, _)) => name.clone(),
+            None => {
+                debug!("proto [{}] is not registered", proto_id);
+                return;
+            }
+        };
+
+        debug!("unregister proto [{}], name: {}", proto_id, name);
+
+        self.protocol_configs.remove(&name);
+        if let Ok(mut infos) = self.service_context.control().proto_infos.write() {
+            infos.remove(&proto_id);
+        }
+
+        for id in self.sessions.keys() {
+            self.write_buf
+                .push_back((*id, SessionEvent::ProtocolUnregister { id: *id, proto_id }));
+        }
+        let ids = self.pending_protocols.keys().cloned().collect::<Vec<_>>();
+        for id in ids {
+            self.open_pending_protocols(id, proto_id);
+        }
+        self.distribute_to_session();
+
+        // The handles must receive the disconnected events of the sessions
+        // which still open this protocol, drop them after all sessions close it
+        self.unregistered_protocols.insert(proto_id);
+        self.drop_unregistered_handles(proto_id);
+    }
+
+    /// Drop the handles of an unregistered protocol if no session opens it
+    fn drop_unregistered_handles(&mut self, proto_id: ProtocolId) {
+        if !self.unregistered_protocols.contains(&proto_id)
+            || self
+                .session_service_protos
+                .values()
+                .any(|protos| protos.contains(&proto_id))
+        {
+            return;
+        }
+
+        debug!("drop the handles of unregistered proto [{}]", proto_id);
+        self.unregistered_protocols.remove(&proto_id);
+        self.config.event.remove(&proto_id);
+
+        // Drop the sender after the buffered events are sent,
+        // then the service protocol stream will be closed
+        if let Some(sender) = self.service_proto_handles.remove(&proto_id) {
+            let (events, others) = self
+                .read_service_buf
+                .split_off(0)
+                .into_iter()
+                .partition::<VecDeque<_>, _>(|(id, _)| *id == proto_id);
+            self.read_service_buf = others;
+            let send_task = sender.send_all(stream::iter_ok::<_, mpsc::SendError<_>>(
+                events.into_iter().map(|(_, event)| event),
+            ));
+            tokio::spawn(send_task.map(|_| ()).map_err(|err| {
+                error!(
+                    "service unregister event send to service handle error: {:?}",
+                    err
+                );
+            }));
+        }
+        if let Some(signals) = self.service_notify_signals.remove(&proto_id) {
+            signals.into_iter().for_each(|(_, signal)| {
+                let _ = signal.send(());
+            })
+        }
+        self.handles_error_count.remove(&(proto_id, None));
+        // The pool will be closed after all handles of this protocol are closed
+        if let Some(pool) = self.handle_pools.remove(&proto_id) {
+            tokio::spawn(pool.shutdown_on_idle());
+        }
+    }
+
+    /// The protocol is open or failed to open on the session,
+    /// open the protocols waiting for it
+    fn open_pending_protocols(&mut self, id: SessionId, proto_id: ProtocolId) {
+        let ready = match self.pending_protocols.get_mut(&id) {
+            Some(pending) => {
+                pending.resolve(proto_id);
+                pending.release(&self.config.open_order)
+            }
+            None => return,
+        };
+        if self
+            .pending_protocols
+            .get(&id)
+            .map(|pending| pending.waiting.is_empty())
+            .unwrap_or_default()
+        {
+            self.pending_protocols.remove(&id);
+        }
+
+        for proto_id in ready {
+            debug!("session [{}] open proto [{}] in order", id, proto_id);
+            self.write_buf.push_back((
+                id,
+                SessionEvent::ProtocolOpen {
+                    id,
+                    proto_id,
+                    version: String::default(),
+                },
+            ));
+        }
     }
 
     #[inline(always)]
@@ -1056,14 +1540,45 @@ where
         }
     }
 
+    /// A remote peer reports the address it observed
+    fn observed_address(&mut self, session_id: SessionId, address: Multiaddr) {
+        let subnet = match self
+            .sessions
+            .get(&session_id)
+            .and_then(|session| Subnet::from_address(&session.inner.address))
+        {
+            Some(subnet) => subnet,
+            None => return,
+        };
+        if self.external_addrs.report(session_id, subnet, address) {
+            self.external_addrs_changed();
+        }
+    }
+
+    /// The confirmed external addresses changed, update listens and notify the handle
+    fn external_addrs_changed(&mut self) {
+        let addresses = self.external_addrs.confirmed().to_vec();
+        debug!("external addresses changed: {:?}", addresses);
+        self.update_listens();
+        self.handle.handle_event(
+            &mut self.service_context,
+            ServiceEvent::ExternalAddressChanged { addresses },
+        );
+    }
+
     /// When listen update, call here
     #[inline]
     fn update_listens(&mut self) {
-        let new_listens = self
+        let mut new_listens = self
             .listens
             .iter()
             .map(|(address, _)| address.clone())
             .collect::<Vec<Multiaddr>>();
+        for address in self.external_addrs.confirmed() {
+            if !new_listens.contains(address) {
+                new_listens.push(address.clone());
+            }
+        }
         self.service_context.update_listens(new_listens.clone());
 
         for proto_id in self.service_proto_handles.keys() {
@@ -1095,19 +1610,45 @@ where
             SessionEvent::HandshakeSuccess {
                 handle,
                 public_key,
+                bandwidth,
+                mut info,
                 address,
                 ty,
             } => {
-                self.session_open(handle, Some(public_key), address, ty);
+                self.service_context.control().metrics.handshake(true);
+                info.negotiated = handle.negotiated();
+                info.secure_traffic = Some(handle.traffic());
+                self.session_open(handle, Some(public_key), info, bandwidth, address, ty);
+            }
+            SessionEvent::PnetHandshakeSuccess {
+                stream,
+                bandwidth,
+                info,
+                address,
+                ty,
+            } => {
+                self.service_context.control().metrics.handshake(true);
+                self.session_open(stream, None, info, bandwidth, address, ty);
             }
             SessionEvent::HandshakeFail { ty, error, address } => {
+                self.service_context.control().metrics.handshake(false);
                 if ty.is_outbound() {
                     self.state.decrease();
                     self.dial_protocols.remove(&address);
-                    self.handle.handle_error(
-                        &mut self.service_context,
-                        ServiceError::DialerError { address, error },
-                    )
+                }
+                match error {
+                    // Rejected by the allowlist, reported on both sides
+                    Error::HandshakeError(SecioError::PeerNotAllowed(peer_id)) => self
+                        .handle_error(ServiceError::PeerNotAllowed {
+                            address,
+                            peer_id,
+                            ty,
+                        }),
+                    error => {
+                        if ty.is_outbound() {
+                            self.handle_error(ServiceError::DialerError { address, error })
+                        }
+                    }
                 }
             }
             SessionEvent::ProtocolMessage { id, proto_id, data } => {
@@ -1122,62 +1663,51 @@ where
                 self.protocol_close(id, proto_id, Source::Internal)
             }
             SessionEvent::ProtocolSelectError { id, proto_name } => {
+                if let Some(proto_id) = proto_name
+                    .as_ref()
+                    .and_then(|name| self.protocol_configs.get(name))
+                    .map(ProtocolMeta::id)
+                {
+                    self.open_pending_protocols(id, proto_id);
+                }
                 if let Some(session_control) = self.sessions.get(&id) {
-                    self.handle.handle_error(
-                        &mut self.service_context,
-                        ServiceError::ProtocolSelectError {
-                            proto_name,
-                            session_context: Arc::clone(&session_control.inner),
-                        },
-                    )
+                    self.handle_error(ServiceError::ProtocolSelectError {
+                        proto_name,
+                        session_context: Arc::clone(&session_control.inner),
+                    })
                 }
             }
             SessionEvent::ProtocolError {
                 id,
                 proto_id,
                 error,
-            } => self.handle.handle_error(
-                &mut self.service_context,
-                ServiceError::ProtocolError {
-                    id,
-                    proto_id,
-                    error,
-                },
-            ),
+            } => self.handle_error(ServiceError::ProtocolError {
+                id,
+                proto_id,
+                error,
+            }),
             SessionEvent::DialError { address, error } => {
                 self.state.decrease();
                 self.dial_protocols.remove(&address);
-                self.handle.handle_error(
-                    &mut self.service_context,
-                    ServiceError::DialerError { address, error },
-                )
+                self.handle_error(ServiceError::DialerError { address, error })
             }
             SessionEvent::ListenError { address, error } => {
                 self.state.decrease();
-                self.handle.handle_error(
-                    &mut self.service_context,
-                    ServiceError::ListenError { address, error },
-                )
+                self.handle_error(ServiceError::ListenError { address, error })
             }
             SessionEvent::SessionTimeout { id } => {
                 if let Some(session_control) = self.sessions.get(&id) {
-                    self.handle.handle_error(
-                        &mut self.service_context,
-                        ServiceError::SessionTimeout {
-                            session_context: Arc::clone(&session_control.inner),
-                        },
-                    )
+                    self.handle_error(ServiceError::SessionTimeout {
+                        session_context: Arc::clone(&session_control.inner),
+                    })
                 }
             }
             SessionEvent::MuxerError { id, error } => {
                 if let Some(session_control) = self.sessions.get(&id) {
-                    self.handle.handle_error(
-                        &mut self.service_context,
-                        ServiceError::MuxerError {
-                            session_context: Arc::clone(&session_control.inner),
-                            error,
-                        },
-                    )
+                    self.handle_error(ServiceError::MuxerError {
+                        session_context: Arc::clone(&session_control.inner),
+                        error,
+                    })
                 }
             }
             SessionEvent::ListenStart {
@@ -1199,6 +1729,10 @@ where
                 remote_address,
                 stream,
             } => self.handshake(stream, SessionType::Outbound, remote_address),
+            // Only send by service
+            SessionEvent::ProtocolRegister { .. }
+            | SessionEvent::ProtocolUnregister { .. }
+            | SessionEvent::ProtocolConnected { .. } => (),
         }
     }
 
@@ -1217,13 +1751,10 @@ where
             ServiceTask::Dial { address, target } => {
                 if !self.dial_protocols.contains_key(&address) {
                     if let Err(e) = self.dial_inner(address.clone(), target) {
-                        self.handle.handle_error(
-                            &mut self.service_context,
-                            ServiceError::DialerError {
-                                address,
-                                error: e.into(),
-                            },
-                        );
+                        self.handle_error(ServiceError::DialerError {
+                            address,
+                            error: e.into(),
+                        });
                     }
                 }
             }
@@ -1235,13 +1766,10 @@ where
                             self.listen_poll();
                         }
                         Err(e) => {
-                            self.handle.handle_error(
-                                &mut self.service_context,
-                                ServiceError::ListenError {
-                                    address,
-                                    error: e.into(),
-                                },
-                            );
+                            self.handle_error(ServiceError::ListenError {
+                                address,
+                                error: e.into(),
+                            });
                         }
                     }
                 }
@@ -1252,38 +1780,55 @@ where
             ServiceTask::FutureTask { task } => {
                 self.send_future_task(task);
             }
+            ServiceTask::ProtocolHandlePanic {
+                proto_id,
+                session_id,
+                version,
+                message,
+            } => self.handle_panic(proto_id, session_id, version, message),
+            ServiceTask::ProtocolConnected {
+                session_id,
+                proto_id,
+            } => self.handle_connected(session_id, proto_id),
+            ServiceTask::RegisterProtocol { meta, open } => self.register_protocol(meta, open),
+            ServiceTask::UnregisterProtocol { proto_id } => self.unregister_protocol(proto_id),
+            ServiceTask::ObservedAddress {
+                session_id,
+                address,
+            } => self.observed_address(session_id, address),
             ServiceTask::SetProtocolNotify {
                 proto_id,
-                interval,
+                schedule,
                 token,
             } => {
                 // TODO: if not contains should call handle_error let user know
                 if self.service_proto_handles.contains_key(&proto_id)
                     || self.config.event.contains(&proto_id)
                 {
-                    let (signal_sender, mut signal_receiver) = oneshot::channel::<()>();
-                    let interval_sender =
-                        self.service_context.control().service_task_sender.clone();
-                    let fut = Interval::new(Instant::now(), interval)
-                        .for_each(move |_| {
-                            if signal_receiver.poll() == Ok(Async::NotReady) {
-                                interval_sender
-                                    .unbounded_send(ServiceTask::ProtocolNotify { proto_id, token })
-                                    .map_err(|err| {
-                                        debug!("interval error: {:?}", err);
-                                        timer::Error::shutdown()
-                                    })
-                            } else {
-                                Err(timer::Error::shutdown())
-                            }
-                        })
-                        .map_err(|err| debug!("notify close by: {}", err));
-
-                    // If set more than once, the older task will stop when sender dropped
-                    self.service_notify_signals
-                        .entry(proto_id)
-                        .or_default()
-                        .insert(token, signal_sender);
+                    let (signal_sender, signal_receiver) = oneshot::channel::<()>();
+                    let notify_sender = self.service_context.control().service_task_sender.clone();
+                    let key = NotifyKey {
+                        session_id: None,
+                        proto_id,
+                        token,
+                    };
+                    let fut = NotifyTimer::new(
+                        schedule,
+                        key,
+                        Arc::clone(&self.service_context.control().notify_times),
+                        signal_receiver,
+                    )
+                    .for_each(move |_| {
+                        notify_sender
+                            .unbounded_send(ServiceTask::ProtocolNotify { proto_id, token })
+                            .map_err(|err| debug!("notify close by: {}", err))
+                    });
+
+                    // If set more than once, the older task will stop when sender dropped,
+                    // finished one-shot tokens are cleaned up here
+                    let signals = self.service_notify_signals.entry(proto_id).or_default();
+                    signals.retain(|_, signal| !signal.is_canceled());
+                    signals.insert(token, signal_sender);
                     self.send_future_task(Box::new(fut));
                 }
             }
@@ -1300,7 +1845,7 @@ where
             ServiceTask::SetProtocolSessionNotify {
                 session_id,
                 proto_id,
-                interval,
+                schedule,
                 token,
             } => {
                 // TODO: if not contains should call handle_error let user know
@@ -1309,35 +1854,35 @@ where
                     .contains_key(&(session_id, proto_id))
                     || self.config.event.contains(&proto_id)
                 {
-                    let (signal_sender, mut signal_receiver) = oneshot::channel::<()>();
-                    let interval_sender =
-                        self.service_context.control().service_task_sender.clone();
-                    let fut = Interval::new(Instant::now(), interval)
-                        .for_each(move |_| {
-                            if signal_receiver.poll() == Ok(Async::NotReady) {
-                                interval_sender
-                                    .unbounded_send(ServiceTask::ProtocolSessionNotify {
-                                        session_id,
-                                        proto_id,
-                                        token,
-                                    })
-                                    .map_err(|err| {
-                                        debug!("interval error: {:?}", err);
-                                        timer::Error::shutdown()
-                                    })
-                            } else {
-                                Err(timer::Error::shutdown())
-                            }
-                        })
-                        .map_err(|err| debug!("session notify close by: {}", err));
+                    let (signal_sender, signal_receiver) = oneshot::channel::<()>();
+                    let notify_sender = self.service_context.control().service_task_sender.clone();
+                    let key = NotifyKey {
+                        session_id: Some(session_id),
+                        proto_id,
+                        token,
+                    };
+                    let fut = NotifyTimer::new(
+                        schedule,
+                        key,
+                        Arc::clone(&self.service_context.control().notify_times),
+                        signal_receiver,
+                    )
+                    .for_each(move |_| {
+                        notify_sender
+                            .unbounded_send(ServiceTask::ProtocolSessionNotify {
+                                session_id,
+                                proto_id,
+                                token,
+                            })
+                            .map_err(|err| debug!("session notify close by: {}", err))
+                    });
 
-                    // If set more than once, the older task will stop when sender dropped
+                    // If set more than once, the older task will stop when sender dropped,
+                    // finished one-shot tokens are cleaned up here
                     if let Some(session) = self.sessions.get_mut(&session_id) {
-                        session
-                            .notify_signals
-                            .entry(proto_id)
-                            .or_default()
-                            .insert(token, signal_sender);
+                        let signals = session.notify_signals.entry(proto_id).or_default();
+                        signals.retain(|_, signal| !signal.is_canceled());
+                        signals.insert(token, signal_sender);
                     }
                     self.send_future_task(Box::new(fut));
                 }
@@ -1475,13 +2020,10 @@ where
                 }
                 Err(err) => {
                     update = true;
-                    self.handle.handle_error(
-                        &mut self.service_context,
-                        ServiceError::ListenError {
-                            address: address.clone(),
-                            error: err.into(),
-                        },
-                    );
+                    self.handle_error(ServiceError::ListenError {
+                        address: address.clone(),
+                        error: err.into(),
+                    });
                     self.handle.handle_event(
                         &mut self.service_context,
                         ServiceEvent::ListenClose { address },
@@ -1549,9 +2091,16 @@ where
             }
         }
 
-        self.listen_poll();
+        // The handle is not ready, stop receiving new sessions and session events,
+        // so the sessions will be back pressured. Service tasks are still processed,
+        // a slow handle can't block the commands such as shutdown.
+        if self.handle.poll_ready().is_ready() {
+            self.listen_poll();
+        }
 
-        loop {
+        // Check the handle before every event, so it buffers the outputs of one event at most.
+        // On shutdown the sessions must be closed even if nobody consumes the outputs.
+        while self.state == State::PreShutdown || self.handle.poll_ready().is_ready() {
             match self.session_event_receiver.poll() {
                 Ok(Async::Ready(Some(event))) => self.handle_session_event(event),
                 Ok(Async::Ready(None)) => unreachable!(),
@@ -1606,6 +2155,59 @@ enum Source {
     Internal,
 }
 
+/// The failure of a handshake which is limited by the timeout
+fn handshake_fail(err: timeout::Error<Error>, address: Multiaddr, ty: SessionType) -> SessionEvent {
+    let error = if err.is_timer() {
+        // tokio timer error
+        io::Error::new(io::ErrorKind::Other, err.description()).into()
+    } else if err.is_elapsed() {
+        // time out error
+        io::Error::new(io::ErrorKind::TimedOut, err.description()).into()
+    } else {
+        // dialer error
+        err.into_inner().unwrap()
+    };
+
+    debug!("Handshake with {} failed, error: {:?}", address, error);
+
+    SessionEvent::HandshakeFail { ty, error, address }
+}
+
+type SecureHandshake = Box<
+    dyn Future<Item = (StreamHandle, PublicKey, EphemeralPublicKey), Error = SecioError> + Send,
+>;
+
+/// Secio or noise handshake, inbound sessions accept both, detected from the first message of remote
+fn secure_handshake<T>(
+    socket: T,
+    ty: SessionType,
+    protocol: SecurityProtocol,
+    accept_noise: bool,
+    config: Config,
+    noise_config: NoiseConfig,
+) -> SecureHandshake
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    match (ty, protocol) {
+        (SessionType::Outbound, SecurityProtocol::Secio) => Box::new(config.handshake(socket)),
+        (SessionType::Outbound, SecurityProtocol::Noise) => {
+            Box::new(noise_config.handshake(socket, true))
+        }
+        // Noise is refused, so the algorithms of secio config can't be bypassed
+        (SessionType::Inbound, _) if !accept_noise => Box::new(config.handshake(socket)),
+        (SessionType::Inbound, _) => {
+            Box::new(noise::detect(socket).and_then(move |(is_noise, socket)| {
+                if is_noise {
+                    future::Either::A(noise_config.handshake(socket, false))
+                } else {
+                    future::Either::B(config.handshake(socket))
+                }
+            }))
+        }
+    }
+}
+
 /// Indicates the session type
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum SessionType {
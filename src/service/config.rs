@@ -1,10 +1,12 @@
 use crate::{
     builder::{CodecFn, NameFn, SelectVersionFn, SessionHandleFn},
+    secio::{handshake::Config as SecioConfig, PeerAllowlist},
     traits::{Codec, ServiceProtocol, SessionProtocol},
     yamux::config::Config as YamuxConfig,
     ProtocolId, SessionId,
 };
 use std::collections::HashSet;
+use std::fmt;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -14,6 +16,25 @@ pub(crate) struct ServiceConfig {
     pub max_frame_length: usize,
     /// event output or callback output
     pub event: HashSet<ProtocolId>,
+    pub handle_panic_policy: HandlePanicPolicy,
+    pub global_bandwidth: BandwidthLimit,
+    pub session_bandwidth: BandwidthLimit,
+    /// Protocols opened by this side on inbound sessions, none means the remote chooses
+    pub inbound_protocols: Option<DialProtocol>,
+    /// (before, after), the protocol `after` is opened only when `before` is open or failed
+    pub open_order: Vec<(ProtocolId, ProtocolId)>,
+    /// Distinct peers needed to confirm an observed external address
+    pub external_address_threshold: usize,
+    /// Secio algorithm propositions, none means the default of secio
+    pub secio_config: Option<SecioConfig>,
+    /// Handshake used by outbound sessions, inbound sessions always accept secio
+    pub security_protocol: SecurityProtocol,
+    /// Inbound sessions accept noise, it's always true if `security_protocol` is noise
+    pub accept_inbound_noise: bool,
+    /// Key of the private network, none means public
+    pub pre_shared_key: Option<PreSharedKey>,
+    /// Peers allowed to handshake, shared with `ServiceControl`
+    pub peer_allowlist: PeerAllowlist,
 }
 
 impl Default for ServiceConfig {
@@ -23,6 +44,17 @@ impl Default for ServiceConfig {
             yamux_config: YamuxConfig::default(),
             max_frame_length: 1024 * 1024 * 8,
             event: HashSet::default(),
+            handle_panic_policy: HandlePanicPolicy::Shutdown,
+            global_bandwidth: BandwidthLimit::default(),
+            session_bandwidth: BandwidthLimit::default(),
+            inbound_protocols: None,
+            open_order: Vec::new(),
+            external_address_threshold: 3,
+            secio_config: None,
+            security_protocol: SecurityProtocol::default(),
+            accept_inbound_noise: false,
+            pre_shared_key: None,
+            peer_allowlist: PeerAllowlist::default(),
         }
     }
 }
@@ -38,6 +70,76 @@ pub enum DialProtocol {
     Multi(Vec<ProtocolId>),
 }
 
+/// What to do after a protocol handle panic
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HandlePanicPolicy {
+    /// Rebuild the session level handle and call `connected` again,
+    /// the events that have not been processed by the old handle will be dropped.
+    ///
+    /// Service level handle can't be rebuilt, it just drops the event that caused the panic
+    /// and continues to run.
+    Restart,
+    /// Close the protocol of the session that caused the panic
+    CloseProtocol,
+    /// Close the whole service, default
+    Shutdown,
+}
+
+/// Bandwidth limit in bytes per second, none means unlimited, default is unlimited
+///
+/// Bytes are counted on the wire, including the overhead of secio and yamux
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct BandwidthLimit {
+    /// Upload limit
+    pub upload: Option<u64>,
+    /// Download limit
+    pub download: Option<u64>,
+}
+
+impl BandwidthLimit {
+    /// New a limit
+    pub fn new(upload: Option<u64>, download: Option<u64>) -> Self {
+        BandwidthLimit { upload, download }
+    }
+}
+
+/// Handshake of encrypted communication
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SecurityProtocol {
+    /// Secio handshake, default
+    Secio,
+    /// Noise handshake, `Noise_XX_25519_ChaChaPoly_SHA256`
+    Noise,
+}
+
+impl Default for SecurityProtocol {
+    fn default() -> Self {
+        SecurityProtocol::Secio
+    }
+}
+
+/// Pre-shared key of a private network, only the nodes with the same key can connect
+#[derive(Clone, PartialEq, Eq)]
+pub struct PreSharedKey([u8; 32]);
+
+impl PreSharedKey {
+    /// New a pre-shared key
+    pub fn new(key: [u8; 32]) -> Self {
+        PreSharedKey(key)
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for PreSharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Don't leak the key to logs
+        write!(f, "PreSharedKey(..)")
+    }
+}
+
 /// When sending a message, select the specified session
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub enum TargetSession {
@@ -120,6 +222,45 @@ pub(crate) struct Meta {
     pub(crate) support_versions: Vec<String>,
     pub(crate) codec: CodecFn,
     pub(crate) select_version: SelectVersionFn,
+    pub(crate) handle_pool: Option<HandlePoolConfig>,
+    pub(crate) inbound_limit: InboundLimit,
+    /// Protocols that must be open before this protocol is opened by this side
+    pub(crate) depends_on: Vec<ProtocolId>,
+}
+
+/// Inbound message limits of protocol, enforced by sub stream
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct InboundLimit {
+    /// Max message size in bytes
+    pub max_message_size: Option<usize>,
+    /// Messages per second and burst
+    pub message_rate: Option<(u32, u32)>,
+    /// Close sub stream on violation
+    pub close_on_violation: bool,
+}
+
+/// The dedicated thread pool config of protocol handles
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct HandlePoolConfig {
+    /// Worker thread count
+    pub threads: usize,
+    /// Channel size of each handle
+    pub queue_size: usize,
+}
+
+impl fmt::Debug for Meta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Meta {{ id: {}, name: {}, support_versions: {:?}, handle_pool: {:?}, inbound_limit: {:?}, depends_on: {:?} }}",
+            self.id,
+            (self.name)(self.id),
+            self.support_versions,
+            self.handle_pool,
+            self.inbound_limit,
+            self.depends_on
+        )
+    }
 }
 
 /// Protocol handle
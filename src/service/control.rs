@@ -1,13 +1,23 @@
 use futures::{prelude::*, sync::mpsc};
 
-use std::time::Duration;
-use std::{collections::HashMap, sync::Arc};
+use std::time::{Duration, Instant};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
 
 use crate::{
     error::Error,
+    metrics::ServiceMetrics,
     multiaddr::Multiaddr,
     protocol_select::ProtocolInfo,
-    service::{DialProtocol, ServiceTask, TargetSession},
+    secio::{PeerAllowlist, PeerId},
+    service::{
+        timer::{NotifyKey, NotifyTimes},
+        BandwidthLimit, DialProtocol, NotifySchedule, ProtocolMeta, ServiceTask, TargetSession,
+    },
+    traffic::{SessionTraffic, SessionTrafficCounter},
+    transports::limit::BandwidthControl,
     ProtocolId, SessionId,
 };
 use bytes::Bytes;
@@ -16,7 +26,12 @@ use bytes::Bytes;
 #[derive(Clone)]
 pub struct ServiceControl {
     pub(crate) service_task_sender: mpsc::UnboundedSender<ServiceTask>,
-    pub(crate) proto_infos: Arc<HashMap<ProtocolId, ProtocolInfo>>,
+    pub(crate) proto_infos: Arc<RwLock<HashMap<ProtocolId, ProtocolInfo>>>,
+    pub(crate) traffic: Arc<RwLock<HashMap<SessionId, Arc<SessionTrafficCounter>>>>,
+    pub(crate) metrics: Arc<ServiceMetrics>,
+    pub(crate) bandwidth: Arc<BandwidthControl>,
+    pub(crate) notify_times: Arc<NotifyTimes>,
+    pub(crate) peer_allowlist: PeerAllowlist,
 }
 
 impl ServiceControl {
@@ -24,10 +39,17 @@ impl ServiceControl {
     pub(crate) fn new(
         service_task_sender: mpsc::UnboundedSender<ServiceTask>,
         proto_infos: HashMap<ProtocolId, ProtocolInfo>,
+        bandwidth: BandwidthControl,
+        peer_allowlist: PeerAllowlist,
     ) -> Self {
         ServiceControl {
             service_task_sender,
-            proto_infos: Arc::new(proto_infos),
+            proto_infos: Arc::new(RwLock::new(proto_infos)),
+            traffic: Arc::new(RwLock::new(HashMap::default())),
+            metrics: Arc::new(ServiceMetrics::default()),
+            bandwidth: Arc::new(bandwidth),
+            notify_times: Arc::new(NotifyTimes::default()),
+            peer_allowlist,
         }
     }
 
@@ -39,10 +61,93 @@ impl ServiceControl {
             .map_err(Into::into)
     }
 
-    /// Get service protocol message, Map(ID, Name), but can't modify
+    /// Get a snapshot of the service protocol message, Map(ID, Name)
+    ///
+    /// Protocols can be registered or unregistered at runtime, so the result may be outdated later
+    #[inline]
+    pub fn protocols(&self) -> HashMap<ProtocolId, ProtocolInfo> {
+        self.proto_infos
+            .read()
+            .map(|infos| infos.clone())
+            .unwrap_or_default()
+    }
+
+    /// Get a snapshot of the traffic of all sessions
+    ///
+    /// Use it to find bandwidth hogs and idle peers
+    #[inline]
+    pub fn traffic(&self) -> HashMap<SessionId, SessionTraffic> {
+        self.traffic
+            .read()
+            .map(|traffic| {
+                traffic
+                    .iter()
+                    .map(|(id, counter)| (*id, counter.snapshot()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Render service metrics in the Prometheus text format
+    ///
+    /// Include open sessions, handshake results, dial errors by kind, protocol select failures,
+    /// handle queue depths and yamux stream counts
+    #[cfg(feature = "metrics")]
+    #[inline]
+    pub fn metrics(&self) -> String {
+        self.metrics.render()
+    }
+
+    /// Adjust the total bandwidth limit of all sessions, take effect immediately
     #[inline]
-    pub fn protocols(&self) -> &Arc<HashMap<ProtocolId, ProtocolInfo>> {
-        &self.proto_infos
+    pub fn set_global_bandwidth_limit(&self, limit: BandwidthLimit) {
+        self.bandwidth.global.set_limit(limit)
+    }
+
+    /// Adjust the bandwidth limit of a session, take effect immediately
+    ///
+    /// If the session does not exist, do nothing
+    #[inline]
+    pub fn set_session_bandwidth_limit(&self, session_id: SessionId, limit: BandwidthLimit) {
+        if let Some(limiter) = self
+            .bandwidth
+            .sessions
+            .read()
+            .ok()
+            .and_then(|sessions| sessions.get(&session_id).cloned())
+        {
+            limiter.set_limit(limit)
+        }
+    }
+
+    /// Adjust the default bandwidth limit of sessions,
+    /// only take effect on the sessions established later
+    #[inline]
+    pub fn set_default_session_bandwidth_limit(&self, limit: BandwidthLimit) {
+        if let Ok(mut default) = self.bandwidth.session_default.write() {
+            *default = limit;
+        }
+    }
+
+    /// Only accept the handshakes of these peers, take effect on the later handshakes
+    ///
+    /// The sessions already open are kept, disconnect them if needed
+    #[inline]
+    pub fn set_peer_allowlist<I: IntoIterator<Item = PeerId>>(&self, peers: I) {
+        self.peer_allowlist
+            .reload(Some(peers.into_iter().collect()))
+    }
+
+    /// Accept the handshakes of all peers
+    #[inline]
+    pub fn clear_peer_allowlist(&self) {
+        self.peer_allowlist.reload(None)
+    }
+
+    /// Get a snapshot of the allowlist, none means all peers are allowed
+    #[inline]
+    pub fn peer_allowlist(&self) -> Option<HashSet<PeerId>> {
+        self.peer_allowlist.peers()
     }
 
     /// Create a new listener
@@ -122,16 +227,84 @@ impl ServiceControl {
         })
     }
 
+    /// Register a new protocol at runtime
+    ///
+    /// If `open` is true, the protocol will be try open on all existing outbound sessions,
+    /// just like dial with `DialProtocol::Single`. Otherwise, it can only be opened by
+    /// `open_protocol` or by remote.
+    ///
+    /// If a protocol with the same id or name already exists, a `ProtocolRegisterError` is reported
+    #[inline]
+    pub fn register_protocol(&self, meta: ProtocolMeta, open: bool) -> Result<(), Error> {
+        self.send(ServiceTask::RegisterProtocol { meta, open })
+    }
+
+    /// Unregister a protocol at runtime
+    ///
+    /// The protocol will be closed on all sessions, and its handle will be dropped
+    #[inline]
+    pub fn unregister_protocol(&self, proto_id: ProtocolId) -> Result<(), Error> {
+        self.send(ServiceTask::UnregisterProtocol { proto_id })
+    }
+
+    /// Report the address of this node observed by a remote peer, such as the one from identify
+    ///
+    /// When enough distinct peers report the same address, it is confirmed as an external
+    /// address and included in `ServiceContext::listens`
+    #[inline]
+    pub fn add_observed_addr(
+        &self,
+        session_id: SessionId,
+        address: Multiaddr,
+    ) -> Result<(), Error> {
+        self.send(ServiceTask::ObservedAddress {
+            session_id,
+            address,
+        })
+    }
+
     /// Set a service notify token
     pub fn set_service_notify(
         &self,
         proto_id: ProtocolId,
         interval: Duration,
         token: u64,
+    ) -> Result<(), Error> {
+        self.set_service_notify_schedule(proto_id, NotifySchedule::interval(interval), token)
+    }
+
+    /// Set a service notify token that fires only once after the delay
+    pub fn set_service_delay(
+        &self,
+        proto_id: ProtocolId,
+        after: Duration,
+        token: u64,
+    ) -> Result<(), Error> {
+        self.set_service_notify_schedule(proto_id, NotifySchedule::once(after), token)
+    }
+
+    /// Set a service notify token with a schedule, such as jitter or missed tick policy
+    ///
+    /// If the token already exists, the old schedule is replaced
+    pub fn set_service_notify_schedule(
+        &self,
+        proto_id: ProtocolId,
+        schedule: NotifySchedule,
+        token: u64,
     ) -> Result<(), Error> {
         self.send(ServiceTask::SetProtocolNotify {
             proto_id,
-            interval,
+            schedule,
+            token,
+        })
+    }
+
+    /// The next fire time of a service notify token, none if it does not exist or has finished
+    #[inline]
+    pub fn service_notify_next_fire(&self, proto_id: ProtocolId, token: u64) -> Option<Instant> {
+        self.notify_times.get(&NotifyKey {
+            session_id: None,
+            proto_id,
             token,
         })
     }
@@ -148,11 +321,55 @@ impl ServiceControl {
         proto_id: ProtocolId,
         interval: Duration,
         token: u64,
+    ) -> Result<(), Error> {
+        self.set_session_notify_schedule(
+            session_id,
+            proto_id,
+            NotifySchedule::interval(interval),
+            token,
+        )
+    }
+
+    /// Set a session notify token that fires only once after the delay
+    pub fn set_session_delay(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        after: Duration,
+        token: u64,
+    ) -> Result<(), Error> {
+        self.set_session_notify_schedule(session_id, proto_id, NotifySchedule::once(after), token)
+    }
+
+    /// Set a session notify token with a schedule, such as jitter or missed tick policy
+    ///
+    /// If the token already exists, the old schedule is replaced
+    pub fn set_session_notify_schedule(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        schedule: NotifySchedule,
+        token: u64,
     ) -> Result<(), Error> {
         self.send(ServiceTask::SetProtocolSessionNotify {
             session_id,
             proto_id,
-            interval,
+            schedule,
+            token,
+        })
+    }
+
+    /// The next fire time of a session notify token, none if it does not exist or has finished
+    #[inline]
+    pub fn session_notify_next_fire(
+        &self,
+        session_id: SessionId,
+        proto_id: ProtocolId,
+        token: u64,
+    ) -> Option<Instant> {
+        self.notify_times.get(&NotifyKey {
+            session_id: Some(session_id),
+            proto_id,
             token,
         })
     }
@@ -1,13 +1,13 @@
 use futures::Future;
 use std::fmt;
 use std::sync::Arc;
-use std::time::Duration;
 
 use crate::{
     context::SessionContext,
     error::Error,
     multiaddr::Multiaddr,
-    service::{DialProtocol, TargetSession},
+    secio::PeerId,
+    service::{DialProtocol, NotifySchedule, ProtocolMeta, SessionType, TargetSession},
     ProtocolId, SessionId,
 };
 use bytes::Bytes;
@@ -66,6 +66,32 @@ pub enum ServiceError {
         /// Protocol id
         proto_id: ProtocolId,
     },
+    /// Protocol handle panic, the handle may be in an inconsistent state,
+    /// the follow-up is decided by `HandlePanicPolicy`
+    ProtocolHandlePanic {
+        /// Protocol id
+        proto_id: ProtocolId,
+        /// Session id, if the panic is caused by a session event
+        session_id: Option<SessionId>,
+        /// Panic message
+        message: String,
+    },
+    /// Register protocol at runtime fail
+    ProtocolRegisterError {
+        /// Protocol id
+        proto_id: ProtocolId,
+        /// error, such as `RepeatedProtocol`
+        error: Error,
+    },
+    /// The handshake is rejected, the remote peer is not in the allowlist
+    PeerNotAllowed {
+        /// Remote address
+        address: Multiaddr,
+        /// Remote peer id
+        peer_id: PeerId,
+        /// Session type
+        ty: SessionType,
+    },
 }
 
 /// Event generated by the Service
@@ -91,6 +117,11 @@ pub enum ServiceEvent {
         /// Listen address
         address: Multiaddr,
     },
+    /// The confirmed external addresses changed, they are also included in `ServiceContext::listens`
+    ExternalAddressChanged {
+        /// Confirmed external addresses, the most reported first
+        addresses: Vec<Multiaddr>,
+    },
 }
 
 /// Event generated by all protocol
@@ -187,8 +218,8 @@ pub(crate) enum ServiceTask {
     SetProtocolNotify {
         /// Protocol id
         proto_id: ProtocolId,
-        /// Timer interval
-        interval: Duration,
+        /// Timer schedule
+        schedule: NotifySchedule,
         /// The timer token
         token: u64,
     },
@@ -205,8 +236,8 @@ pub(crate) enum ServiceTask {
         session_id: SessionId,
         /// Protocol id
         proto_id: ProtocolId,
-        /// Timer interval
-        interval: Duration,
+        /// Timer schedule
+        schedule: NotifySchedule,
         /// The timer token
         token: u64,
     },
@@ -219,6 +250,43 @@ pub(crate) enum ServiceTask {
         /// The timer token
         token: u64,
     },
+    /// The protocol handle has run `connected`
+    ProtocolConnected {
+        /// Session id
+        session_id: SessionId,
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
+    /// Protocol handle panic
+    ProtocolHandlePanic {
+        /// Protocol id
+        proto_id: ProtocolId,
+        /// Session id
+        session_id: Option<SessionId>,
+        /// Protocol version, only session level handle has it
+        version: Option<String>,
+        /// Panic message
+        message: String,
+    },
+    /// Register a protocol at runtime
+    RegisterProtocol {
+        /// Protocol meta
+        meta: ProtocolMeta,
+        /// Try open on existing outbound sessions
+        open: bool,
+    },
+    /// Unregister a protocol at runtime
+    UnregisterProtocol {
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
+    /// A remote peer reports the address it observed
+    ObservedAddress {
+        /// The session of the peer
+        session_id: SessionId,
+        /// Observed address
+        address: Multiaddr,
+    },
     /// Future task
     FutureTask {
         /// Future
@@ -296,6 +364,17 @@ impl fmt::Debug for ServiceTask {
                 "session id: {}, protocol id: {}, token: {}",
                 session_id, proto_id, token
             ),
+            RegisterProtocol { meta, .. } => write!(
+                f,
+                "Register protocol [{}], name: {}",
+                meta.id(),
+                meta.name()
+            ),
+            UnregisterProtocol { proto_id } => write!(f, "Unregister protocol [{}]", proto_id),
+            ObservedAddress {
+                session_id,
+                address,
+            } => write!(f, "Session [{}] observed address: {}", session_id, address),
             FutureTask { .. } => write!(f, "Future task"),
             Disconnect { session_id } => write!(f, "Disconnect session [{}]", session_id),
             Dial { address, .. } => write!(f, "Dial address: {}", address),
@@ -308,6 +387,10 @@ impl fmt::Debug for ServiceTask {
                 session_id,
                 proto_id,
             } => write!(f, "Close session [{}] proto [{}]", session_id, proto_id),
+            ProtocolConnected {
+                session_id,
+                proto_id,
+            } => write!(f, "Session [{}] proto [{}] connected", session_id, proto_id),
             Shutdown(_) => write!(f, "Try close service"),
         }
     }
@@ -0,0 +1,112 @@
+use futures::{prelude::*, sync::mpsc};
+use log::debug;
+use std::collections::VecDeque;
+
+use crate::{
+    context::ServiceContext,
+    service::{ProtocolEvent, ServiceError, ServiceEvent},
+    traits::ServiceHandle,
+};
+
+/// The output of a service built without handle
+#[derive(Debug)]
+pub enum ServiceOutput {
+    /// Session establishment and disconnection events
+    Event(ServiceEvent),
+    /// Runtime errors
+    Error(ServiceError),
+    /// Events of the protocols which have event handle
+    Protocol(ProtocolEvent),
+}
+
+/// The sender side of a bounded output channel, buffers the outputs when the channel is full
+///
+/// It's not ready until the buffer is empty, the service takes a new session event only
+/// when the handle is ready, so the buffer holds the outputs of one event, and the errors
+/// and events of the service tasks processed while the channel is full.
+pub(crate) struct OutputSender<T> {
+    sender: mpsc::Sender<T>,
+    pending: VecDeque<T>,
+}
+
+impl<T> OutputSender<T> {
+    pub(crate) fn new(capacity: usize) -> (Self, mpsc::Receiver<T>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        (
+            OutputSender {
+                sender,
+                pending: VecDeque::default(),
+            },
+            receiver,
+        )
+    }
+
+    #[inline]
+    pub(crate) fn push(&mut self, output: T) {
+        self.pending.push_back(output);
+        self.flush();
+    }
+
+    /// Send the pending outputs to channel
+    pub(crate) fn flush(&mut self) -> Async<()> {
+        while let Some(output) = self.pending.pop_front() {
+            match self.sender.start_send(output) {
+                Ok(AsyncSink::Ready) => (),
+                Ok(AsyncSink::NotReady(output)) => {
+                    self.pending.push_front(output);
+                    return Async::NotReady;
+                }
+                Err(err) => {
+                    debug!("event stream has been dropped: {}", err);
+                    self.pending.clear();
+                }
+            }
+        }
+        Async::Ready(())
+    }
+}
+
+/// A service handle that forwards everything to a bounded channel,
+/// created by [build_with_stream](../builder/struct.ServiceBuilder.html#method.build_with_stream)
+pub struct EventHandle {
+    sender: OutputSender<ServiceOutput>,
+}
+
+impl EventHandle {
+    pub(crate) fn new(capacity: usize) -> (Self, EventStream) {
+        let (sender, receiver) = OutputSender::new(capacity);
+        (EventHandle { sender }, EventStream { inner: receiver })
+    }
+}
+
+impl ServiceHandle for EventHandle {
+    fn handle_error(&mut self, _control: &mut ServiceContext, error: ServiceError) {
+        self.sender.push(ServiceOutput::Error(error))
+    }
+
+    fn handle_event(&mut self, _control: &mut ServiceContext, event: ServiceEvent) {
+        self.sender.push(ServiceOutput::Event(event))
+    }
+
+    fn handle_proto(&mut self, _control: &mut ServiceContext, event: ProtocolEvent) {
+        self.sender.push(ServiceOutput::Protocol(event))
+    }
+
+    fn poll_ready(&mut self) -> Async<()> {
+        self.sender.flush()
+    }
+}
+
+/// The typed stream of service output, the receiver side of `EventHandle`
+pub struct EventStream {
+    inner: mpsc::Receiver<ServiceOutput>,
+}
+
+impl Stream for EventStream {
+    type Item = ServiceOutput;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.inner.poll()
+    }
+}
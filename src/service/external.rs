@@ -0,0 +1,219 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use crate::{
+    multiaddr::{Multiaddr, Protocol},
+    SessionId,
+};
+
+/// Max votes remembered, the oldest vote is dropped when full
+const MAX_VOTES: usize = 1024;
+
+/// The subnet of a reporter, IPv4 /24 or IPv6 /64
+///
+/// Votes are counted by subnet rather than peer id, so a single host can't
+/// confirm an address alone by reconnecting with fresh peer ids.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Subnet(IpAddr);
+
+impl Subnet {
+    /// The subnet of the remote address of a session
+    pub(crate) fn from_address(address: &Multiaddr) -> Option<Self> {
+        address.iter().find_map(|proto| match proto {
+            Protocol::Ip4(ip) => {
+                let [a, b, c, _] = ip.octets();
+                Some(Subnet(IpAddr::V4(Ipv4Addr::new(a, b, c, 0))))
+            }
+            Protocol::Ip6(ip) => {
+                let segments = ip.segments();
+                Some(Subnet(IpAddr::V6(Ipv6Addr::new(
+                    segments[0],
+                    segments[1],
+                    segments[2],
+                    segments[3],
+                    0,
+                    0,
+                    0,
+                    0,
+                ))))
+            }
+            _ => None,
+        })
+    }
+}
+
+/// Confirm the external addresses observed by remote peers
+///
+/// Each session votes for the latest address its remote observed, an address is confirmed
+/// when the votes from distinct subnets reach the threshold. The vote of a session is
+/// dropped when it is closed.
+pub(crate) struct ExternalAddrs {
+    threshold: usize,
+    votes: HashMap<SessionId, (Subnet, Multiaddr)>,
+    /// Sessions in the order of their first vote
+    voters: VecDeque<SessionId>,
+    /// Sorted by votes, the most voted first
+    confirmed: Vec<Multiaddr>,
+}
+
+impl ExternalAddrs {
+    pub(crate) fn new(threshold: usize) -> Self {
+        ExternalAddrs {
+            threshold: ::std::cmp::max(threshold, 1),
+            votes: HashMap::default(),
+            voters: VecDeque::new(),
+            confirmed: Vec::new(),
+        }
+    }
+
+    /// Confirmed external addresses
+    #[inline]
+    pub(crate) fn confirmed(&self) -> &[Multiaddr] {
+        &self.confirmed
+    }
+
+    /// Record an observed address, return true if the confirmed addresses changed
+    pub(crate) fn report(
+        &mut self,
+        session_id: SessionId,
+        subnet: Subnet,
+        mut address: Multiaddr,
+    ) -> bool {
+        // Peer id is not part of the external address
+        if let Some(Protocol::P2p(_)) = address.iter().last() {
+            address.pop();
+        }
+
+        match self.votes.get(&session_id) {
+            Some((_, addr)) if addr == &address => return false,
+            Some(_) => (),
+            None => {
+                if self.voters.len() >= MAX_VOTES {
+                    if let Some(oldest) = self.voters.pop_front() {
+                        self.votes.remove(&oldest);
+                    }
+                }
+                self.voters.push_back(session_id);
+            }
+        }
+        self.votes.insert(session_id, (subnet, address));
+        self.update()
+    }
+
+    /// Drop the vote of a closed session, return true if the confirmed addresses changed
+    pub(crate) fn remove(&mut self, session_id: SessionId) -> bool {
+        if self.votes.remove(&session_id).is_some() {
+            self.voters.retain(|id| id != &session_id);
+            self.update()
+        } else {
+            false
+        }
+    }
+
+    fn update(&mut self) -> bool {
+        let mut subnets: HashMap<&Multiaddr, HashSet<&Subnet>> = HashMap::default();
+        for (subnet, address) in self.votes.values() {
+            subnets.entry(address).or_default().insert(subnet);
+        }
+
+        let mut confirmed = subnets
+            .into_iter()
+            .map(|(address, subnets)| (address, subnets.len()))
+            .filter(|(_, count)| *count >= self.threshold)
+            .collect::<Vec<_>>();
+        confirmed.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.to_vec().cmp(&b.0.to_vec())));
+        let confirmed = confirmed
+            .into_iter()
+            .map(|(address, _)| address.clone())
+            .collect::<Vec<_>>();
+
+        let changed = confirmed.iter().collect::<HashSet<_>>()
+            != self.confirmed.iter().collect::<HashSet<_>>();
+        self.confirmed = confirmed;
+        changed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ExternalAddrs, Subnet, MAX_VOTES};
+    use crate::{multiaddr::Multiaddr, SessionId};
+
+    fn subnet(address: &str) -> Subnet {
+        Subnet::from_address(&address.parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_confirm_external_address() {
+        let mut external = ExternalAddrs::new(2);
+        let addr_1: Multiaddr = "/ip4/1.1.1.1/tcp/1337".parse().unwrap();
+        let addr_2: Multiaddr = "/ip4/2.2.2.2/tcp/1337".parse().unwrap();
+
+        assert!(!external.report(SessionId::new(1), subnet("/ip4/10.0.1.1"), addr_1.clone()));
+        // The same session reports again, not count
+        assert!(!external.report(SessionId::new(1), subnet("/ip4/10.0.1.1"), addr_1.clone()));
+        assert!(external.confirmed().is_empty());
+
+        assert!(external.report(SessionId::new(2), subnet("/ip4/10.0.2.1"), addr_1.clone()));
+        assert_eq!(external.confirmed(), &[addr_1.clone()]);
+
+        // A session changes its vote, the address is no longer confirmed
+        assert!(external.report(SessionId::new(2), subnet("/ip4/10.0.2.1"), addr_2.clone()));
+        assert!(external.confirmed().is_empty());
+
+        assert!(external.report(SessionId::new(1), subnet("/ip4/10.0.1.1"), addr_2.clone()));
+        assert_eq!(external.confirmed(), &[addr_2]);
+
+        // The reporter is gone, its vote is dropped
+        assert!(external.remove(SessionId::new(1)));
+        assert!(external.confirmed().is_empty());
+    }
+
+    #[test]
+    fn test_votes_from_same_subnet() {
+        let mut external = ExternalAddrs::new(2);
+        let addr: Multiaddr = "/ip4/1.1.1.1/tcp/1337".parse().unwrap();
+
+        // One host reconnects with fresh peer ids, it still has only one vote
+        assert!(!external.report(SessionId::new(1), subnet("/ip4/10.0.1.1"), addr.clone()));
+        assert!(!external.report(SessionId::new(2), subnet("/ip4/10.0.1.2"), addr.clone()));
+        assert!(!external.report(
+            SessionId::new(3),
+            subnet("/ip6/2001:db8::1"),
+            "/ip4/2.2.2.2/tcp/1337".parse().unwrap()
+        ));
+        assert!(external.confirmed().is_empty());
+
+        assert!(external.report(
+            SessionId::new(4),
+            subnet("/ip6/2001:db8:1::1"),
+            addr.clone()
+        ));
+        assert_eq!(external.confirmed(), &[addr]);
+    }
+
+    #[test]
+    fn test_drop_oldest_vote() {
+        let mut external = ExternalAddrs::new(2);
+        let addr: Multiaddr = "/ip4/1.1.1.1/tcp/1337".parse().unwrap();
+
+        assert!(!external.report(SessionId::new(0), subnet("/ip4/10.0.0.1"), addr.clone()));
+        for id in 1..MAX_VOTES {
+            let other: Multiaddr = format!("/ip4/2.2.2.2/tcp/{}", id).parse().unwrap();
+            external.report(SessionId::new(id), subnet("/ip4/10.0.1.1"), other);
+        }
+        // Session 0 has the oldest vote, it's dropped for the new one
+        assert!(!external.report(
+            SessionId::new(MAX_VOTES),
+            subnet("/ip4/10.0.2.1"),
+            addr.clone()
+        ));
+        assert!(external.confirmed().is_empty());
+
+        // Session 0 votes again, the vote of session 1 is dropped this time
+        assert!(external.report(SessionId::new(0), subnet("/ip4/10.0.0.1"), addr.clone()));
+        assert_eq!(external.confirmed(), &[addr]);
+    }
+}
@@ -0,0 +1,339 @@
+use futures::{prelude::*, sync::oneshot};
+use log::debug;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::timer::Delay;
+
+use crate::{ProtocolId, SessionId};
+
+/// What to do with the ticks missed when the runtime stalls longer than the interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissedTickPolicy {
+    /// Drop the missed ticks, fire once and continue on the original schedule
+    Skip,
+    /// Fire all missed ticks immediately, one after another
+    CatchUp,
+}
+
+impl Default for MissedTickPolicy {
+    fn default() -> Self {
+        MissedTickPolicy::CatchUp
+    }
+}
+
+/// Schedule of a notify token
+///
+/// ```rust
+/// use std::time::Duration;
+/// use tentacle::service::{MissedTickPolicy, NotifySchedule};
+///
+/// // Fire every 24 hours, each fire is delayed randomly by up to 1 hour
+/// let announce = NotifySchedule::interval(Duration::from_secs(24 * 60 * 60))
+///     .first_after(Duration::from_secs(60))
+///     .jitter(Duration::from_secs(60 * 60))
+///     .missed_tick_policy(MissedTickPolicy::Skip);
+///
+/// // Fire only once, after 30 seconds
+/// let timeout = NotifySchedule::once(Duration::from_secs(30));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotifySchedule {
+    first: Duration,
+    interval: Option<Duration>,
+    jitter: Duration,
+    missed_tick: MissedTickPolicy,
+}
+
+impl NotifySchedule {
+    /// Fire repeatedly with a fixed interval, the first fire is immediate
+    pub fn interval(interval: Duration) -> Self {
+        NotifySchedule {
+            first: Duration::default(),
+            interval: Some(interval),
+            jitter: Duration::default(),
+            missed_tick: MissedTickPolicy::default(),
+        }
+    }
+
+    /// Fire only once after the delay
+    pub fn once(after: Duration) -> Self {
+        NotifySchedule {
+            first: after,
+            interval: None,
+            jitter: Duration::default(),
+            missed_tick: MissedTickPolicy::default(),
+        }
+    }
+
+    /// Delay of the first fire
+    pub fn first_after(mut self, delay: Duration) -> Self {
+        self.first = delay;
+        self
+    }
+
+    /// Delay each fire by a random duration in `[0, max]`
+    ///
+    /// Jitter does not accumulate, the following fires stay on the original schedule
+    pub fn jitter(mut self, max: Duration) -> Self {
+        self.jitter = max;
+        self
+    }
+
+    /// How to handle missed ticks, default is `CatchUp`
+    pub fn missed_tick_policy(mut self, policy: MissedTickPolicy) -> Self {
+        self.missed_tick = policy;
+        self
+    }
+
+    /// Whether the token only fires once
+    pub fn is_once(&self) -> bool {
+        self.interval.is_none()
+    }
+}
+
+/// Owner and token of a notify timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct NotifyKey {
+    pub(crate) session_id: Option<SessionId>,
+    pub(crate) proto_id: ProtocolId,
+    pub(crate) token: u64,
+}
+
+/// The next fire time of all timers, shared by timers and service control
+#[derive(Debug, Default)]
+pub(crate) struct NotifyTimes {
+    /// The timer id is used to avoid an old timer removing the entry of the new one with same token
+    inner: RwLock<HashMap<NotifyKey, (u64, Instant)>>,
+    next_id: AtomicU64,
+}
+
+impl NotifyTimes {
+    #[inline]
+    pub(crate) fn get(&self, key: &NotifyKey) -> Option<Instant> {
+        self.inner
+            .read()
+            .ok()
+            .and_then(|times| times.get(key).map(|(_, at)| *at))
+    }
+
+    #[inline]
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    #[inline]
+    fn set(&self, key: NotifyKey, id: u64, at: Instant) {
+        if let Ok(mut times) = self.inner.write() {
+            times.insert(key, (id, at));
+        }
+    }
+
+    #[inline]
+    fn remove(&self, key: &NotifyKey, id: u64) {
+        if let Ok(mut times) = self.inner.write() {
+            if times.get(key).map(|(old, _)| *old == id).unwrap_or(false) {
+                times.remove(key);
+            }
+        }
+    }
+}
+
+/// Timer stream of a notify token, end when the signal sender is dropped or fired,
+/// or after a one-shot fire
+pub(crate) struct NotifyTimer {
+    schedule: NotifySchedule,
+    key: NotifyKey,
+    id: u64,
+    times: Arc<NotifyTimes>,
+    signal: oneshot::Receiver<()>,
+    /// Scheduled time of the next fire, without jitter
+    next: Instant,
+    delay: Delay,
+    rng: u64,
+    finished: bool,
+}
+
+impl NotifyTimer {
+    pub(crate) fn new(
+        schedule: NotifySchedule,
+        key: NotifyKey,
+        times: Arc<NotifyTimes>,
+        signal: oneshot::Receiver<()>,
+    ) -> Self {
+        let id = times.next_id();
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| u64::from(now.subsec_nanos()) ^ now.as_secs())
+            .unwrap_or_default();
+        let next = Instant::now() + schedule.first;
+        let mut timer = NotifyTimer {
+            schedule,
+            key,
+            id,
+            times,
+            signal,
+            next,
+            delay: Delay::new(next),
+            // xorshift must not be seeded with 0
+            rng: (seed ^ id.rotate_left(32)) | 1,
+            finished: false,
+        };
+        timer.reset_delay();
+        timer
+    }
+
+    /// Reset the delay to the next fire time with jitter, and publish it
+    fn reset_delay(&mut self) {
+        let at = self.next + self.random_jitter();
+        self.delay.reset(at);
+        self.times.set(self.key, self.id, at);
+    }
+
+    fn random_jitter(&mut self) -> Duration {
+        let max = self.schedule.jitter.as_nanos() as u64;
+        if max == 0 {
+            return Duration::default();
+        }
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        Duration::from_nanos(self.rng % (max + 1))
+    }
+
+    /// Schedule the next fire after a fire, return false if no more fire
+    fn advance(&mut self) -> bool {
+        let interval = match self.schedule.interval {
+            Some(interval) if interval > Duration::default() => interval,
+            Some(_) => Duration::from_millis(1),
+            None => return false,
+        };
+        self.next += interval;
+
+        let now = Instant::now();
+        if self.schedule.missed_tick == MissedTickPolicy::Skip && self.next <= now {
+            let missed = (now - self.next).as_nanos() / interval.as_nanos() + 1;
+            debug!("notify timer {:?} skip {} missed ticks", self.key, missed);
+            self.next += interval * missed.min(u128::from(u32::max_value())) as u32;
+        }
+        true
+    }
+}
+
+impl Stream for NotifyTimer {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.finished {
+            return Ok(Async::Ready(None));
+        }
+
+        // Stop immediately when removed, or when the owner is gone
+        if self.signal.poll() != Ok(Async::NotReady) {
+            self.finished = true;
+            return Ok(Async::Ready(None));
+        }
+
+        match self.delay.poll() {
+            Ok(Async::Ready(_)) => {
+                if self.advance() {
+                    self.reset_delay();
+                } else {
+                    self.finished = true;
+                    self.times.remove(&self.key, self.id);
+                }
+                Ok(Async::Ready(Some(())))
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(err) => {
+                debug!("notify timer {:?} error: {}", self.key, err);
+                Err(())
+            }
+        }
+    }
+}
+
+impl Drop for NotifyTimer {
+    fn drop(&mut self) {
+        self.times.remove(&self.key, self.id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MissedTickPolicy, NotifyKey, NotifySchedule, NotifyTimer, NotifyTimes};
+    use futures::sync::oneshot;
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    fn key() -> NotifyKey {
+        NotifyKey {
+            session_id: None,
+            proto_id: 1.into(),
+            token: 1,
+        }
+    }
+
+    #[test]
+    fn test_skip_missed_ticks() {
+        let times = Arc::new(NotifyTimes::default());
+        let (_sender, receiver) = oneshot::channel();
+        let interval = Duration::from_millis(10);
+        let schedule =
+            NotifySchedule::interval(interval).missed_tick_policy(MissedTickPolicy::Skip);
+        let mut timer = NotifyTimer::new(schedule, key(), Arc::clone(&times), receiver);
+        let start = timer.next;
+
+        // Pretend the runtime stalled for 10 intervals
+        std::thread::sleep(interval * 10);
+        assert!(timer.advance());
+        assert!(timer.next > Instant::now());
+        assert!(timer.next - start >= interval * 10);
+    }
+
+    #[test]
+    fn test_catch_up_missed_ticks() {
+        let times = Arc::new(NotifyTimes::default());
+        let (_sender, receiver) = oneshot::channel();
+        let interval = Duration::from_millis(10);
+        let mut timer = NotifyTimer::new(
+            NotifySchedule::interval(interval),
+            key(),
+            Arc::clone(&times),
+            receiver,
+        );
+        let start = timer.next;
+
+        std::thread::sleep(interval * 10);
+        assert!(timer.advance());
+        assert_eq!(timer.next - start, interval);
+    }
+
+    #[test]
+    fn test_jitter_and_next_fire() {
+        let times = Arc::new(NotifyTimes::default());
+        let (_sender, receiver) = oneshot::channel();
+        let jitter = Duration::from_secs(5);
+        let schedule = NotifySchedule::once(Duration::from_secs(10)).jitter(jitter);
+        let mut timer = NotifyTimer::new(schedule, key(), Arc::clone(&times), receiver);
+
+        for _ in 0..100 {
+            assert!(timer.random_jitter() <= jitter);
+        }
+
+        let at = times.get(&key()).unwrap();
+        assert!(at >= timer.next && at <= timer.next + jitter);
+        assert!(!timer.advance());
+
+        drop(timer);
+        assert!(times.get(&key()).is_none());
+    }
+}
@@ -14,18 +14,34 @@ use tokio::{
 
 use crate::{
     error::Error,
+    metrics::ServiceMetrics,
     multiaddr::Multiaddr,
     protocol_select::{client_select, server_select, ProtocolInfo},
-    secio::{codec::stream_handle::StreamHandle as SecureHandle, PublicKey},
+    secio::{
+        codec::{secure_stream::SecureTraffic, stream_handle::StreamHandle as SecureHandle},
+        Negotiated, PublicKey,
+    },
     service::{
         config::Meta, SessionType, BUF_SHRINK_THRESHOLD, DELAY_TIME, RECEIVED_SIZE, SEND_SIZE,
     },
     substream::{ProtocolEvent, SubStream},
-    transports::{MultiIncoming, MultiStream},
+    traffic::SessionTrafficCounter,
+    transports::limit::{BandwidthLimiter, LimitedStream},
+    transports::pnet::PnetStream,
+    transports::{MultiIncoming, MultiStream, TransportKind},
     yamux::{Config, Session as YamuxSession, StreamHandle},
     ProtocolId, SessionId, StreamId,
 };
 
+/// Details of the underlying connection, collected before the session opens
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionInfo {
+    pub(crate) local_address: Option<Multiaddr>,
+    pub(crate) transport: TransportKind,
+    pub(crate) negotiated: Option<Negotiated>,
+    pub(crate) secure_traffic: Option<Arc<SecureTraffic>>,
+}
+
 /// Event generated/received by the Session
 #[derive(Debug)]
 pub(crate) enum SessionEvent {
@@ -47,6 +63,23 @@ pub(crate) enum SessionEvent {
         handle: SecureHandle,
         /// Remote Public key
         public_key: PublicKey,
+        /// Bandwidth limiter of this session
+        bandwidth: Arc<BandwidthLimiter>,
+        /// Details of the raw connection
+        info: ConnectionInfo,
+        /// Remote address
+        address: Multiaddr,
+        /// Session type
+        ty: SessionType,
+    },
+    /// Without secio, the connection is only encrypted by the pre-shared key
+    PnetHandshakeSuccess {
+        /// Stream encrypted by the pre-shared key
+        stream: PnetStream<LimitedStream<MultiStream>>,
+        /// Bandwidth limiter of this session
+        bandwidth: Arc<BandwidthLimiter>,
+        /// Details of the raw connection
+        info: ConnectionInfo,
         /// Remote address
         address: Multiaddr,
         /// Session type
@@ -97,6 +130,13 @@ pub(crate) enum SessionEvent {
         /// Protocol id
         proto_id: ProtocolId,
     },
+    /// The handles of the protocol have run `connected`, only send by service
+    ProtocolConnected {
+        /// Session id
+        id: SessionId,
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
     ProtocolSelectError {
         /// Session id
         id: SessionId,
@@ -120,6 +160,22 @@ pub(crate) enum SessionEvent {
         id: SessionId,
         error: Error,
     },
+    /// Protocol registered at runtime
+    ProtocolRegister {
+        /// Session id
+        id: SessionId,
+        /// Protocol meta
+        meta: Arc<Meta>,
+        /// Try open it
+        open: bool,
+    },
+    /// Protocol unregistered at runtime
+    ProtocolUnregister {
+        /// Session id
+        id: SessionId,
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
 }
 
 /// Wrapper for real data streams, such as TCP stream
@@ -144,6 +200,12 @@ pub(crate) struct Session<T> {
     /// Sub streams maps a stream id to a sender of sub stream
     sub_streams: HashMap<StreamId, mpsc::Sender<ProtocolEvent>>,
     proto_streams: HashMap<ProtocolId, StreamId>,
+    /// The sub streams opened by the dialer of this session
+    dialer_streams: HashSet<StreamId>,
+    /// Protocols whose handles have run `connected`
+    connected_protos: HashSet<ProtocolId>,
+    /// Protocols waiting for their dependencies to be connected
+    pending_protos: Vec<String>,
     /// The buffer which will distribute to sub streams
     write_buf: VecDeque<(ProtocolId, ProtocolEvent)>,
     /// The buffer which will send to service
@@ -160,6 +222,12 @@ pub(crate) struct Session<T> {
     service_receiver: mpsc::Receiver<SessionEvent>,
     /// Delay notify with abnormally poor machines
     delay: Option<Delay>,
+    /// Traffic of this session
+    traffic: Arc<SessionTrafficCounter>,
+    /// Service metrics
+    metrics: Arc<ServiceMetrics>,
+    /// Yamux stream count on last poll
+    stream_count: usize,
 }
 
 impl<T> Session<T>
@@ -185,6 +253,9 @@ where
             next_stream: 0,
             sub_streams: HashMap::default(),
             proto_streams: HashMap::default(),
+            dialer_streams: HashSet::default(),
+            connected_protos: HashSet::default(),
+            pending_protos: Vec::new(),
             write_buf: VecDeque::default(),
             read_buf: VecDeque::default(),
             proto_event_sender,
@@ -193,6 +264,9 @@ where
             service_receiver,
             delay: None,
             state: SessionState::Normal,
+            traffic: meta.traffic,
+            metrics: meta.metrics,
+            stream_count: 0,
         }
     }
 
@@ -251,16 +325,132 @@ where
     }
 
     /// After the session is established, the client is requested to open some custom protocol sub stream.
+    ///
+    /// If the protocol depends on other protocols, it will be opened after the handles of
+    /// all of them have run `connected`.
     pub fn open_proto_stream(&mut self, proto_name: &str) {
+        let versions = match self.protocol_configs.get(proto_name) {
+            Some(meta) => {
+                if meta
+                    .depends_on
+                    .iter()
+                    .any(|id| !self.connected_protos.contains(id))
+                {
+                    debug!("proto {} waiting for dependencies", proto_name);
+                    if !self.pending_protos.iter().any(|name| name == proto_name) {
+                        self.pending_protos.push(proto_name.to_owned());
+                    }
+                    return;
+                }
+                meta.support_versions.clone()
+            }
+            None => {
+                debug!("This protocol [{}] is not supported", proto_name);
+                return;
+            }
+        };
         debug!("try open proto, {}", proto_name);
         let handle = self.socket.open_stream().unwrap();
-        let versions = self.protocol_configs[proto_name].support_versions.clone();
         let proto_info = ProtocolInfo::new(&proto_name, versions);
 
         let task = client_select(handle, proto_info);
         self.select_procedure(task);
     }
 
+    /// A protocol is connected, try open the protocols waiting for it
+    fn open_pending_protos(&mut self) {
+        if self.state != SessionState::Normal {
+            return;
+        }
+        for name in ::std::mem::replace(&mut self.pending_protos, Vec::new()) {
+            let opened = self
+                .protocol_configs
+                .get(&name)
+                .map(|meta| self.proto_streams.contains_key(&meta.id));
+            // Skip the protocols opened by remote or unregistered
+            if opened == Some(false) {
+                self.open_proto_stream(&name);
+            }
+        }
+    }
+
+    /// A protocol failed to open, drop the protocols waiting for it and report them
+    fn drop_dependent_protos(&mut self, proto_name: &str) {
+        let mut failed = match self.protocol_configs.get(proto_name) {
+            Some(meta) => vec![meta.id],
+            None => return,
+        };
+        while let Some(proto_id) = failed.pop() {
+            let protocol_configs = &self.protocol_configs;
+            let (dropped, pending) = self
+                .pending_protos
+                .split_off(0)
+                .into_iter()
+                .partition::<Vec<_>, _>(|name| {
+                    protocol_configs
+                        .get(name)
+                        .map(|meta| meta.depends_on.contains(&proto_id))
+                        .unwrap_or_default()
+                });
+            self.pending_protos = pending;
+            for name in dropped {
+                debug!(
+                    "session [{}] proto [{}] dropped because proto [{}] failed to open",
+                    self.id, name, proto_id
+                );
+                failed.extend(protocol_configs.get(&name).map(|meta| meta.id));
+                self.read_buf.push_back(SessionEvent::ProtocolSelectError {
+                    id: self.id,
+                    proto_name: Some(name),
+                });
+            }
+        }
+        self.output();
+    }
+
+    /// Stop sending on the replaced sub stream, the data sent before
+    /// will be flushed first
+    fn replace_sub_stream(&mut self, stream_id: StreamId, proto_id: ProtocolId) {
+        if let Some(sender) = self.sub_streams.get(&stream_id) {
+            let send_task = sender.clone().send(ProtocolEvent::Replaced {
+                id: stream_id,
+                proto_id,
+            });
+            tokio::spawn(send_task.map(|_| ()).map_err(|err| {
+                debug!("stream replaced event send error: {:?}", err);
+            }));
+        }
+    }
+
+    /// A protocol is closed, close the protocols depend on it
+    fn close_dependent_protos(&mut self, proto_id: ProtocolId) {
+        let dependents = self
+            .protocol_configs
+            .values()
+            .filter(|meta| meta.depends_on.contains(&proto_id))
+            .filter_map(|meta| {
+                self.proto_streams
+                    .get(&meta.id)
+                    .map(|stream_id| (meta.id, *stream_id))
+            })
+            .collect::<Vec<_>>();
+
+        for (dependent, stream_id) in dependents {
+            debug!(
+                "session [{}] proto [{}] closed because proto [{}] closed",
+                self.id, dependent, proto_id
+            );
+            self.write_buf.push_back((
+                dependent,
+                ProtocolEvent::Close {
+                    id: stream_id,
+                    proto_id: dependent,
+                },
+            ));
+        }
+        self.distribute_to_substream();
+    }
+
     /// Push the generated event to the Service
     #[inline]
     fn event_output(&mut self, event: SessionEvent) {
@@ -340,10 +530,39 @@ where
             } => {
                 let proto = match self.protocol_configs.get(&proto_name) {
                     Some(proto) => proto,
-                    None => unreachable!(),
+                    None => {
+                        // The protocol has been unregistered during the select procedure,
+                        // drop the sub stream will close it
+                        debug!("proto [{}] has been unregistered", proto_name);
+                        return;
+                    }
                 };
 
                 let proto_id = proto.id;
+                // The yamux client is the dialer, it opens the streams with odd id
+                let opened_by_dialer = sub_stream.get_ref().id() % 2 == 1;
+                // Both sides open the protocol at the same time, both of them send on
+                // the sub stream opened by the dialer. The other one only receives the data
+                // already sent on it, and is closed after both sides close their sending side
+                let replaced = match self.proto_streams.get(&proto_id) {
+                    Some(stream_id)
+                        if !opened_by_dialer || self.dialer_streams.contains(stream_id) =>
+                    {
+                        debug!(
+                            "session [{}] proto [{}] has been opened, only receive on the new sub stream",
+                            self.id, proto_id
+                        );
+                        Some(self.next_stream)
+                    }
+                    Some(stream_id) => {
+                        debug!(
+                            "session [{}] proto [{}] is replaced by the sub stream of dialer",
+                            self.id, proto_id
+                        );
+                        Some(*stream_id)
+                    }
+                    None => None,
+                };
                 let raw_part = sub_stream.into_parts();
                 let mut part = FramedParts::new(raw_part.io, (proto.codec)());
                 // Replace buffered data
@@ -357,16 +576,32 @@ where
                     session_to_proto_receiver,
                     self.next_stream,
                     proto_id,
+                    self.traffic.protocol(proto_id),
+                    proto.inbound_limit,
                 );
                 self.sub_streams
                     .insert(self.next_stream, session_to_proto_sender);
-                self.proto_streams.insert(proto_id, self.next_stream);
+                if opened_by_dialer {
+                    self.dialer_streams.insert(self.next_stream);
+                }
 
-                self.event_output(SessionEvent::ProtocolOpen {
-                    id: self.id,
-                    proto_id,
-                    version,
-                });
+                match replaced {
+                    Some(stream_id) => {
+                        if stream_id != self.next_stream {
+                            self.proto_streams.insert(proto_id, self.next_stream);
+                        }
+                        // The protocol is already open on service, only the sub stream changed
+                        self.replace_sub_stream(stream_id, proto_id);
+                    }
+                    None => {
+                        self.proto_streams.insert(proto_id, self.next_stream);
+                        self.event_output(SessionEvent::ProtocolOpen {
+                            id: self.id,
+                            proto_id,
+                            version,
+                        });
+                    }
+                }
                 self.next_stream += 1;
 
                 debug!("session [{}] proto [{}] open", self.id, proto_id);
@@ -376,25 +611,38 @@ where
             ProtocolEvent::Close { id, proto_id } => {
                 debug!("session [{}] proto [{}] closed", self.id, proto_id);
                 self.sub_streams.remove(&id);
+                self.dialer_streams.remove(&id);
+                if self.proto_streams.get(&proto_id) != Some(&id) {
+                    // The sub stream has been replaced
+                    return;
+                }
                 self.proto_streams.remove(&proto_id);
+                self.connected_protos.remove(&proto_id);
                 self.event_output(SessionEvent::ProtocolClose {
                     id: self.id,
                     proto_id,
                 });
+                self.close_dependent_protos(proto_id);
             }
             ProtocolEvent::Message { data, proto_id, .. } => {
                 debug!("get proto [{}] data len: {}", proto_id, data.len());
+                self.traffic.total.received(data.len());
                 self.event_output(SessionEvent::ProtocolMessage {
                     id: self.id,
                     proto_id,
                     data,
                 })
             }
+            ProtocolEvent::Replaced { .. } => (),
             ProtocolEvent::SelectError { proto_name } => {
+                let failed = proto_name.clone();
                 self.event_output(SessionEvent::ProtocolSelectError {
                     id: self.id,
                     proto_name,
-                })
+                });
+                if let Some(name) = failed {
+                    self.drop_dependent_protos(&name);
+                }
             }
             ProtocolEvent::Error {
                 proto_id, error, ..
@@ -414,6 +662,7 @@ where
         match event {
             SessionEvent::ProtocolMessage { proto_id, data, .. } => {
                 if let Some(stream_id) = self.proto_streams.get(&proto_id) {
+                    self.traffic.total.sent(data.len());
                     self.write_buf.push_back((
                         proto_id,
                         ProtocolEvent::Message {
@@ -452,6 +701,12 @@ where
                     }
                 }
             }
+            SessionEvent::ProtocolConnected { proto_id, .. } => {
+                if self.proto_streams.contains_key(&proto_id) {
+                    self.connected_protos.insert(proto_id);
+                    self.open_pending_protos();
+                }
+            }
             SessionEvent::ProtocolClose { proto_id, .. } => {
                 if !self.proto_streams.contains_key(&proto_id) {
                     debug!("proto [{}] has been closed", proto_id);
@@ -465,6 +720,30 @@ where
                     ));
                 }
             }
+            SessionEvent::ProtocolRegister { meta, open, .. } => {
+                let name = (meta.name)(meta.id);
+                debug!("session [{}] register proto [{}]", self.id, meta.id);
+                self.protocol_configs.insert(name.clone(), meta);
+                if open {
+                    self.open_proto_stream(&name);
+                }
+            }
+            SessionEvent::ProtocolUnregister { proto_id, .. } => {
+                debug!("session [{}] unregister proto [{}]", self.id, proto_id);
+                self.protocol_configs.retain(|_, meta| meta.id != proto_id);
+                let protocol_configs = &self.protocol_configs;
+                self.pending_protos
+                    .retain(|name| protocol_configs.contains_key(name));
+                if let Some(stream_id) = self.proto_streams.get(&proto_id) {
+                    self.write_buf.push_back((
+                        proto_id,
+                        ProtocolEvent::Close {
+                            id: *stream_id,
+                            proto_id,
+                        },
+                    ));
+                }
+            }
             _ => (),
         }
         self.distribute_to_substream();
@@ -613,6 +892,13 @@ where
             }
         }
 
+        let stream_count = self.socket.stream_count();
+        if stream_count != self.stream_count {
+            self.metrics
+                .yamux_streams_change(self.stream_count, stream_count);
+            self.stream_count = stream_count;
+        }
+
         loop {
             // Local close means user doesn't want any message from this session
             // But when remote close, we should try my best to accept all data as much as possible
@@ -674,6 +960,12 @@ where
     }
 }
 
+impl<T> Drop for Session<T> {
+    fn drop(&mut self) {
+        self.metrics.yamux_streams_change(self.stream_count, 0);
+    }
+}
+
 pub(crate) struct SessionMeta {
     config: Config,
     id: SessionId,
@@ -682,6 +974,8 @@ pub(crate) struct SessionMeta {
     // remote_address: ::std::net::SocketAddr,
     // remote_public_key: Option<PublicKey>,
     timeout: Duration,
+    traffic: Arc<SessionTrafficCounter>,
+    metrics: Arc<ServiceMetrics>,
 }
 
 impl SessionMeta {
@@ -692,6 +986,8 @@ impl SessionMeta {
             ty,
             protocol_configs: HashMap::new(),
             timeout,
+            traffic: Arc::new(SessionTrafficCounter::default()),
+            metrics: Arc::new(ServiceMetrics::default()),
         }
     }
 
@@ -704,6 +1000,16 @@ impl SessionMeta {
         self.config = config;
         self
     }
+
+    pub fn traffic(mut self, traffic: Arc<SessionTrafficCounter>) -> Self {
+        self.traffic = traffic;
+        self
+    }
+
+    pub fn metrics(mut self, metrics: Arc<ServiceMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
 }
 
 /// Session state
@@ -1,18 +1,27 @@
+use bytes::{Bytes, BytesMut};
 use futures::{prelude::*, stream::iter_ok, sync::mpsc};
 use log::debug;
 use std::{
     collections::VecDeque,
+    error::Error as ErrorTrait,
+    fmt,
     io::{self, ErrorKind},
-    time::Instant,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 use tokio::{
-    codec::{length_delimited::LengthDelimitedCodec, Framed},
+    codec::{length_delimited::LengthDelimitedCodec, Decoder, Encoder, Framed},
     prelude::AsyncWrite,
     timer::Delay,
 };
 
 use crate::{
-    error::Error, service::DELAY_TIME, traits::Codec, yamux::StreamHandle, ProtocolId, StreamId,
+    error::Error,
+    service::{config::InboundLimit, DELAY_TIME},
+    traffic::TrafficCounter,
+    traits::Codec,
+    yamux::StreamHandle,
+    ProtocolId, StreamId,
 };
 
 /// Event generated/received by the protocol stream
@@ -43,6 +52,14 @@ pub(crate) enum ProtocolEvent {
         /// Data
         data: bytes::Bytes,
     },
+    /// Another sub stream of the protocol is used to send, close the sending side
+    /// after the sent data is flushed, and keep receiving until the remote closes it
+    Replaced {
+        /// Stream id
+        id: StreamId,
+        /// Protocol id
+        proto_id: ProtocolId,
+    },
     SelectError {
         proto_name: Option<String>,
     },
@@ -57,6 +74,127 @@ pub(crate) enum ProtocolEvent {
     },
 }
 
+/// Token bucket of inbound message rate
+struct MessageRate {
+    per_second: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl MessageRate {
+    fn new(per_second: u32, burst: u32) -> Self {
+        let burst = f64::from(::std::cmp::max(burst, 1));
+        MessageRate {
+            per_second: f64::from(per_second),
+            burst,
+            tokens: burst,
+            last: Instant::now(),
+        }
+    }
+
+    /// Take a token, return false if exceeded
+    fn check(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = duration_to_secs(now - self.last);
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.burst);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[inline]
+fn duration_to_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+/// Size of the length field of the default codec
+const LENGTH_FIELD_SIZE: usize = 4;
+
+/// The default codec with a max message size
+///
+/// The length field is checked before the payload is buffered, so an oversized
+/// message is rejected without reading it into memory. The framing can't recover
+/// after that, so the sub stream is always closed.
+pub(crate) struct MaxLengthCodec {
+    inner: LengthDelimitedCodec,
+    max_message_size: usize,
+}
+
+impl MaxLengthCodec {
+    pub(crate) fn new(max_message_size: usize) -> Self {
+        MaxLengthCodec {
+            inner: LengthDelimitedCodec::new(),
+            max_message_size,
+        }
+    }
+}
+
+impl Decoder for MaxLengthCodec {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.len() < LENGTH_FIELD_SIZE {
+            return Ok(None);
+        }
+
+        let mut length = [0; LENGTH_FIELD_SIZE];
+        length.copy_from_slice(&src[..LENGTH_FIELD_SIZE]);
+        let size = u32::from_be_bytes(length) as usize;
+        if size > self.max_message_size {
+            return Err(io::Error::new(ErrorKind::InvalidData, FrameTooLarge(size)));
+        }
+
+        if src.len() < LENGTH_FIELD_SIZE + size {
+            src.reserve(LENGTH_FIELD_SIZE + size - src.len());
+            return Ok(None);
+        }
+        src.split_to(LENGTH_FIELD_SIZE);
+        Ok(Some(src.split_to(size)))
+    }
+}
+
+impl Encoder for MaxLengthCodec {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.inner.encode(item, dst)
+    }
+}
+
+/// The length field of an inbound frame exceeds the max message size
+#[derive(Debug)]
+struct FrameTooLarge(usize);
+
+impl FrameTooLarge {
+    /// The frame size, if the error is caused by a too large frame
+    fn size(error: &io::Error) -> Option<usize> {
+        error
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<FrameTooLarge>())
+            .map(|err| err.0)
+    }
+}
+
+impl fmt::Display for FrameTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "frame size {} is too large", self.0)
+    }
+}
+
+impl ErrorTrait for FrameTooLarge {
+    fn description(&self) -> &str {
+        "frame size is too large"
+    }
+}
+
 /// Each custom protocol in a session corresponds to a sub stream
 /// Can be seen as the route of each protocol
 pub(crate) struct SubStream<U> {
@@ -68,6 +206,10 @@ pub(crate) struct SubStream<U> {
     // The buffer which will send to user
     read_buf: VecDeque<ProtocolEvent>,
     dead: bool,
+    /// Replaced by another sub stream, only receive data
+    replaced: bool,
+    /// The sending side has been closed
+    write_closed: bool,
 
     /// Send event to session
     event_sender: mpsc::Sender<ProtocolEvent>,
@@ -75,6 +217,12 @@ pub(crate) struct SubStream<U> {
     event_receiver: mpsc::Receiver<ProtocolEvent>,
     /// Delay notify with abnormally poor machines
     delay: Option<Delay>,
+    /// Traffic of this protocol
+    traffic: Arc<TrafficCounter>,
+    /// Inbound message limits
+    max_message_size: Option<usize>,
+    message_rate: Option<MessageRate>,
+    close_on_violation: bool,
 }
 
 impl<U> SubStream<U>
@@ -88,6 +236,8 @@ where
         event_receiver: mpsc::Receiver<ProtocolEvent>,
         id: StreamId,
         proto_id: ProtocolId,
+        traffic: Arc<TrafficCounter>,
+        inbound_limit: InboundLimit,
     ) -> Self {
         SubStream {
             sub_stream,
@@ -99,19 +249,57 @@ where
             read_buf: VecDeque::new(),
             delay: None,
             dead: false,
+            replaced: false,
+            write_closed: false,
+            traffic,
+            max_message_size: inbound_limit.max_message_size,
+            message_rate: inbound_limit
+                .message_rate
+                .map(|(per_second, burst)| MessageRate::new(per_second, burst)),
+            close_on_violation: inbound_limit.close_on_violation,
+        }
+    }
+
+    /// Check the inbound message limits, the message will be dropped on violation
+    fn check_inbound(&mut self, size: usize) -> bool {
+        let error = if self.max_message_size.map(|max| size > max).unwrap_or(false) {
+            Error::MessageTooLarge(size)
+        } else if self
+            .message_rate
+            .as_mut()
+            .map(|rate| !rate.check())
+            .unwrap_or(false)
+        {
+            Error::MessageRateExceeded
+        } else {
+            return true;
+        };
+
+        debug!("protocol [{}] inbound violation: {}", self.proto_id, error);
+        self.read_buf.push_back(ProtocolEvent::Error {
+            id: self.id,
+            proto_id: self.proto_id,
+            error,
+        });
+        if self.close_on_violation {
+            self.dead = true;
+        } else {
+            self.output();
         }
+        false
     }
 
     /// Send data to the lower `yamux` sub stream
     fn send_data(&mut self) -> Result<(), io::Error> {
         while let Some(frame) = self.write_buf.pop_front() {
+            let len = frame.len();
             match self.sub_stream.start_send(frame) {
                 Ok(AsyncSink::NotReady(frame)) => {
                     debug!("framed_stream NotReady, frame len: {:?}", frame.len());
                     self.write_buf.push_front(frame);
                     return Ok(());
                 }
-                Ok(AsyncSink::Ready) => {}
+                Ok(AsyncSink::Ready) => self.traffic.sent(len),
                 Err(err) => {
                     debug!("framed_stream send error: {:?}", err);
                     return Err(err);
@@ -150,13 +338,43 @@ where
         );
     }
 
+    /// Close the sending side after the buffered data is sent
+    fn close_write(&mut self) -> Result<(), io::Error> {
+        if !self.write_buf.is_empty() {
+            return Ok(());
+        }
+        match self.sub_stream.close() {
+            Ok(Async::Ready(())) => {
+                debug!("proto [{}] replaced, sending side closed", self.proto_id);
+                self.write_closed = true;
+                Ok(())
+            }
+            Ok(Async::NotReady) => Ok(()),
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
+                self.set_delay();
+                Ok(())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
     /// When send or receive message error, output error and close stream
     fn error_close(&mut self, error: io::Error) {
         self.dead = true;
+        let error = match FrameTooLarge::size(&error) {
+            Some(size) => {
+                debug!(
+                    "protocol [{}] inbound frame too large: {}",
+                    self.proto_id, size
+                );
+                Error::MessageTooLarge(size)
+            }
+            None => error.into(),
+        };
         self.read_buf.push_back(ProtocolEvent::Error {
             id: self.id,
             proto_id: self.proto_id,
-            error: error.into(),
+            error,
         });
         self.close_proto_stream();
     }
@@ -187,6 +405,7 @@ where
                 self.write_buf.clear();
                 self.dead = true;
             }
+            ProtocolEvent::Replaced { .. } => self.replaced = true,
             _ => (),
         }
     }
@@ -281,11 +500,14 @@ where
                         self.proto_id,
                         data.len()
                     );
-                    self.output_event(ProtocolEvent::Message {
-                        id: self.id,
-                        proto_id: self.proto_id,
-                        data: data.into(),
-                    })
+                    self.traffic.received(data.len());
+                    if self.check_inbound(data.len()) {
+                        self.output_event(ProtocolEvent::Message {
+                            id: self.id,
+                            proto_id: self.proto_id,
+                            data: data.into(),
+                        })
+                    }
                 }
                 Ok(Async::Ready(None)) => {
                     debug!("protocol [{}] close", self.proto_id);
@@ -328,6 +550,13 @@ where
             }
         }
 
+        if self.replaced && !self.write_closed && !self.dead {
+            if let Err(err) = self.close_write() {
+                self.error_close(err);
+                return Ok(Async::Ready(None));
+            }
+        }
+
         if self.dead {
             self.close_proto_stream();
             return Ok(Async::Ready(None));
@@ -336,3 +565,26 @@ where
         Ok(Async::NotReady)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{FrameTooLarge, MaxLengthCodec};
+    use bytes::{BufMut, BytesMut};
+    use tokio::codec::Decoder;
+
+    #[test]
+    fn test_max_length_codec() {
+        let mut codec = MaxLengthCodec::new(4);
+
+        let mut src = BytesMut::with_capacity(16);
+        src.put_u32_be(2);
+        src.put_slice(b"hi");
+        assert_eq!(&codec.decode(&mut src).unwrap().unwrap()[..], b"hi");
+
+        // Only the length field is received, the payload is never buffered
+        src.put_u32_be(1024 * 1024 * 1024);
+        let error = codec.decode(&mut src).unwrap_err();
+        assert_eq!(FrameTooLarge::size(&error), Some(1024 * 1024 * 1024));
+        assert!(src.capacity() < 1024);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{secio::codec::secure_stream::SecureTraffic, ProtocolId};
+
+/// Traffic statistics, bytes are counted on the protocol message, without encryption
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrafficStats {
+    /// Bytes sent
+    pub sent_bytes: u64,
+    /// Messages sent
+    pub sent_messages: u64,
+    /// Bytes received
+    pub received_bytes: u64,
+    /// Messages received
+    pub received_messages: u64,
+    /// The last time a message was sent or received, none if never
+    pub last_activity: Option<SystemTime>,
+}
+
+/// Traffic snapshot of a session
+#[derive(Debug, Clone, Default)]
+pub struct SessionTraffic {
+    /// Traffic of all protocols
+    pub total: TrafficStats,
+    /// Traffic of each protocol, including the closed ones
+    pub protocols: HashMap<ProtocolId, TrafficStats>,
+    /// Bytes sent on the wire after encryption, none if secio is not enabled
+    pub encrypted_sent_bytes: Option<u64>,
+    /// Bytes received on the wire before decryption, none if secio is not enabled
+    pub encrypted_received_bytes: Option<u64>,
+}
+
+/// Lock-free traffic counter
+#[derive(Debug, Default)]
+pub(crate) struct TrafficCounter {
+    sent_bytes: AtomicU64,
+    sent_messages: AtomicU64,
+    received_bytes: AtomicU64,
+    received_messages: AtomicU64,
+    /// Milliseconds since unix epoch, 0 means never
+    last_activity: AtomicU64,
+}
+
+impl TrafficCounter {
+    #[inline]
+    pub(crate) fn sent(&self, bytes: usize) {
+        self.sent_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.sent_messages.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+    }
+
+    #[inline]
+    pub(crate) fn received(&self, bytes: usize) {
+        self.received_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.received_messages.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+    }
+
+    #[inline]
+    fn touch(&self) {
+        if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+            let millis = now.as_secs() * 1000 + u64::from(now.subsec_millis());
+            self.last_activity.store(millis, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> TrafficStats {
+        let last_activity = match self.last_activity.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+        };
+        TrafficStats {
+            sent_bytes: self.sent_bytes.load(Ordering::Relaxed),
+            sent_messages: self.sent_messages.load(Ordering::Relaxed),
+            received_bytes: self.received_bytes.load(Ordering::Relaxed),
+            received_messages: self.received_messages.load(Ordering::Relaxed),
+            last_activity,
+        }
+    }
+}
+
+/// Traffic counters of a session, shared by session, sub streams and session context
+#[derive(Debug, Default)]
+pub(crate) struct SessionTrafficCounter {
+    /// Counted by session
+    pub(crate) total: TrafficCounter,
+    /// Counted by sub streams
+    protocols: RwLock<HashMap<ProtocolId, Arc<TrafficCounter>>>,
+    /// Counted by secio
+    secure: Option<Arc<SecureTraffic>>,
+}
+
+impl SessionTrafficCounter {
+    pub(crate) fn new(secure: Option<Arc<SecureTraffic>>) -> Self {
+        SessionTrafficCounter {
+            secure,
+            ..Default::default()
+        }
+    }
+
+    /// Get the counter of protocol, create it if not exist
+    pub(crate) fn protocol(&self, proto_id: ProtocolId) -> Arc<TrafficCounter> {
+        if let Some(counter) = self
+            .protocols
+            .read()
+            .ok()
+            .and_then(|protocols| protocols.get(&proto_id).cloned())
+        {
+            return counter;
+        }
+        match self.protocols.write() {
+            Ok(mut protocols) => Arc::clone(protocols.entry(proto_id).or_default()),
+            Err(_) => Arc::new(TrafficCounter::default()),
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> SessionTraffic {
+        SessionTraffic {
+            total: self.total.stats(),
+            protocols: self
+                .protocols
+                .read()
+                .map(|protocols| {
+                    protocols
+                        .iter()
+                        .map(|(proto_id, counter)| (*proto_id, counter.stats()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            encrypted_sent_bytes: self.secure.as_ref().map(|secure| secure.sent_bytes()),
+            encrypted_received_bytes: self.secure.as_ref().map(|secure| secure.received_bytes()),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use futures::Async;
 use std::io;
 use tokio::codec::{Decoder, Encoder};
 
@@ -34,6 +35,14 @@ pub trait ServiceHandle {
     /// If the handle of the protocol has event, then its events will be placed here.
     /// If there is no event handle in the protocol, this interface will not be called.
     fn handle_proto(&mut self, _control: &mut ServiceContext, _event: ProtocolEvent) {}
+    /// Called before the service receives new events, return `NotReady` to pause the service,
+    /// the handle must make sure that the current task will be notified when it is ready again.
+    ///
+    /// Default is always ready
+    #[inline]
+    fn poll_ready(&mut self) -> Async<()> {
+        Async::Ready(())
+    }
 }
 
 /// Service level protocol handle
@@ -132,6 +141,10 @@ impl ServiceHandle for Box<dyn ServiceHandle + Send + 'static> {
     fn handle_proto(&mut self, control: &mut ServiceContext, event: ProtocolEvent) {
         (&mut **self).handle_proto(control, event)
     }
+
+    fn poll_ready(&mut self) -> Async<()> {
+        (&mut **self).poll_ready()
+    }
 }
 
 impl ServiceHandle for Box<dyn ServiceHandle + Send + Sync + 'static> {
@@ -146,6 +159,10 @@ impl ServiceHandle for Box<dyn ServiceHandle + Send + Sync + 'static> {
     fn handle_proto(&mut self, control: &mut ServiceContext, event: ProtocolEvent) {
         (&mut **self).handle_proto(control, event)
     }
+
+    fn poll_ready(&mut self) -> Async<()> {
+        (&mut **self).poll_ready()
+    }
 }
 
 impl ServiceHandle for () {}
@@ -0,0 +1,319 @@
+use futures::{prelude::*, task};
+use log::debug;
+use std::{
+    cmp::min,
+    collections::HashMap,
+    io::{self, Read, Write},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::{
+    prelude::{AsyncRead, AsyncWrite},
+    timer::Delay,
+};
+
+use crate::{service::BandwidthLimit, SessionId};
+
+/// When the bucket is empty, wait this time before retry
+const LIMIT_DELAY: Duration = Duration::from_millis(50);
+
+#[derive(Debug)]
+struct BucketState {
+    /// Bytes per second, none means unlimited
+    rate: Option<u64>,
+    tokens: u64,
+    last: Instant,
+}
+
+impl BucketState {
+    #[inline]
+    fn refill(&mut self) {
+        let now = Instant::now();
+        match self.rate {
+            Some(rate) => {
+                let elapsed = now - self.last;
+                let add = (u128::from(rate) * elapsed.as_nanos() / 1_000_000_000) as u64;
+                if add > 0 || self.tokens >= rate {
+                    // The capacity of bucket is the rate of one second
+                    self.tokens = min(self.tokens.saturating_add(add), rate);
+                    self.last = now;
+                }
+            }
+            None => self.last = now,
+        }
+    }
+}
+
+/// Token bucket
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    state: Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    fn new(rate: Option<u64>) -> Self {
+        let rate = rate.map(|rate| rate.max(1));
+        TokenBucket {
+            state: Mutex::new(BucketState {
+                rate,
+                tokens: rate.unwrap_or_default(),
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    fn set_rate(&self, rate: Option<u64>) {
+        if let Ok(mut state) = self.state.lock() {
+            state.refill();
+            let rate = rate.map(|rate| rate.max(1));
+            if state.rate.is_none() {
+                state.tokens = rate.unwrap_or_default();
+            }
+            state.rate = rate;
+            if let Some(rate) = rate {
+                state.tokens = min(state.tokens, rate);
+            }
+        }
+    }
+
+    /// Bytes allowed to pass now, none means unlimited
+    #[cfg(test)]
+    fn available(&self) -> Option<u64> {
+        self.state.lock().ok().and_then(|mut state| {
+            state.refill();
+            state.rate.map(|_| state.tokens)
+        })
+    }
+
+    /// Takes up to `n` tokens at once, returns the number taken, none means unlimited
+    #[inline]
+    fn acquire(&self, n: u64) -> Option<u64> {
+        self.state.lock().ok().and_then(|mut state| {
+            state.refill();
+            state.rate.map(|_| {
+                let taken = min(state.tokens, n);
+                state.tokens -= taken;
+                taken
+            })
+        })
+    }
+
+    /// Gives back the tokens taken but not used
+    #[inline]
+    fn release(&self, n: u64) {
+        if let Ok(mut state) = self.state.lock() {
+            if let Some(rate) = state.rate {
+                state.tokens = min(state.tokens.saturating_add(n), rate);
+            }
+        }
+    }
+}
+
+/// Upload and download token buckets
+#[derive(Debug)]
+pub(crate) struct BandwidthLimiter {
+    upload: TokenBucket,
+    download: TokenBucket,
+}
+
+impl BandwidthLimiter {
+    pub(crate) fn new(limit: BandwidthLimit) -> Self {
+        BandwidthLimiter {
+            upload: TokenBucket::new(limit.upload),
+            download: TokenBucket::new(limit.download),
+        }
+    }
+
+    pub(crate) fn set_limit(&self, limit: BandwidthLimit) {
+        self.upload.set_rate(limit.upload);
+        self.download.set_rate(limit.download);
+    }
+}
+
+/// Global and per-session limiters, shared by service and service control
+#[derive(Debug)]
+pub(crate) struct BandwidthControl {
+    pub(crate) global: Arc<BandwidthLimiter>,
+    /// The limit of new sessions
+    pub(crate) session_default: RwLock<BandwidthLimit>,
+    pub(crate) sessions: RwLock<HashMap<SessionId, Arc<BandwidthLimiter>>>,
+}
+
+impl BandwidthControl {
+    pub(crate) fn new(global: BandwidthLimit, session_default: BandwidthLimit) -> Self {
+        BandwidthControl {
+            global: Arc::new(BandwidthLimiter::new(global)),
+            session_default: RwLock::new(session_default),
+            sessions: RwLock::new(HashMap::default()),
+        }
+    }
+
+    /// New a limiter for a new session
+    pub(crate) fn new_session(&self) -> Arc<BandwidthLimiter> {
+        let limit = self
+            .session_default
+            .read()
+            .map(|limit| *limit)
+            .unwrap_or_default();
+        Arc::new(BandwidthLimiter::new(limit))
+    }
+}
+
+/// Wraps the transport stream, read and write will be limited by
+/// both the global limiter and the session limiter.
+///
+/// When there is no token, return `WouldBlock` and wait for refill,
+/// so the upper layer will buffer data rather than drop it.
+pub(crate) struct LimitedStream<T> {
+    inner: T,
+    global: Arc<BandwidthLimiter>,
+    session: Arc<BandwidthLimiter>,
+    read_delay: Option<Delay>,
+    write_delay: Option<Delay>,
+}
+
+impl<T> LimitedStream<T> {
+    pub(crate) fn new(
+        inner: T,
+        global: Arc<BandwidthLimiter>,
+        session: Arc<BandwidthLimiter>,
+    ) -> Self {
+        LimitedStream {
+            inner,
+            global,
+            session,
+            read_delay: None,
+            write_delay: None,
+        }
+    }
+}
+
+/// Takes the allowance from both buckets before the io, so the concurrent sessions can't
+/// overshoot the global limit, none means unlimited
+#[inline]
+fn acquire(global: &TokenBucket, session: &TokenBucket, n: usize) -> Option<u64> {
+    let n = n as u64;
+    let session_taken = session.acquire(n);
+    let global_taken = global.acquire(session_taken.unwrap_or(n));
+    match (global_taken, session_taken) {
+        (Some(a), Some(b)) => {
+            session.release(b - a);
+            Some(a)
+        }
+        (a, b) => a.or(b),
+    }
+}
+
+/// Gives back the allowance which is not used by the io
+#[inline]
+fn release(global: &TokenBucket, session: &TokenBucket, unused: usize) {
+    global.release(unused as u64);
+    session.release(unused as u64);
+}
+
+/// Register current task to be notified when the bucket refill
+#[inline]
+fn wait(delay: &mut Option<Delay>) -> io::Error {
+    let mut inner = delay
+        .take()
+        .unwrap_or_else(|| Delay::new(Instant::now() + LIMIT_DELAY));
+    if let Ok(Async::Ready(_)) = inner.poll() {
+        // Still no token after the last delay, wait again
+        inner = Delay::new(Instant::now() + LIMIT_DELAY);
+    }
+    match inner.poll() {
+        Ok(Async::NotReady) => *delay = Some(inner),
+        Ok(Async::Ready(_)) => task::current().notify(),
+        Err(err) => {
+            debug!("limit delay error: {:?}", err);
+            task::current().notify()
+        }
+    }
+    io::ErrorKind::WouldBlock.into()
+}
+
+impl<T: Read> Read for LimitedStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.inner.read(buf);
+        }
+        let len = match acquire(&self.global.download, &self.session.download, buf.len()) {
+            Some(0) => return Err(wait(&mut self.read_delay)),
+            Some(n) => n as usize,
+            None => buf.len(),
+        };
+        self.read_delay = None;
+
+        let result = self.inner.read(&mut buf[..len]);
+        let used = *result.as_ref().unwrap_or(&0);
+        release(&self.global.download, &self.session.download, len - used);
+        result
+    }
+}
+
+impl<T: Write> Write for LimitedStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.inner.write(buf);
+        }
+        let len = match acquire(&self.global.upload, &self.session.upload, buf.len()) {
+            Some(0) => return Err(wait(&mut self.write_delay)),
+            Some(n) => n as usize,
+            None => buf.len(),
+        };
+        self.write_delay = None;
+
+        let result = self.inner.write(&buf[..len]);
+        let used = *result.as_ref().unwrap_or(&0);
+        release(&self.global.upload, &self.session.upload, len - used);
+        result
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for LimitedStream<T> {
+    #[inline]
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for LimitedStream<T> {
+    #[inline]
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TokenBucket;
+
+    #[test]
+    fn test_token_bucket() {
+        let bucket = TokenBucket::new(Some(100));
+        assert_eq!(bucket.available(), Some(100));
+
+        assert_eq!(bucket.acquire(60), Some(60));
+        assert!(bucket.available().unwrap() < 100);
+
+        assert!(bucket.acquire(60).unwrap() <= 40);
+        assert!(bucket.available().unwrap() < 10);
+
+        bucket.release(200);
+        assert_eq!(bucket.available(), Some(100));
+
+        bucket.set_rate(Some(10));
+        assert!(bucket.available().unwrap() <= 10);
+
+        bucket.set_rate(None);
+        assert_eq!(bucket.available(), None);
+
+        bucket.set_rate(Some(50));
+        assert_eq!(bucket.available(), Some(50));
+    }
+}
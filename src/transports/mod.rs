@@ -14,6 +14,8 @@ use tokio::{
 
 use self::tcp::{TcpDialFuture, TcpListenFuture, TcpTransport};
 
+pub(crate) mod limit;
+pub(crate) mod pnet;
 mod tcp;
 
 /// Transport Error
@@ -108,10 +110,35 @@ impl Future for MultiDialFuture {
     }
 }
 
+/// The kind of transport a session runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportKind {
+    /// Tcp
+    Tcp,
+}
+
 pub enum MultiStream {
     Tcp(TcpStream),
 }
 
+impl MultiStream {
+    /// Transport kind of this stream
+    #[inline]
+    pub fn kind(&self) -> TransportKind {
+        match self {
+            MultiStream::Tcp(_) => TransportKind::Tcp,
+        }
+    }
+
+    /// Local address of this stream
+    #[inline]
+    pub fn local_addr(&self) -> Option<Multiaddr> {
+        match self {
+            MultiStream::Tcp(inner) => inner.local_addr().ok().map(socketaddr_to_multiaddr),
+        }
+    }
+}
+
 impl fmt::Debug for MultiStream {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
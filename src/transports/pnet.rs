@@ -0,0 +1,230 @@
+use futures::{future, prelude::*};
+use log::debug;
+use std::fmt;
+use std::io::{self, Read, Write};
+use tokio::prelude::{AsyncRead, AsyncWrite};
+
+use crate::{
+    error::Error,
+    secio::{
+        codec::StreamCipher,
+        stream_cipher::{ctr_init, Cipher},
+    },
+    service::PreSharedKey,
+};
+
+/// The cipher of a private network, keyed by the pre-shared key
+const PNET_CIPHER: Cipher = Cipher::Aes256;
+/// Size of the random iv sent in clear by each side
+const IV_SIZE: usize = 16;
+/// Sent encrypted after the iv, the remote with another key decrypts it to garbage
+const MAGIC: &[u8; 16] = b"/tentacle/pnet/1";
+
+/// Exchanges the ivs with remote and checks that both sides use the same pre-shared key
+///
+/// Each side sends its iv and the encrypted magic at the same time, so a remote with
+/// a different key fails after one round trip.
+pub(crate) fn handshake<T>(
+    socket: T,
+    psk: PreSharedKey,
+) -> impl Future<Item = PnetStream<T>, Error = Error>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    let local_iv: [u8; IV_SIZE] = rand::random();
+
+    future::result(ctr_init(PNET_CIPHER, psk.as_bytes(), &local_iv))
+        .from_err()
+        .and_then(move |mut encode_cipher| {
+            let mut magic = *MAGIC;
+            encode_cipher.encrypt(&mut magic);
+            let hello = [&local_iv[..], &magic[..]].concat();
+
+            tokio::io::write_all(socket, hello)
+                .and_then(|(socket, _)| tokio::io::read_exact(socket, [0u8; IV_SIZE + 16]))
+                .from_err()
+                .map(move |(socket, remote_hello)| (socket, remote_hello, encode_cipher))
+        })
+        .and_then(move |(socket, remote_hello, encode_cipher)| {
+            let (remote_iv, remote_magic) = remote_hello.split_at(IV_SIZE);
+            let mut decode_cipher = ctr_init(PNET_CIPHER, psk.as_bytes(), remote_iv)?;
+
+            let mut magic = remote_magic.to_vec();
+            decode_cipher.decrypt(&mut magic);
+            if magic[..] != MAGIC[..] {
+                debug!("the remote uses another pre-shared key");
+                return Err(Error::PreSharedKeyMismatch);
+            }
+
+            Ok(PnetStream {
+                inner: socket,
+                encode_cipher,
+                decode_cipher,
+                write_buf: Vec::new(),
+                written: 0,
+            })
+        })
+}
+
+/// Stream encrypted by the pre-shared key of a private network
+pub(crate) struct PnetStream<T> {
+    inner: T,
+    encode_cipher: StreamCipher,
+    decode_cipher: StreamCipher,
+    /// Encrypted data which is not written to the inner stream yet
+    write_buf: Vec<u8>,
+    written: usize,
+}
+
+impl<T> fmt::Debug for PnetStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The ciphers are keyed by the pre-shared key
+        write!(f, "PnetStream(..)")
+    }
+}
+
+impl<T: Write> PnetStream<T> {
+    /// Writes the encrypted data left by the last `write`
+    fn write_buf(&mut self) -> io::Result<()> {
+        while self.written < self.write_buf.len() {
+            let n = self.inner.write(&self.write_buf[self.written..])?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.written += n;
+        }
+        self.write_buf.clear();
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl<T: Read> Read for PnetStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.decode_cipher.decrypt(&mut buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<T: AsyncRead> AsyncRead for PnetStream<T> {}
+
+impl<T: Write> Write for PnetStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_buf()?;
+
+        // The cipher can't go back, so the encrypted data is always accepted
+        // and written later if the inner stream is not ready
+        self.write_buf.extend_from_slice(buf);
+        self.encode_cipher.encrypt(&mut self.write_buf);
+        match self.write_buf() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+            Err(e) => return Err(e),
+            Ok(()) => (),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_buf()?;
+        self.inner.flush()
+    }
+}
+
+impl<T: AsyncWrite> AsyncWrite for PnetStream<T> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self.write_buf() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+            Err(e) => return Err(e),
+            Ok(()) => (),
+        }
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{handshake, PnetStream, PNET_CIPHER};
+    use crate::{error::Error, secio::stream_cipher::ctr_init, service::PreSharedKey};
+
+    use futures::prelude::*;
+    use std::io::{Read, Write};
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn pnet_handshake(
+        listener_key: PreSharedKey,
+        dialer_key: PreSharedKey,
+    ) -> Result<Vec<u8>, Error> {
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener_addr = listener.local_addr().unwrap();
+
+        let server = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| e.into())
+            .and_then(move |(socket, _)| handshake(socket.unwrap(), listener_key))
+            .and_then(|stream: PnetStream<TcpStream>| {
+                tokio::io::read_exact(stream, [0u8; 11])
+                    .and_then(|(stream, data)| tokio::io::write_all(stream, data))
+                    .map(|_| ())
+                    .from_err()
+            })
+            .map_err(|_| ());
+
+        let client = TcpStream::connect(&listener_addr)
+            .from_err()
+            .and_then(move |socket| handshake(socket, dialer_key))
+            .and_then(|stream| {
+                tokio::io::write_all(stream, b"hello world")
+                    .and_then(|(stream, _)| tokio::io::read_exact(stream, [0u8; 11]))
+                    .map(|(_, data)| data.to_vec())
+                    .from_err()
+            });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        rt.spawn(server);
+        rt.block_on(client)
+    }
+
+    #[test]
+    fn test_pnet_same_key() {
+        let key = PreSharedKey::new([7; 32]);
+        assert_eq!(
+            pnet_handshake(key.clone(), key).unwrap(),
+            b"hello world".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_pnet_different_key() {
+        match pnet_handshake(PreSharedKey::new([7; 32]), PreSharedKey::new([8; 32])) {
+            Err(Error::PreSharedKeyMismatch) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    fn new_stream<T>(inner: T, key: &PreSharedKey) -> PnetStream<T> {
+        PnetStream {
+            inner,
+            encode_cipher: ctr_init(PNET_CIPHER, key.as_bytes(), &[0; 16]).unwrap(),
+            decode_cipher: ctr_init(PNET_CIPHER, key.as_bytes(), &[0; 16]).unwrap(),
+            write_buf: Vec::new(),
+            written: 0,
+        }
+    }
+
+    #[test]
+    fn test_pnet_stream_is_encrypted() {
+        let key = PreSharedKey::new([7; 32]);
+        let mut wire = Vec::new();
+
+        let mut writer = new_stream(&mut wire, &key);
+        writer.write_all(b"hello world").unwrap();
+        writer.flush().unwrap();
+        assert_ne!(&wire[..], b"hello world");
+
+        let mut data = Vec::new();
+        new_stream(&wire[..], &key).read_to_end(&mut data).unwrap();
+        assert_eq!(&data[..], b"hello world");
+    }
+}
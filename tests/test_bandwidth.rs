@@ -0,0 +1,115 @@
+use bytes::Bytes;
+use futures::prelude::Stream;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef},
+    service::{BandwidthLimit, DialProtocol, ProtocolHandle, ProtocolMeta, Service, SessionType},
+    traits::ServiceProtocol,
+};
+
+const PAYLOAD_SIZE: usize = 96 * 1024;
+const LIMIT: u64 = 32 * 1024;
+
+pub fn create(meta: ProtocolMeta, global: BandwidthLimit, session: BandwidthLimit) -> Service<()> {
+    ServiceBuilder::default()
+        .insert_protocol(meta)
+        .global_bandwidth_limit(global)
+        .session_bandwidth_limit(session)
+        .forever(true)
+        .build(())
+}
+
+fn payload() -> Bytes {
+    (0..PAYLOAD_SIZE).map(|i| (i % 251) as u8).collect()
+}
+
+struct PHandle {
+    sender: crossbeam_channel::Sender<Bytes>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        if context.session.ty == SessionType::Outbound {
+            context.send_message(payload());
+        }
+    }
+
+    fn received(&mut self, _context: ProtocolContextMutRef, data: Bytes) {
+        let _ = self.sender.send(data);
+    }
+}
+
+fn create_meta(sender: crossbeam_channel::Sender<Bytes>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(1.into())
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle { sender })))
+        .build()
+}
+
+#[test]
+fn test_session_bandwidth_limit() {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    let mut service_1 = create(
+        create_meta(sender.clone()),
+        BandwidthLimit::default(),
+        BandwidthLimit::default(),
+    );
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let start = Instant::now();
+    let mut service_2 = create(
+        create_meta(sender),
+        BandwidthLimit::default(),
+        BandwidthLimit::new(Some(LIMIT), None),
+    );
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    // The bucket starts full, the rest of the payload takes about two seconds
+    let received = receiver.recv_timeout(Duration::from_secs(30)).unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(received, payload());
+    assert!(elapsed >= Duration::from_millis(1500), "{:?}", elapsed);
+}
+
+#[test]
+fn test_bandwidth_limit_changed_at_runtime() {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    let mut service_1 = create(
+        create_meta(sender.clone()),
+        BandwidthLimit::default(),
+        BandwidthLimit::default(),
+    );
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    // It takes more than a minute with this limit
+    let mut service_2 = create(
+        create_meta(sender),
+        BandwidthLimit::new(Some(1024), None),
+        BandwidthLimit::default(),
+    );
+    let control = service_2.control().clone();
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    // Throttled, the data is buffered rather than dropped
+    assert!(receiver.recv_timeout(Duration::from_secs(1)).is_err());
+
+    control.set_global_bandwidth_limit(BandwidthLimit::default());
+    let received = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(received, payload());
+}
@@ -0,0 +1,82 @@
+#![cfg(feature = "async-compat")]
+
+use futures::prelude::Stream;
+use futures03::{executor::block_on, stream::StreamExt};
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    compat::{AsyncEventHandle, AsyncEventStreams, AsyncServiceControl},
+    secio::SecioKeyPair,
+    service::{DialProtocol, ProtocolEvent, ProtocolHandle, ProtocolMeta, Service, ServiceEvent},
+};
+
+pub fn create(secio: bool, meta: ProtocolMeta) -> (Service<AsyncEventHandle>, AsyncEventStreams) {
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build_with_async_streams(4)
+    } else {
+        builder.build_with_async_streams(4)
+    }
+}
+
+fn create_meta() -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(1.into())
+        .service_handle(|| ProtocolHandle::Event)
+        .build()
+}
+
+fn test_async_streams(secio: bool) {
+    let (mut service_1, streams_1) = create(secio, create_meta());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let (service_2, streams_2) = create(secio, create_meta());
+    let control_2 = AsyncServiceControl::from(service_2.control().clone());
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    block_on(control_2.dial(listen_addr, DialProtocol::All)).unwrap();
+
+    let AsyncEventStreams {
+        mut sessions,
+        mut protocols,
+        ..
+    } = streams_1;
+    loop {
+        match block_on(sessions.next()) {
+            Some(ServiceEvent::SessionOpen { .. }) => break,
+            Some(_) => (),
+            None => panic!("session stream closed"),
+        }
+    }
+    match block_on(protocols.next()) {
+        Some(ProtocolEvent::Connected { proto_id, .. }) => assert_eq!(proto_id, 1.into()),
+        other => panic!("unexpected protocol event: {:?}", other),
+    }
+
+    // The dialer gets the same events on its own streams
+    let AsyncEventStreams { mut protocols, .. } = streams_2;
+    match block_on(protocols.next()) {
+        Some(ProtocolEvent::Connected { proto_id, .. }) => assert_eq!(proto_id, 1.into()),
+        other => panic!("unexpected protocol event: {:?}", other),
+    }
+
+    block_on(control_2.shutdown()).unwrap();
+}
+
+#[test]
+fn test_async_streams_with_secio() {
+    test_async_streams(true)
+}
+
+#[test]
+fn test_async_streams_with_no_secio() {
+    test_async_streams(false)
+}
@@ -0,0 +1,77 @@
+use futures::prelude::Stream;
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef},
+    secio::SecioKeyPair,
+    service::{DialProtocol, ProtocolHandle, ProtocolMeta, Service},
+    traits::{ServiceHandle, ServiceProtocol},
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct PHandle {
+    sender: crossbeam_channel::Sender<Option<String>>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, _context: ProtocolContextMutRef, _version: &str) {
+        let _ = self
+            .sender
+            .send(thread::current().name().map(ToOwned::to_owned));
+    }
+}
+
+fn create_meta(sender: crossbeam_channel::Sender<Option<String>>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(1.into())
+        .dedicated_pool(1, 8)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle { sender })))
+        .build()
+}
+
+fn test_dedicated_pool(secio: bool) {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    let mut service_1 = create(secio, create_meta(sender.clone()), ());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let mut service_2 = create(secio, create_meta(sender), ());
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    for _ in 0..2 {
+        let name = receiver.recv().unwrap().unwrap();
+        assert!(name.starts_with("proto-1-handle-"));
+    }
+}
+
+#[test]
+fn test_dedicated_pool_with_secio() {
+    test_dedicated_pool(true)
+}
+
+#[test]
+fn test_dedicated_pool_with_no_secio() {
+    test_dedicated_pool(false)
+}
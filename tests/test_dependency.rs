@@ -0,0 +1,218 @@
+use futures::prelude::Stream;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef, ServiceContext},
+    secio::SecioKeyPair,
+    service::{DialProtocol, ProtocolEvent, ProtocolHandle, ProtocolMeta, Service, ServiceError},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(secio: bool, metas: Vec<ProtocolMeta>, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    let builder = metas
+        .into_iter()
+        .fold(ServiceBuilder::default(), |builder, meta| {
+            builder.insert_protocol(meta)
+        })
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+/// Record the protocol events in the order of service
+struct SHandle {
+    sender: crossbeam_channel::Sender<(ProtocolId, bool)>,
+    error_sender: crossbeam_channel::Sender<Option<String>>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _control: &mut ServiceContext, error: ServiceError) {
+        if let ServiceError::ProtocolSelectError { proto_name, .. } = error {
+            let _ = self.error_sender.send(proto_name);
+        }
+    }
+
+    fn handle_proto(&mut self, _control: &mut ServiceContext, event: ProtocolEvent) {
+        match event {
+            ProtocolEvent::Connected { proto_id, .. } => {
+                let _ = self.sender.send((proto_id, true));
+            }
+            ProtocolEvent::Disconnected { proto_id, .. } => {
+                let _ = self.sender.send((proto_id, false));
+            }
+            _ => (),
+        }
+    }
+}
+
+struct PHandle {
+    /// Close the dependency after this protocol is connected
+    close_dependency: bool,
+    /// Set by the dependency when its `connected` returns
+    dependency_connected: Arc<AtomicBool>,
+    /// Whether the dependency is connected when this protocol is connected
+    sender: Option<crossbeam_channel::Sender<bool>>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        match self.sender {
+            Some(ref sender) => {
+                let _ = sender.send(self.dependency_connected.load(Ordering::SeqCst));
+                if self.close_dependency {
+                    context.close_protocol(context.session.id, 1.into());
+                }
+            }
+            None => {
+                // A slow dependency
+                thread::sleep(Duration::from_millis(200));
+                self.dependency_connected.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+/// Protocol 2 depends on protocol 1
+fn create_metas(
+    close_dependency: bool,
+    sender: crossbeam_channel::Sender<bool>,
+) -> Vec<ProtocolMeta> {
+    let dependency_connected = Arc::new(AtomicBool::new(false));
+    let dependency_connected_1 = Arc::clone(&dependency_connected);
+    vec![
+        MetaBuilder::new()
+            .id(1.into())
+            .service_handle(move || {
+                ProtocolHandle::Both(Box::new(PHandle {
+                    close_dependency: false,
+                    dependency_connected: dependency_connected_1,
+                    sender: None,
+                }))
+            })
+            .build(),
+        MetaBuilder::new()
+            .id(2.into())
+            .depends_on(1.into())
+            .service_handle(move || {
+                ProtocolHandle::Both(Box::new(PHandle {
+                    close_dependency,
+                    dependency_connected,
+                    sender: Some(sender),
+                }))
+            })
+            .build(),
+    ]
+}
+
+fn test_dependency(secio: bool) {
+    let (sender_1, _receiver_1) = crossbeam_channel::unbounded();
+    let (sender_2, receiver_2) = crossbeam_channel::unbounded();
+    let (event_sender_1, _event_receiver_1) = crossbeam_channel::unbounded();
+    let (event_sender_2, event_receiver_2) = crossbeam_channel::unbounded();
+
+    let mut service_1 = create(
+        secio,
+        create_metas(false, sender_1),
+        SHandle {
+            sender: event_sender_1,
+            error_sender: crossbeam_channel::unbounded().0,
+        },
+    );
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let mut service_2 = create(
+        secio,
+        create_metas(true, sender_2),
+        SHandle {
+            sender: event_sender_2,
+            error_sender: crossbeam_channel::unbounded().0,
+        },
+    );
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    // Protocol 2 is opened after the handle of protocol 1 is connected
+    assert_eq!(event_receiver_2.recv().unwrap(), (1.into(), true));
+    assert_eq!(event_receiver_2.recv().unwrap(), (2.into(), true));
+    assert!(receiver_2.recv().unwrap());
+
+    // Closing protocol 1 closes protocol 2 too
+    let mut closed = vec![
+        event_receiver_2.recv().unwrap(),
+        event_receiver_2.recv().unwrap(),
+    ];
+    closed.sort();
+    assert_eq!(closed, vec![(1.into(), false), (2.into(), false)]);
+}
+
+/// The remote doesn't support protocol 1, protocol 2 will never be opened
+fn test_dependency_unsupported(secio: bool) {
+    let (sender_1, _receiver_1) = crossbeam_channel::unbounded();
+    let (sender_2, receiver_2) = crossbeam_channel::unbounded();
+    let (event_sender, event_receiver) = crossbeam_channel::unbounded();
+    let (error_sender, error_receiver) = crossbeam_channel::unbounded();
+
+    let mut service_1 = create(secio, create_metas(false, sender_1).split_off(1), ());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let mut service_2 = create(
+        secio,
+        create_metas(false, sender_2),
+        SHandle {
+            sender: event_sender,
+            error_sender,
+        },
+    );
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    assert_eq!(error_receiver.recv().unwrap(), Some("/p2p/1".to_owned()));
+    assert_eq!(error_receiver.recv().unwrap(), Some("/p2p/2".to_owned()));
+    assert!(event_receiver.recv_timeout(Duration::from_secs(1)).is_err());
+    assert!(receiver_2.try_recv().is_err());
+}
+
+#[test]
+fn test_dependency_with_secio() {
+    test_dependency(true)
+}
+
+#[test]
+fn test_dependency_with_no_secio() {
+    test_dependency(false)
+}
+
+#[test]
+fn test_dependency_unsupported_with_secio() {
+    test_dependency_unsupported(true)
+}
+
+#[test]
+fn test_dependency_unsupported_with_no_secio() {
+    test_dependency_unsupported(false)
+}
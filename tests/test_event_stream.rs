@@ -0,0 +1,105 @@
+use futures::prelude::Stream;
+use std::{sync::mpsc::channel, thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    secio::SecioKeyPair,
+    service::{
+        DialProtocol, EventHandle, EventStream, ProtocolEvent, ProtocolHandle, ProtocolMeta,
+        Service, ServiceEvent, ServiceOutput,
+    },
+};
+
+pub fn create(secio: bool, meta: ProtocolMeta) -> (Service<EventHandle>, EventStream) {
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build_with_stream(4)
+    } else {
+        builder.build_with_stream(4)
+    }
+}
+
+fn create_meta() -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(1.into())
+        .service_handle(|| ProtocolHandle::Event)
+        .build()
+}
+
+fn test_event_stream(secio: bool) {
+    let (mut service_1, stream_1) = create(secio, create_meta());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let (mut service_2, _stream_2) = create(secio, create_meta());
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    let mut session_open = false;
+    for output in stream_1.wait() {
+        match output.unwrap() {
+            ServiceOutput::Event(ServiceEvent::SessionOpen { .. }) => session_open = true,
+            ServiceOutput::Protocol(ProtocolEvent::Connected { proto_id, .. }) => {
+                assert!(session_open);
+                assert_eq!(proto_id, 1.into());
+                break;
+            }
+            _ => (),
+        }
+    }
+}
+
+#[test]
+fn test_event_stream_with_secio() {
+    test_event_stream(true)
+}
+
+#[test]
+fn test_event_stream_with_no_secio() {
+    test_event_stream(false)
+}
+
+#[test]
+fn test_shutdown_without_consuming_stream() {
+    let (mut service_1, _stream_1) = ServiceBuilder::default()
+        .insert_protocol(create_meta())
+        .forever(true)
+        .build_with_stream(1);
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    let control_1 = service_1.control().clone();
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        tokio::run(service_1.for_each(|_| Ok(())));
+        let _ = sender.send(());
+    });
+
+    // The outputs of these sessions fill the stream of service 1, which is never consumed
+    let (mut service_2, stream_2) = create(false, create_meta());
+    for _ in 0..3 {
+        service_2
+            .dial(listen_addr.clone(), DialProtocol::All)
+            .unwrap();
+    }
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+    let mut connected = 0;
+    for output in stream_2.wait() {
+        if let ServiceOutput::Protocol(ProtocolEvent::Connected { .. }) = output.unwrap() {
+            connected += 1;
+            if connected == 3 {
+                break;
+            }
+        }
+    }
+
+    // Service tasks are still processed, the service shuts down
+    control_1.shutdown().unwrap();
+    assert!(receiver.recv_timeout(Duration::from_secs(10)).is_ok());
+}
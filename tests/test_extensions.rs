@@ -0,0 +1,119 @@
+use futures::prelude::Stream;
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef},
+    secio::SecioKeyPair,
+    service::{DialProtocol, ProtocolHandle, ProtocolMeta, Service},
+    traits::{ServiceHandle, ServiceProtocol},
+};
+
+pub fn create<F>(secio: bool, metas: Vec<ProtocolMeta>, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    let builder = metas
+        .into_iter()
+        .fold(ServiceBuilder::default(), |builder, meta| {
+            builder.insert_protocol(meta)
+        })
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct AgentVersion(String);
+
+/// Attach the agent version to session
+struct IdentifyHandle {
+    /// Signal sync after the agent version is attached
+    signal: crossbeam_channel::Sender<()>,
+}
+
+impl ServiceProtocol for IdentifyHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        context
+            .session
+            .extensions()
+            .insert(AgentVersion("tentacle".to_owned()));
+        let _ = self.signal.send(());
+    }
+}
+
+/// Read the agent version attached by identify
+struct SyncHandle {
+    signal: crossbeam_channel::Receiver<()>,
+    sender: crossbeam_channel::Sender<Option<AgentVersion>>,
+}
+
+impl ServiceProtocol for SyncHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        // Wait for identify, the handles of different protocols run independently
+        let _ = self.signal.recv_timeout(Duration::from_secs(10));
+        let _ = self
+            .sender
+            .send(context.session.extensions().get::<AgentVersion>());
+    }
+}
+
+fn create_metas(sender: crossbeam_channel::Sender<Option<AgentVersion>>) -> Vec<ProtocolMeta> {
+    let (signal_sender, signal) = crossbeam_channel::unbounded();
+    vec![
+        MetaBuilder::new()
+            .id(1.into())
+            .service_handle(move || {
+                ProtocolHandle::Callback(Box::new(IdentifyHandle {
+                    signal: signal_sender,
+                }))
+            })
+            .build(),
+        MetaBuilder::new()
+            .id(2.into())
+            .depends_on(1.into())
+            .service_handle(move || {
+                ProtocolHandle::Callback(Box::new(SyncHandle { signal, sender }))
+            })
+            .build(),
+    ]
+}
+
+fn test_extensions(secio: bool) {
+    let (sender_1, _receiver_1) = crossbeam_channel::unbounded();
+    let (sender_2, receiver_2) = crossbeam_channel::unbounded();
+
+    let mut service_1 = create(secio, create_metas(sender_1), ());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let mut service_2 = create(secio, create_metas(sender_2), ());
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    assert_eq!(
+        receiver_2.recv().unwrap(),
+        Some(AgentVersion("tentacle".to_owned()))
+    );
+}
+
+#[test]
+fn test_extensions_with_secio() {
+    test_extensions(true)
+}
+
+#[test]
+fn test_extensions_with_no_secio() {
+    test_extensions(false)
+}
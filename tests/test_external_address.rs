@@ -0,0 +1,94 @@
+use futures::prelude::Stream;
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ServiceContext,
+    multiaddr::Multiaddr,
+    secio::SecioKeyPair,
+    service::{DialProtocol, ProtocolMeta, Service, ServiceEvent},
+    traits::ServiceHandle,
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .external_address_threshold(1)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct SHandle {
+    observed: Multiaddr,
+    sender: crossbeam_channel::Sender<(Vec<Multiaddr>, Vec<Multiaddr>)>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_event(&mut self, context: &mut ServiceContext, event: ServiceEvent) {
+        match event {
+            ServiceEvent::SessionOpen { session_context } => {
+                // Pretend the remote observed this address
+                context.add_observed_addr(session_context.id, self.observed.clone());
+            }
+            ServiceEvent::ExternalAddressChanged { addresses } => {
+                let _ = self.sender.send((addresses, context.listens().to_vec()));
+            }
+            _ => (),
+        }
+    }
+}
+
+fn test_external_address(secio: bool) {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let observed: Multiaddr = "/ip4/1.2.3.4/tcp/1337".parse().unwrap();
+
+    let mut service_1 = create(
+        secio,
+        MetaBuilder::new().build(),
+        SHandle {
+            observed: observed.clone(),
+            sender,
+        },
+    );
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let mut service_2 = create(secio, MetaBuilder::new().build(), ());
+    service_2
+        .dial(listen_addr.clone(), DialProtocol::All)
+        .unwrap();
+    let control_2 = service_2.control().clone();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    let (addresses, listens) = receiver.recv().unwrap();
+    assert_eq!(addresses, vec![observed.clone()]);
+    assert!(listens.contains(&listen_addr));
+    assert!(listens.contains(&observed));
+
+    // The reporter is gone, the address is no longer confirmed
+    control_2.shutdown().unwrap();
+    let (addresses, listens) = receiver.recv().unwrap();
+    assert!(addresses.is_empty());
+    assert!(!listens.contains(&observed));
+}
+
+#[test]
+fn test_external_address_with_secio() {
+    test_external_address(true)
+}
+
+#[test]
+fn test_external_address_with_no_secio() {
+    test_external_address(false)
+}
@@ -0,0 +1,200 @@
+use bytes::Bytes;
+use futures::prelude::Stream;
+use std::{collections::HashSet, thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef},
+    secio::SecioKeyPair,
+    service::{DialProtocol, ProtocolHandle, ProtocolMeta, Service},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(secio: bool, listener: bool, metas: Vec<ProtocolMeta>, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    let mut builder = metas
+        .into_iter()
+        .fold(ServiceBuilder::default(), |builder, meta| {
+            builder.insert_protocol(meta)
+        })
+        .forever(true);
+
+    if listener {
+        // Listener opens all protocols, in the order of 3, 2, 1
+        builder = builder
+            .inbound_protocols(DialProtocol::All)
+            .open_before(3.into(), 2.into())
+            .open_before(2.into(), 1.into());
+    }
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct PHandle {
+    sender: crossbeam_channel::Sender<ProtocolId>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        let _ = self.sender.send(context.proto_id);
+    }
+}
+
+fn create_metas(sender: crossbeam_channel::Sender<ProtocolId>) -> Vec<ProtocolMeta> {
+    (1..=3usize)
+        .map(|id| {
+            let sender = sender.clone();
+            MetaBuilder::new()
+                .id(id.into())
+                .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle { sender })))
+                .build()
+        })
+        .collect()
+}
+
+/// Send a message as soon as the protocol is open
+struct SendHandle {
+    sender: crossbeam_channel::Sender<(ProtocolId, Bytes)>,
+}
+
+impl ServiceProtocol for SendHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        let data = format!("{:?} {}", context.session.ty, context.proto_id);
+        context.send_message(Bytes::from(data));
+    }
+
+    fn received(&mut self, context: ProtocolContextMutRef, data: Bytes) {
+        let _ = self.sender.send((context.proto_id, data));
+    }
+}
+
+fn create_send_metas(sender: crossbeam_channel::Sender<(ProtocolId, Bytes)>) -> Vec<ProtocolMeta> {
+    (1..=3usize)
+        .map(|id| {
+            let sender = sender.clone();
+            MetaBuilder::new()
+                .id(id.into())
+                .service_handle(move || ProtocolHandle::Callback(Box::new(SendHandle { sender })))
+                .build()
+        })
+        .collect()
+}
+
+fn test_inbound_protocols(secio: bool) {
+    let (sender_1, _receiver_1) = crossbeam_channel::unbounded();
+    let (sender_2, receiver_2) = crossbeam_channel::unbounded();
+
+    let mut service_1 = create(secio, true, create_metas(sender_1), ());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    // Dialer opens nothing, all protocols are opened by listener
+    let mut service_2 = create(secio, false, create_metas(sender_2), ());
+    service_2
+        .dial(listen_addr, DialProtocol::Multi(Vec::new()))
+        .unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    assert_eq!(receiver_2.recv().unwrap(), 3.into());
+    assert_eq!(receiver_2.recv().unwrap(), 2.into());
+    assert_eq!(receiver_2.recv().unwrap(), 1.into());
+}
+
+/// Both sides open all protocols at the same time, each protocol must be opened once
+fn test_simultaneous_open(secio: bool) {
+    let (sender_1, receiver_1) = crossbeam_channel::unbounded();
+    let (sender_2, receiver_2) = crossbeam_channel::unbounded();
+
+    let mut service_1 = create(secio, true, create_metas(sender_1), ());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let mut service_2 = create(secio, false, create_metas(sender_2), ());
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    for receiver in &[receiver_1, receiver_2] {
+        let opened = (0..3)
+            .map(|_| receiver.recv().unwrap())
+            .collect::<HashSet<ProtocolId>>();
+        assert_eq!(opened.len(), 3);
+        assert!(receiver.recv_timeout(Duration::from_secs(1)).is_err());
+    }
+}
+
+/// Both sides open all protocols at the same time and send a message at once,
+/// no message is lost
+fn test_simultaneous_send(secio: bool) {
+    let (sender_1, receiver_1) = crossbeam_channel::unbounded();
+    let (sender_2, receiver_2) = crossbeam_channel::unbounded();
+
+    let mut service_1 = create(secio, true, create_send_metas(sender_1), ());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let mut service_2 = create(secio, false, create_send_metas(sender_2), ());
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    for (receiver, remote_ty) in &[(receiver_1, "Outbound"), (receiver_2, "Inbound")] {
+        let received = (0..3)
+            .map(|_| receiver.recv_timeout(Duration::from_secs(10)).unwrap())
+            .collect::<HashSet<(ProtocolId, Bytes)>>();
+        let expected = (1..=3usize)
+            .map(|id| {
+                let proto_id = ProtocolId::new(id);
+                (proto_id, Bytes::from(format!("{} {}", remote_ty, proto_id)))
+            })
+            .collect::<HashSet<(ProtocolId, Bytes)>>();
+        assert_eq!(received, expected);
+        assert!(receiver.recv_timeout(Duration::from_secs(1)).is_err());
+    }
+}
+
+#[test]
+fn test_inbound_protocols_with_secio() {
+    test_inbound_protocols(true)
+}
+
+#[test]
+fn test_inbound_protocols_with_no_secio() {
+    test_inbound_protocols(false)
+}
+
+#[test]
+fn test_simultaneous_open_with_secio() {
+    test_simultaneous_open(true)
+}
+
+#[test]
+fn test_simultaneous_open_with_no_secio() {
+    test_simultaneous_open(false)
+}
+
+#[test]
+fn test_simultaneous_send_with_secio() {
+    test_simultaneous_send(true)
+}
+
+#[test]
+fn test_simultaneous_send_with_no_secio() {
+    test_simultaneous_send(false)
+}
@@ -0,0 +1,119 @@
+use bytes::Bytes;
+use futures::prelude::Stream;
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef, ServiceContext},
+    error::Error,
+    secio::SecioKeyPair,
+    service::{DialProtocol, ProtocolHandle, ProtocolMeta, Service, ServiceError, SessionType},
+    traits::{ServiceHandle, ServiceProtocol},
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<Error>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _context: &mut ServiceContext, error: ServiceError) {
+        if let ServiceError::ProtocolError { error, .. } = error {
+            let _ = self.sender.send(error);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Received(Bytes),
+    Disconnected,
+}
+
+struct PHandle {
+    sender: crossbeam_channel::Sender<Event>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        if context.session.ty == SessionType::Outbound {
+            context.send_message(Bytes::from("hi"));
+            context.send_message(Bytes::from("hello world"));
+        }
+    }
+
+    fn disconnected(&mut self, _context: ProtocolContextMutRef) {
+        let _ = self.sender.send(Event::Disconnected);
+    }
+
+    fn received(&mut self, _context: ProtocolContextMutRef, data: Bytes) {
+        let _ = self.sender.send(Event::Received(data));
+    }
+}
+
+fn create_meta(sender: crossbeam_channel::Sender<Event>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(1.into())
+        .max_message_size(4)
+        .close_on_violation(true)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle { sender })))
+        .build()
+}
+
+fn test_message_limit(secio: bool) {
+    let (error_sender, error_receiver) = crossbeam_channel::unbounded();
+    let (sender_1, receiver_1) = crossbeam_channel::unbounded();
+    let (sender_2, receiver_2) = crossbeam_channel::unbounded();
+
+    let mut service_1 = create(
+        secio,
+        create_meta(sender_1),
+        SHandle {
+            sender: error_sender,
+        },
+    );
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let mut service_2 = create(secio, create_meta(sender_2), ());
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    assert_eq!(error_receiver.recv().unwrap(), Error::MessageTooLarge(11));
+    // The small message reaches the handle, the large one doesn't
+    assert_eq!(
+        receiver_1.recv().unwrap(),
+        Event::Received(Bytes::from("hi"))
+    );
+    assert_eq!(receiver_1.recv().unwrap(), Event::Disconnected);
+    assert_eq!(receiver_2.recv().unwrap(), Event::Disconnected);
+}
+
+#[test]
+fn test_message_limit_with_secio() {
+    test_message_limit(true)
+}
+
+#[test]
+fn test_message_limit_with_no_secio() {
+    test_message_limit(false)
+}
@@ -0,0 +1,69 @@
+#![cfg(feature = "metrics")]
+
+use futures::prelude::Stream;
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef},
+    secio::SecioKeyPair,
+    service::{DialProtocol, ProtocolHandle, ProtocolMeta, Service},
+    traits::{ServiceHandle, ServiceProtocol},
+};
+
+pub fn create<F>(meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    ServiceBuilder::default()
+        .insert_protocol(meta)
+        .key_pair(SecioKeyPair::secp256k1_generated())
+        .forever(true)
+        .build(shandle)
+}
+
+struct PHandle {
+    sender: crossbeam_channel::Sender<()>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, _context: ProtocolContextMutRef, _version: &str) {
+        let _ = self.sender.send(());
+    }
+}
+
+fn create_meta(sender: crossbeam_channel::Sender<()>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(1.into())
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle { sender })))
+        .build()
+}
+
+#[test]
+fn test_metrics() {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    let mut service_1 = create(create_meta(sender.clone()), ());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    let control_1 = service_1.control().clone();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let mut service_2 = create(create_meta(sender), ());
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    let control_2 = service_2.control().clone();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    receiver.recv().unwrap();
+    receiver.recv().unwrap();
+
+    let metrics_1 = control_1.metrics();
+    assert!(metrics_1.contains("tentacle_sessions{direction=\"inbound\"} 1\n"));
+    assert!(metrics_1.contains("tentacle_handshakes_total{result=\"success\"} 1\n"));
+
+    let metrics_2 = control_2.metrics();
+    assert!(metrics_2.contains("tentacle_sessions{direction=\"outbound\"} 1\n"));
+    assert!(metrics_2.contains("# TYPE tentacle_dial_errors_total counter\n"));
+}
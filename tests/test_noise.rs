@@ -0,0 +1,113 @@
+use futures::prelude::Stream;
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ServiceContext,
+    secio::{stream_cipher::Cipher, KeyAgreement, Negotiated, PublicKey, SecioKeyPair},
+    service::{DialProtocol, SecurityProtocol, Service, ServiceError, ServiceEvent},
+    traits::ServiceHandle,
+};
+
+pub fn create<F>(
+    key_pair: SecioKeyPair,
+    protocol: SecurityProtocol,
+    accept_noise: bool,
+    shandle: F,
+) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    ServiceBuilder::default()
+        .insert_protocol(MetaBuilder::new().build())
+        .key_pair(key_pair)
+        .security_protocol(protocol)
+        .accept_inbound_noise(accept_noise)
+        .forever(true)
+        .build(shandle)
+}
+
+/// The negotiated algorithms and the remote public key, none if the handshake fails
+type Outcome = Option<(Option<Negotiated>, Option<PublicKey>)>;
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<Outcome>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _context: &mut ServiceContext, error: ServiceError) {
+        if let ServiceError::DialerError { .. } = error {
+            let _ = self.sender.send(None);
+        }
+    }
+
+    fn handle_event(&mut self, _context: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::SessionOpen { session_context } = event {
+            let _ = self.sender.send(Some((
+                session_context.negotiated,
+                session_context.remote_pubkey.clone(),
+            )));
+        }
+    }
+}
+
+fn handshake(
+    listener: SecurityProtocol,
+    accept_noise: bool,
+    dialer: SecurityProtocol,
+) -> (SecioKeyPair, Outcome) {
+    let listener_key = SecioKeyPair::secp256k1_generated();
+    let mut service_1 = create(listener_key.clone(), listener, accept_noise, ());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let mut service_2 = create(
+        SecioKeyPair::ed25519_generated(),
+        dialer,
+        false,
+        SHandle { sender },
+    );
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    (listener_key, receiver.recv().unwrap())
+}
+
+fn test_noise(listener: SecurityProtocol, accept_noise: bool, dialer: SecurityProtocol) {
+    let (listener_key, result) = handshake(listener, accept_noise, dialer);
+    let (negotiated, remote_pubkey) = result.expect("handshake fail");
+    assert_eq!(remote_pubkey, Some(listener_key.to_public_key()));
+
+    let negotiated = negotiated.unwrap();
+    match dialer {
+        SecurityProtocol::Noise => {
+            assert_eq!(negotiated.agreement, KeyAgreement::X25519);
+            assert_eq!(negotiated.cipher, Cipher::ChaCha20Poly1305);
+        }
+        // The default proposition of secio prefers AES-128-GCM
+        SecurityProtocol::Secio => assert_eq!(negotiated.cipher, Cipher::Aes128Gcm),
+    }
+}
+
+#[test]
+fn test_noise_dial_noise() {
+    test_noise(SecurityProtocol::Noise, false, SecurityProtocol::Noise)
+}
+
+#[test]
+fn test_noise_dial_secio() {
+    test_noise(SecurityProtocol::Secio, true, SecurityProtocol::Noise)
+}
+
+#[test]
+fn test_secio_dial_noise() {
+    test_noise(SecurityProtocol::Noise, false, SecurityProtocol::Secio)
+}
+
+#[test]
+fn test_inbound_noise_refused_by_default() {
+    let (_, result) = handshake(SecurityProtocol::Secio, false, SecurityProtocol::Noise);
+    assert!(result.is_none());
+}
@@ -0,0 +1,98 @@
+use futures::prelude::Stream;
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ProtocolContext,
+    secio::SecioKeyPair,
+    service::{MissedTickPolicy, NotifySchedule, ProtocolHandle, ProtocolMeta, Service},
+    traits::{ServiceHandle, ServiceProtocol},
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct PHandle {
+    sender: crossbeam_channel::Sender<(u64, bool)>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, context: &mut ProtocolContext) {
+        let proto_id = context.proto_id;
+        context.set_service_delay(proto_id, Duration::from_millis(100), 1);
+        context.set_service_notify_schedule(
+            proto_id,
+            NotifySchedule::interval(Duration::from_millis(100))
+                .first_after(Duration::from_millis(50))
+                .jitter(Duration::from_millis(20))
+                .missed_tick_policy(MissedTickPolicy::Skip),
+            2,
+        );
+    }
+
+    fn notify(&mut self, context: &mut ProtocolContext, token: u64) {
+        let next_fire = context
+            .service_notify_next_fire(context.proto_id, token)
+            .is_some();
+        let _ = self.sender.send((token, next_fire));
+    }
+}
+
+fn create_meta(sender: crossbeam_channel::Sender<(u64, bool)>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(1.into())
+        .service_handle(move || {
+            ProtocolHandle::Callback(Box::new(PHandle {
+                sender: sender.clone(),
+            }))
+        })
+        .build()
+}
+
+fn test_notify(secio: bool) {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    let service = create(secio, create_meta(sender), ());
+    thread::spawn(|| tokio::run(service.for_each(|_| Ok(()))));
+
+    let mut once = 0;
+    let mut repeat = 0;
+    while repeat < 5 {
+        match receiver.recv().unwrap() {
+            (1, next_fire) => {
+                // The one-shot token has no next fire
+                assert!(!next_fire);
+                once += 1;
+            }
+            (2, next_fire) => {
+                assert!(next_fire);
+                repeat += 1;
+            }
+            _ => unreachable!(),
+        }
+    }
+    assert_eq!(once, 1);
+}
+
+#[test]
+fn test_notify_with_secio() {
+    test_notify(true)
+}
+
+#[test]
+fn test_notify_with_no_secio() {
+    test_notify(false)
+}
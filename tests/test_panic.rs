@@ -0,0 +1,114 @@
+use futures::prelude::Stream;
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContextMutRef, ServiceContext},
+    secio::SecioKeyPair,
+    service::{
+        DialProtocol, HandlePanicPolicy, ProtocolHandle, ProtocolMeta, Service, ServiceError,
+    },
+    traits::{ServiceHandle, SessionProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .handle_panic_policy(HandlePanicPolicy::CloseProtocol)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<(ProtocolId, String)>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _context: &mut ServiceContext, error: ServiceError) {
+        if let ServiceError::ProtocolHandlePanic {
+            proto_id, message, ..
+        } = error
+        {
+            let _ = self.sender.send((proto_id, message));
+        }
+    }
+}
+
+struct PHandle {
+    panic: bool,
+    sender: crossbeam_channel::Sender<bool>,
+}
+
+impl SessionProtocol for PHandle {
+    fn connected(&mut self, _context: ProtocolContextMutRef, _version: &str) {
+        if self.panic {
+            panic!("boom");
+        }
+        let _ = self.sender.send(true);
+    }
+
+    fn disconnected(&mut self, _context: ProtocolContextMutRef) {
+        let _ = self.sender.send(false);
+    }
+}
+
+fn create_meta(panic: bool, sender: crossbeam_channel::Sender<bool>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(1.into())
+        .session_handle(move || {
+            ProtocolHandle::Callback(Box::new(PHandle {
+                panic,
+                sender: sender.clone(),
+            }))
+        })
+        .build()
+}
+
+fn test_panic(secio: bool) {
+    let (error_sender, error_receiver) = crossbeam_channel::unbounded();
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    let mut service_1 = create(
+        secio,
+        create_meta(true, sender.clone()),
+        SHandle {
+            sender: error_sender,
+        },
+    );
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let mut service_2 = create(secio, create_meta(false, sender), ());
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    // The panic is isolated, the service keeps running and only the protocol is closed
+    assert_eq!(
+        error_receiver.recv().unwrap(),
+        (1.into(), "boom".to_owned())
+    );
+    assert!(receiver.recv().unwrap());
+    assert!(!receiver.recv().unwrap());
+}
+
+#[test]
+fn test_panic_with_secio() {
+    test_panic(true)
+}
+
+#[test]
+fn test_panic_with_no_secio() {
+    test_panic(false)
+}
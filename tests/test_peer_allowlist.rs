@@ -0,0 +1,147 @@
+use futures::prelude::Stream;
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ServiceContext,
+    secio::{handshake::Config, PeerAllowlist, PeerId, SecioKeyPair},
+    service::{DialProtocol, Service, ServiceControl, ServiceError, ServiceEvent, SessionType},
+    traits::ServiceHandle,
+};
+
+pub fn create<F>(key_pair: SecioKeyPair, allowlist: Option<Vec<PeerId>>, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(MetaBuilder::new().build())
+        .key_pair(key_pair)
+        .forever(true);
+
+    match allowlist {
+        Some(peers) => builder.peer_allowlist(peers).build(shandle),
+        None => builder.build(shandle),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Open,
+    NotAllowed(PeerId, SessionType),
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<Outcome>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _context: &mut ServiceContext, error: ServiceError) {
+        if let ServiceError::PeerNotAllowed { peer_id, ty, .. } = error {
+            let _ = self.sender.send(Outcome::NotAllowed(peer_id, ty));
+        }
+    }
+
+    fn handle_event(&mut self, _context: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::SessionOpen { .. } = event {
+            let _ = self.sender.send(Outcome::Open);
+        }
+    }
+}
+
+#[test]
+fn test_peer_allowlist() {
+    let listener_key = SecioKeyPair::secp256k1_generated();
+    let allowed_key = SecioKeyPair::secp256k1_generated();
+    let other_key = SecioKeyPair::secp256k1_generated();
+    let other_id = other_key.to_peer_id();
+
+    let (listener_sender, listener_receiver) = crossbeam_channel::unbounded();
+    let mut service_1 = create(
+        listener_key,
+        Some(vec![allowed_key.to_peer_id()]),
+        SHandle {
+            sender: listener_sender,
+        },
+    );
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    let control: ServiceControl = service_1.control().clone();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    // Not in the allowlist
+    let mut service_2 = create(other_key.clone(), None, ());
+    service_2
+        .dial(listen_addr.clone(), DialProtocol::All)
+        .unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+    assert_eq!(
+        listener_receiver.recv().unwrap(),
+        Outcome::NotAllowed(other_id.clone(), SessionType::Inbound)
+    );
+
+    // In the allowlist
+    let mut service_3 = create(allowed_key, None, ());
+    service_3
+        .dial(listen_addr.clone(), DialProtocol::All)
+        .unwrap();
+    thread::spawn(|| tokio::run(service_3.for_each(|_| Ok(()))));
+    assert_eq!(listener_receiver.recv().unwrap(), Outcome::Open);
+
+    // Reload at runtime
+    control.set_peer_allowlist(vec![other_id.clone()]);
+    assert_eq!(
+        control.peer_allowlist(),
+        Some(vec![other_id].into_iter().collect())
+    );
+    let mut service_4 = create(other_key, None, ());
+    service_4.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_4.for_each(|_| Ok(()))));
+    assert_eq!(listener_receiver.recv().unwrap(), Outcome::Open);
+}
+
+#[test]
+fn test_peer_allowlist_of_secio_config() {
+    let config_peer = SecioKeyPair::secp256k1_generated().to_peer_id();
+    let builder_peer = SecioKeyPair::secp256k1_generated().to_peer_id();
+    let other_key = SecioKeyPair::secp256k1_generated();
+
+    let config_allowlist = PeerAllowlist::new(vec![config_peer.clone()]);
+    let config =
+        Config::new(SecioKeyPair::secp256k1_generated()).peer_allowlist(config_allowlist.clone());
+    let (listener_sender, listener_receiver) = crossbeam_channel::unbounded();
+    let mut service_1 = ServiceBuilder::default()
+        .insert_protocol(MetaBuilder::new().build())
+        .peer_allowlist(vec![builder_peer.clone()])
+        .secio_config(config)
+        .forever(true)
+        .build(SHandle {
+            sender: listener_sender,
+        });
+    assert_eq!(
+        service_1.control().peer_allowlist(),
+        Some(
+            vec![config_peer.clone(), builder_peer]
+                .into_iter()
+                .collect()
+        )
+    );
+    // The list of the config is left untouched
+    assert_eq!(
+        config_allowlist.peers(),
+        Some(vec![config_peer].into_iter().collect())
+    );
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    // In neither list
+    let other_id = other_key.to_peer_id();
+    let mut service_2 = create(other_key, None, ());
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+    assert_eq!(
+        listener_receiver.recv().unwrap(),
+        Outcome::NotAllowed(other_id, SessionType::Inbound)
+    );
+}
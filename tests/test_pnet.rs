@@ -0,0 +1,105 @@
+use futures::prelude::Stream;
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ServiceContext,
+    error::Error,
+    secio::SecioKeyPair,
+    service::{DialProtocol, PreSharedKey, Service, ServiceError, ServiceEvent},
+    traits::ServiceHandle,
+};
+
+pub fn create<F>(secio: bool, psk: Option<PreSharedKey>, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    let mut builder = ServiceBuilder::default()
+        .insert_protocol(MetaBuilder::new().build())
+        .forever(true);
+    if secio {
+        builder = builder.key_pair(SecioKeyPair::secp256k1_generated());
+    }
+
+    match psk {
+        Some(psk) => builder.pre_shared_key(psk).build(shandle),
+        None => builder.build(shandle),
+    }
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<Result<(), Error>>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _context: &mut ServiceContext, error: ServiceError) {
+        if let ServiceError::DialerError { error, .. } = error {
+            let _ = self.sender.send(Err(error));
+        }
+    }
+
+    fn handle_event(&mut self, _context: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::SessionOpen { .. } = event {
+            let _ = self.sender.send(Ok(()));
+        }
+    }
+}
+
+fn test_pnet(
+    secio: bool,
+    listener: Option<PreSharedKey>,
+    dialer: Option<PreSharedKey>,
+) -> Result<(), Error> {
+    let mut service_1 = create(secio, listener, ());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let mut service_2 = create(secio, dialer, SHandle { sender });
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    receiver.recv().unwrap()
+}
+
+#[test]
+fn test_pnet_same_key() {
+    let psk = PreSharedKey::new([1; 32]);
+    assert_eq!(test_pnet(true, Some(psk.clone()), Some(psk)), Ok(()));
+}
+
+#[test]
+fn test_pnet_different_key() {
+    assert_eq!(
+        test_pnet(
+            true,
+            Some(PreSharedKey::new([1; 32])),
+            Some(PreSharedKey::new([2; 32]))
+        ),
+        Err(Error::PreSharedKeyMismatch)
+    );
+}
+
+#[test]
+fn test_pnet_public_node() {
+    assert!(test_pnet(true, Some(PreSharedKey::new([1; 32])), None).is_err());
+}
+
+#[test]
+fn test_pnet_same_key_with_no_secio() {
+    let psk = PreSharedKey::new([1; 32]);
+    assert_eq!(test_pnet(false, Some(psk.clone()), Some(psk)), Ok(()));
+}
+
+#[test]
+fn test_pnet_different_key_with_no_secio() {
+    assert_eq!(
+        test_pnet(
+            false,
+            Some(PreSharedKey::new([1; 32])),
+            Some(PreSharedKey::new([2; 32]))
+        ),
+        Err(Error::PreSharedKeyMismatch)
+    );
+}
@@ -0,0 +1,102 @@
+use futures::prelude::Stream;
+use std::{thread, time::Duration};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef},
+    secio::SecioKeyPair,
+    service::{DialProtocol, ProtocolHandle, ProtocolMeta, Service},
+    traits::{ServiceHandle, ServiceProtocol},
+    ProtocolId,
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct PHandle {
+    sender: crossbeam_channel::Sender<(ProtocolId, bool)>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        let _ = self.sender.send((context.proto_id, true));
+    }
+
+    fn disconnected(&mut self, context: ProtocolContextMutRef) {
+        let _ = self.sender.send((context.proto_id, false));
+    }
+}
+
+fn create_meta(
+    id: ProtocolId,
+    sender: crossbeam_channel::Sender<(ProtocolId, bool)>,
+) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(id)
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle { sender })))
+        .build()
+}
+
+fn test_register(secio: bool) {
+    let (sender_1, receiver_1) = crossbeam_channel::unbounded();
+    let (sender_2, receiver_2) = crossbeam_channel::unbounded();
+
+    let mut service_1 = create(secio, create_meta(0.into(), sender_1.clone()), ());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    let control_1 = service_1.control().clone();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let mut service_2 = create(secio, create_meta(0.into(), sender_2.clone()), ());
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    let control_2 = service_2.control().clone();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    assert_eq!(receiver_1.recv().unwrap(), (0.into(), true));
+    assert_eq!(receiver_2.recv().unwrap(), (0.into(), true));
+
+    control_1
+        .register_protocol(create_meta(1.into(), sender_1), false)
+        .unwrap();
+    thread::sleep(Duration::from_millis(500));
+    control_2
+        .register_protocol(create_meta(1.into(), sender_2), true)
+        .unwrap();
+
+    assert_eq!(receiver_1.recv().unwrap(), (1.into(), true));
+    assert_eq!(receiver_2.recv().unwrap(), (1.into(), true));
+    assert!(control_2.protocols().contains_key(&1.into()));
+
+    control_2.unregister_protocol(1.into()).unwrap();
+
+    assert_eq!(receiver_1.recv().unwrap(), (1.into(), false));
+    // The handle of the unregistered protocol still gets the close event
+    assert_eq!(receiver_2.recv().unwrap(), (1.into(), false));
+    assert!(!control_2.protocols().contains_key(&1.into()));
+}
+
+#[test]
+fn test_register_with_secio() {
+    test_register(true)
+}
+
+#[test]
+fn test_register_with_no_secio() {
+    test_register(false)
+}
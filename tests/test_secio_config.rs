@@ -0,0 +1,107 @@
+use futures::prelude::Stream;
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::ServiceContext,
+    error::Error,
+    secio::{
+        error::SecioError, handshake::Config, stream_cipher::Cipher, Digest, Negotiated,
+        SecioKeyPair,
+    },
+    service::{DialProtocol, Service, ServiceError, ServiceEvent},
+    traits::ServiceHandle,
+};
+
+pub fn create<F>(config: Config, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    ServiceBuilder::default()
+        .insert_protocol(MetaBuilder::new().build())
+        .secio_config(config)
+        .forever(true)
+        .build(shandle)
+}
+
+enum Outcome {
+    Open(Option<Negotiated>),
+    HandshakeFail(Error),
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<Outcome>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_error(&mut self, _context: &mut ServiceContext, error: ServiceError) {
+        if let ServiceError::DialerError { error, .. } = error {
+            let _ = self.sender.send(Outcome::HandshakeFail(error));
+        }
+    }
+
+    fn handle_event(&mut self, _context: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::SessionOpen { session_context } = event {
+            let _ = self.sender.send(Outcome::Open(session_context.negotiated));
+        }
+    }
+}
+
+fn compliance_config() -> Config {
+    Config::new(SecioKeyPair::secp256k1_generated())
+        .ciphers(&[Cipher::Aes256Gcm, Cipher::Aes256])
+        .digests(&[Digest::Sha256])
+}
+
+#[test]
+fn test_secio_config_restricts_algorithms() {
+    let mut service_1 = create(compliance_config(), ());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    // Default config supports all algorithms
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let mut service_2 = create(
+        Config::new(SecioKeyPair::secp256k1_generated()),
+        SHandle { sender },
+    );
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    match receiver.recv().unwrap() {
+        Outcome::Open(negotiated) => {
+            let negotiated = negotiated.unwrap();
+            assert_eq!(negotiated.cipher, Cipher::Aes256Gcm);
+            assert_eq!(negotiated.digest, Digest::Sha256);
+        }
+        Outcome::HandshakeFail(error) => panic!("handshake fail: {:?}", error),
+    }
+}
+
+#[test]
+fn test_secio_config_no_common_cipher() {
+    let mut service_1 = create(compliance_config(), ());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let mut service_2 = create(
+        Config::new(SecioKeyPair::secp256k1_generated()).ciphers(&[Cipher::TwofishCtr]),
+        SHandle { sender },
+    );
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    match receiver.recv().unwrap() {
+        Outcome::HandshakeFail(error) => {
+            assert_eq!(
+                error,
+                Error::HandshakeError(SecioError::NoSupportIntersection)
+            );
+        }
+        Outcome::Open(_) => panic!("handshake should fail"),
+    }
+}
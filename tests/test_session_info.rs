@@ -0,0 +1,72 @@
+use futures::prelude::Stream;
+use std::{sync::Arc, thread, time::SystemTime};
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ServiceContext, SessionContext, TransportKind},
+    secio::SecioKeyPair,
+    service::{DialProtocol, ProtocolMeta, Service, ServiceEvent},
+    traits::ServiceHandle,
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct SHandle {
+    sender: crossbeam_channel::Sender<Arc<SessionContext>>,
+}
+
+impl ServiceHandle for SHandle {
+    fn handle_event(&mut self, _context: &mut ServiceContext, event: ServiceEvent) {
+        if let ServiceEvent::SessionOpen { session_context } = event {
+            let _ = self.sender.send(session_context);
+        }
+    }
+}
+
+fn test_session_info(secio: bool) {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let start = SystemTime::now();
+
+    let mut service_1 = create(secio, MetaBuilder::new().build(), SHandle { sender });
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let mut service_2 = create(secio, MetaBuilder::new().build(), ());
+    service_2
+        .dial(listen_addr.clone(), DialProtocol::All)
+        .unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    let session = receiver.recv().unwrap();
+    assert_eq!(session.transport, TransportKind::Tcp);
+    // The listener accepts on the listen address
+    assert_eq!(session.local_address, Some(listen_addr));
+    assert_eq!(session.negotiated.is_some(), secio);
+    assert!(session.opened_at >= start);
+}
+
+#[test]
+fn test_session_info_with_secio() {
+    test_session_info(true)
+}
+
+#[test]
+fn test_session_info_with_no_secio() {
+    test_session_info(false)
+}
@@ -0,0 +1,95 @@
+use bytes::Bytes;
+use futures::prelude::Stream;
+use std::thread;
+use tentacle::{
+    builder::{MetaBuilder, ServiceBuilder},
+    context::{ProtocolContext, ProtocolContextMutRef},
+    secio::SecioKeyPair,
+    service::{DialProtocol, ProtocolHandle, ProtocolMeta, Service, SessionType},
+    traits::{ServiceHandle, ServiceProtocol},
+};
+
+pub fn create<F>(secio: bool, meta: ProtocolMeta, shandle: F) -> Service<F>
+where
+    F: ServiceHandle,
+{
+    let builder = ServiceBuilder::default()
+        .insert_protocol(meta)
+        .forever(true);
+
+    if secio {
+        builder
+            .key_pair(SecioKeyPair::secp256k1_generated())
+            .build(shandle)
+    } else {
+        builder.build(shandle)
+    }
+}
+
+struct PHandle {
+    count: usize,
+    sender: crossbeam_channel::Sender<()>,
+}
+
+impl ServiceProtocol for PHandle {
+    fn init(&mut self, _context: &mut ProtocolContext) {}
+
+    fn connected(&mut self, context: ProtocolContextMutRef, _version: &str) {
+        if context.session.ty == SessionType::Outbound {
+            for _ in 0..3 {
+                context.send_message(Bytes::from("hello"));
+            }
+        }
+    }
+
+    fn received(&mut self, _context: ProtocolContextMutRef, _data: Bytes) {
+        self.count += 1;
+        if self.count == 3 {
+            let _ = self.sender.send(());
+        }
+    }
+}
+
+fn create_meta(sender: crossbeam_channel::Sender<()>) -> ProtocolMeta {
+    MetaBuilder::new()
+        .id(1.into())
+        .service_handle(move || ProtocolHandle::Callback(Box::new(PHandle { count: 0, sender })))
+        .build()
+}
+
+fn test_traffic(secio: bool) {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+
+    let mut service_1 = create(secio, create_meta(sender.clone()), ());
+    let listen_addr = service_1
+        .listen("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    let control = service_1.control().clone();
+    thread::spawn(|| tokio::run(service_1.for_each(|_| Ok(()))));
+
+    let mut service_2 = create(secio, create_meta(sender), ());
+    service_2.dial(listen_addr, DialProtocol::All).unwrap();
+    thread::spawn(|| tokio::run(service_2.for_each(|_| Ok(()))));
+
+    receiver.recv().unwrap();
+
+    let traffic = control.traffic();
+    assert_eq!(traffic.len(), 1);
+    let session = traffic.values().next().unwrap();
+    assert_eq!(session.total.received_messages, 3);
+    assert_eq!(session.total.received_bytes, 15);
+    assert_eq!(session.total.sent_messages, 0);
+    assert!(session.total.last_activity.is_some());
+    assert_eq!(session.protocols[&1.into()].received_messages, 3);
+    assert_eq!(session.encrypted_received_bytes.is_some(), secio);
+}
+
+#[test]
+fn test_traffic_with_secio() {
+    test_traffic(true)
+}
+
+#[test]
+fn test_traffic_with_no_secio() {
+    test_traffic(false)
+}
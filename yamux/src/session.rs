@@ -103,6 +103,12 @@ impl<T> Session<T>
 where
     T: AsyncRead + AsyncWrite,
 {
+    /// Number of streams currently open
+    #[inline]
+    pub fn stream_count(&self) -> usize {
+        self.streams.len()
+    }
+
     /// Create a new session from a low level stream
     pub fn new(raw_stream: T, config: Config, ty: SessionType) -> Session<T> {
         let next_stream_id = match ty {